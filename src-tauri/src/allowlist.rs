@@ -0,0 +1,126 @@
+use std::net::IpAddr;
+
+// Client addresses allowed to use a local forward; an empty list allows everyone
+#[derive(Debug, Clone, Default)]
+pub struct ClientAllowlist {
+    networks: Vec<Network>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Network {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl ClientAllowlist {
+    // Entries are single addresses or CIDR ranges, e.g. "192.168.1.0/24" or "fd00::/8"
+    pub fn parse(entries: &[String]) -> Result<Self, String> {
+        let networks = entries
+            .iter()
+            .map(|entry| Network::parse(entry.trim()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { networks })
+    }
+
+    pub fn allows(&self, client: IpAddr) -> bool {
+        if self.networks.is_empty() {
+            return true;
+        }
+
+        // IPv4 clients on a dual-stack socket show up as ::ffff:a.b.c.d
+        let client = client.to_canonical();
+        self.networks.iter().any(|network| network.contains(client))
+    }
+}
+
+impl Network {
+    fn parse(entry: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid client address or CIDR range: {}", entry);
+        let (addr, prefix_len) = match entry.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (entry, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len.parse::<u8>().map_err(|_| invalid())?,
+            None => max_len,
+        };
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            addr: addr.to_canonical(),
+            prefix_len,
+        })
+    }
+
+    fn contains(&self, client: IpAddr) -> bool {
+        match (self.addr, client) {
+            (IpAddr::V4(network), IpAddr::V4(client)) => {
+                prefix_matches(&network.octets(), &client.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(client)) => {
+                prefix_matches(&network.octets(), &client.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_matches(network: &[u8], client: &[u8], prefix_len: u8) -> bool {
+    let full_bytes = (prefix_len / 8) as usize;
+    let rest_bits = prefix_len % 8;
+
+    if network[..full_bytes] != client[..full_bytes] {
+        return false;
+    }
+    if rest_bits == 0 {
+        return true;
+    }
+
+    let mask = 0xffu8 << (8 - rest_bits);
+    network[full_bytes] & mask == client[full_bytes] & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowlist(entries: &[&str]) -> ClientAllowlist {
+        let entries: Vec<String> = entries.iter().map(|entry| entry.to_string()).collect();
+        ClientAllowlist::parse(&entries).unwrap()
+    }
+
+    fn ip(addr: &str) -> IpAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_addresses_and_ranges() {
+        let list = allowlist(&["10.0.0.0/8", "192.168.1.20", "fd00::/8", "172.16.0.0/12"]);
+
+        assert!(list.allows(ip("10.200.3.4")));
+        assert!(list.allows(ip("192.168.1.20")));
+        assert!(!list.allows(ip("192.168.1.21")));
+        assert!(list.allows(ip("172.31.255.1")));
+        assert!(!list.allows(ip("172.32.0.1")));
+        assert!(list.allows(ip("fd12:3456::1")));
+        assert!(!list.allows(ip("fe80::1")));
+        assert!(list.allows(ip("::ffff:10.1.2.3")));
+        assert!(ClientAllowlist::default().allows(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        for entry in ["10.0.0.0/33", "fd00::/129", "example.com", "10.0.0.0/"] {
+            assert!(
+                ClientAllowlist::parse(&[entry.to_string()]).is_err(),
+                "{}",
+                entry
+            );
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::{sleep, Duration, Instant};
+
+const COPY_BUFFER_SIZE: usize = 16 * 1024;
+const MIN_BYTES_PER_SEC: u64 = 1024;
+
+// Rates in bytes per second, None means unlimited. Upload is traffic sent into the SSH session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthLimit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_bytes_per_sec: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_bytes_per_sec: Option<u64>,
+}
+
+impl BandwidthLimit {
+    pub fn validate(&self) -> Result<(), String> {
+        for rate in [self.upload_bytes_per_sec, self.download_bytes_per_sec]
+            .into_iter()
+            .flatten()
+        {
+            if rate < MIN_BYTES_PER_SEC {
+                return Err(format!(
+                    "Bandwidth limits must be at least {} bytes per second",
+                    MIN_BYTES_PER_SEC
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+struct BucketState {
+    rate: Option<u64>,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: Option<u64>) -> Self {
+        Self {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate.unwrap_or(0) as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    fn set_rate(&self, rate: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        if state.rate != rate {
+            state.rate = rate;
+            state.tokens = state.tokens.min(rate.unwrap_or(0) as f64);
+            state.refilled_at = Instant::now();
+        }
+    }
+
+    // Spend `bytes` tokens and return how long the caller has to wait to stay under the rate.
+    // The bucket can go into debt so chunks larger than one second of traffic still pass
+    fn take(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let Some(rate) = state.rate.map(|rate| rate as f64) else {
+            return Duration::ZERO;
+        };
+
+        let now = Instant::now();
+        let refill = now.duration_since(state.refilled_at).as_secs_f64() * rate;
+        // Bursts are capped at one second worth of traffic
+        state.tokens = (state.tokens + refill).min(rate) - bytes as f64;
+        state.refilled_at = now;
+
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+// Upload and download buckets of one tunnel or connection, shared by all its forwarded streams
+pub struct BandwidthLimiter {
+    upload: TokenBucket,
+    download: TokenBucket,
+}
+
+impl BandwidthLimiter {
+    pub fn new(limit: &BandwidthLimit) -> Self {
+        Self {
+            upload: TokenBucket::new(limit.upload_bytes_per_sec),
+            download: TokenBucket::new(limit.download_bytes_per_sec),
+        }
+    }
+
+    // Takes effect for streams that are already running
+    pub fn set_limit(&self, limit: &BandwidthLimit) {
+        self.upload.set_rate(limit.upload_bytes_per_sec);
+        self.download.set_rate(limit.download_bytes_per_sec);
+    }
+
+    fn bucket(&self, direction: Direction) -> &TokenBucket {
+        match direction {
+            Direction::Upload => &self.upload,
+            Direction::Download => &self.download,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Upload,
+    Download,
+}
+
+// Like `copy_bidirectional` between an SSH channel and a local socket, paced by `limiters`.
+// Every stream goes through the limiters, even unlimited ones, so a limit set later applies to it
+pub async fn copy_bidirectional_limited<C, S>(
+    channel: &mut C,
+    stream: &mut S,
+    limiters: &[Arc<BandwidthLimiter>],
+) -> io::Result<(u64, u64)>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut channel_reader, mut channel_writer) = tokio::io::split(channel);
+    let (mut stream_reader, mut stream_writer) = tokio::io::split(stream);
+    tokio::try_join!(
+        copy_limited(
+            &mut channel_reader,
+            &mut stream_writer,
+            limiters,
+            Direction::Download
+        ),
+        copy_limited(
+            &mut stream_reader,
+            &mut channel_writer,
+            limiters,
+            Direction::Upload
+        ),
+    )
+}
+
+async fn copy_limited<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    limiters: &[Arc<BandwidthLimiter>],
+    direction: Direction,
+) -> io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            writer.shutdown().await?;
+            return Ok(copied);
+        }
+
+        let delay = limiters
+            .iter()
+            .map(|limiter| limiter.bucket(direction).take(read))
+            .max()
+            .unwrap_or_default();
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+
+        writer.write_all(&buffer[..read]).await?;
+        copied += read as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_allows_a_burst_then_makes_callers_wait() {
+        let bucket = TokenBucket::new(Some(1000));
+
+        assert_eq!(bucket.take(1000), Duration::ZERO);
+        let delay = bucket.take(500);
+        assert!(delay > Duration::from_millis(400) && delay <= Duration::from_millis(500));
+
+        bucket.set_rate(None);
+        assert_eq!(bucket.take(1_000_000), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn limited_copy_moves_data_both_ways() {
+        let limiters = [Arc::new(BandwidthLimiter::new(&BandwidthLimit {
+            upload_bytes_per_sec: Some(1024 * 1024),
+            download_bytes_per_sec: None,
+        }))];
+        let (mut channel, mut channel_peer) = tokio::io::duplex(1024);
+        let (mut stream, mut stream_peer) = tokio::io::duplex(1024);
+
+        let peers = async {
+            channel_peer.write_all(b"from server").await.unwrap();
+            channel_peer.shutdown().await.unwrap();
+            stream_peer.write_all(b"from client").await.unwrap();
+            stream_peer.shutdown().await.unwrap();
+
+            let mut to_client = String::new();
+            stream_peer.read_to_string(&mut to_client).await.unwrap();
+            let mut to_server = String::new();
+            channel_peer.read_to_string(&mut to_server).await.unwrap();
+            (to_client, to_server)
+        };
+
+        let (copied, (to_client, to_server)) = tokio::join!(
+            copy_bidirectional_limited(&mut channel, &mut stream, &limiters),
+            peers
+        );
+        assert_eq!(copied.unwrap(), (11, 11));
+        assert_eq!(to_client, "from server");
+        assert_eq!(to_server, "from client");
+    }
+}
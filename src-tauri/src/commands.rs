@@ -1,10 +1,33 @@
-use crate::settings::AppConfig;
+use crate::allowlist::ClientAllowlist;
+use crate::bandwidth::BandwidthLimit;
+use crate::duplicate::DuplicateOverrides;
+use crate::error::{VesperError, VesperResult};
+use crate::limits::ClientLimits;
+use crate::monitor::MetricSample;
+use crate::openssh::{export_openssh, OpenSshExport};
+use crate::pool::MAX_SESSION_POOL_SIZE;
+use crate::ports::{port_mappings, validate_port_mappings, PortMapping};
+use crate::probe::{ProbeResult, TunnelProbe};
+use crate::profiles::{validate_profile_name, ProfileInfo, Profiles};
+use crate::schedule::{ScheduleStatus, TunnelSchedule};
+use crate::settings::{apply_settings, AppConfig};
+use crate::sftp::{SftpEntry, TransferDirection, TransferProgress};
+use crate::snippets::{snippet_variables, CommandSnippet, SnippetRunResult};
 use crate::ssh::{
-    generate_id, AuthMethod, ConnectionManager, SSHConnection, SSHTunnel, TunnelType,
+    default_session_pool_size, generate_id, AuthMethod, ConnectionManager, OnDemand, SSHConnection,
+    SSHTunnel, TunnelType,
 };
+use crate::stats::TunnelStats;
+use crate::storage::{DataManager, SnapshotInfo};
+use crate::store::Store;
+use crate::templates::{
+    builtin_templates, is_builtin_id, pick_free_local_port, render_open_url, TunnelTemplate,
+};
+use crate::terminal::{TerminalEvent, TerminalInfo, DEFAULT_TERMINAL_TYPE};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tauri::State;
+use tauri::{AppHandle, Emitter, State};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateConnectionRequest {
@@ -15,6 +38,10 @@ pub struct CreateConnectionRequest {
     pub auth_method: String,
     pub password: Option<String>,
     pub key_path: Option<String>,
+    #[serde(default)]
+    pub session_pool_size: Option<u8>,
+    #[serde(default)]
+    pub bandwidth: Option<BandwidthLimit>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +54,10 @@ pub struct UpdateConnectionRequest {
     pub auth_method: String,
     pub password: Option<String>,
     pub key_path: Option<String>,
+    #[serde(default)]
+    pub session_pool_size: Option<u8>,
+    #[serde(default)]
+    pub bandwidth: Option<BandwidthLimit>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +69,24 @@ pub struct CreateTunnelRequest {
     pub remote_host: String,
     pub remote_port: u16,
     pub auto_reconnect: bool,
+    #[serde(default)]
+    pub probe: Option<TunnelProbe>,
+    #[serde(default)]
+    pub bandwidth: Option<BandwidthLimit>,
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+    #[serde(default)]
+    pub client_limits: Option<ClientLimits>,
+    #[serde(default)]
+    pub on_demand: Option<OnDemand>,
+    #[serde(default)]
+    pub port_count: Option<u16>,
+    #[serde(default)]
+    pub extra_ports: Vec<PortMapping>,
+    #[serde(default)]
+    pub open_url: Option<String>,
+    #[serde(default)]
+    pub schedule: Option<TunnelSchedule>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -50,25 +99,174 @@ pub struct UpdateTunnelRequest {
     pub remote_host: String,
     pub remote_port: u16,
     pub auto_reconnect: bool,
+    #[serde(default)]
+    pub probe: Option<TunnelProbe>,
+    #[serde(default)]
+    pub bandwidth: Option<BandwidthLimit>,
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+    #[serde(default)]
+    pub client_limits: Option<ClientLimits>,
+    #[serde(default)]
+    pub on_demand: Option<OnDemand>,
+    #[serde(default)]
+    pub port_count: Option<u16>,
+    #[serde(default)]
+    pub extra_ports: Vec<PortMapping>,
+    #[serde(default)]
+    pub open_url: Option<String>,
+    #[serde(default)]
+    pub schedule: Option<TunnelSchedule>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateTunnelFromTemplateRequest {
+    pub template_id: String,
+    pub connection_id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateSnippetRequest {
+    pub connection_id: String,
+    pub name: String,
+    pub command: String,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateSnippetRequest {
+    pub id: String,
+    pub name: String,
+    pub command: String,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunSnippetRequest {
+    pub id: String,
+    pub run_id: Option<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+    #[serde(default)]
+    pub stream: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnippetInfo {
+    #[serde(flatten)]
+    pub snippet: CommandSnippet,
+    pub variables: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpenTerminalRequest {
+    pub connection_id: String,
+    pub term: Option<String>,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferRequest {
+    pub connection_id: String,
+    pub local_path: String,
+    pub remote_path: String,
+    #[serde(default)]
+    pub resume: bool,
 }
 
 // Initialize Data Storage
 #[tauri::command]
-pub async fn initialize_storage(manager: State<'_, Arc<ConnectionManager>>) -> Result<(), String> {
+pub async fn initialize_storage(
+    app: AppHandle,
+    manager: State<'_, Arc<ConnectionManager>>,
+    store: State<'_, Store>,
+) -> VesperResult<()> {
+    manager.initialize().await?;
+
+    let restore_manager = manager.inner().clone();
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = restore_manager.restore_last_session().await {
+            log::error!("Failed to restore last session: {}", e);
+        }
+    });
+
+    if store.is_read_only() {
+        let message = "Another Vesper instance is running; changes will not be saved";
+        if let Err(e) = app.emit("storage-read-only", message) {
+            log::error!("Failed to emit storage read-only notice: {}", e);
+        }
+    }
+
+    if let Some(notice) = DataManager::take_recovery_notice() {
+        if let Err(e) = app.emit("storage-recovered", &notice) {
+            log::error!("Failed to emit storage recovery notice: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_snapshots(store: State<'_, Store>) -> VesperResult<Vec<SnapshotInfo>> {
+    store.list_snapshots().await
+}
+
+// Replace the data file with a snapshot and reload everything from it
+#[tauri::command]
+pub async fn restore_snapshot(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+    store: State<'_, Store>,
+) -> VesperResult<()> {
+    manager.shutdown_all("Restoring data snapshot").await;
+    store.restore_snapshot(id).await?;
     manager.initialize().await
 }
 
+#[tauri::command]
+pub async fn list_profiles(profiles: State<'_, Profiles>) -> VesperResult<Vec<ProfileInfo>> {
+    Ok(profiles.list())
+}
+
+// Stop everything running on the current profile, then load another (created if missing)
+#[tauri::command]
+pub async fn switch_profile(
+    name: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+    profiles: State<'_, Profiles>,
+) -> VesperResult<ProfileInfo> {
+    validate_profile_name(&name)?;
+    if name == profiles.active() {
+        return Ok(profiles.info(&name));
+    }
+
+    manager.shutdown_all("Switching profile").await;
+
+    let data_manager = DataManager::with_path(profiles.profile_dir(&name));
+    manager.switch_data_manager(data_manager).await?;
+    profiles.set_active(&name)?;
+
+    Ok(profiles.info(&name))
+}
+
 // SSH Connection Commands
 #[tauri::command]
 pub async fn create_connection(
     request: CreateConnectionRequest,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<String, String> {
+    store: State<'_, Store>,
+) -> VesperResult<String> {
     let auth_method = match request.auth_method.as_str() {
         "password" => AuthMethod::Password,
         "key" => AuthMethod::Key,
-        _ => return Err("Invalid auth method".to_string()),
+        _ => return Err(VesperError::InvalidInput("Invalid auth method".to_string())),
     };
+    let key_path = key_path_or_default(&auth_method, request.key_path, &store).await?;
+    let session_pool_size = validate_session_pool_size(request.session_pool_size)?;
+    validate_bandwidth(request.bandwidth.as_ref())?;
 
     let connection = SSHConnection {
         id: generate_id(),
@@ -78,19 +276,100 @@ pub async fn create_connection(
         username: request.username,
         auth_method,
         password: request.password,
-        key_path: request.key_path,
+        key_path,
         status: crate::ssh::ConnectionStatus::Disconnected,
         last_connected: None,
         created_at: std::time::SystemTime::now(),
+        session_pool_size,
+        bandwidth: request.bandwidth,
     };
 
     manager.add_connection(connection).await
 }
 
+fn validate_bandwidth(limit: Option<&BandwidthLimit>) -> VesperResult<()> {
+    limit
+        .map_or(Ok(()), BandwidthLimit::validate)
+        .map_err(VesperError::InvalidInput)
+}
+
+fn validate_client_limits(limits: Option<&ClientLimits>) -> VesperResult<()> {
+    limits
+        .map_or(Ok(()), ClientLimits::validate)
+        .map_err(VesperError::InvalidInput)
+}
+
+fn validate_schedule(schedule: Option<&TunnelSchedule>) -> VesperResult<()> {
+    schedule
+        .map_or(Ok(()), TunnelSchedule::validate)
+        .map_err(VesperError::InvalidInput)
+}
+
+fn validate_on_demand(on_demand: Option<&OnDemand>, tunnel_type: &TunnelType) -> VesperResult<()> {
+    let Some(on_demand) = on_demand else {
+        return Ok(());
+    };
+    if matches!(tunnel_type, TunnelType::Remote) {
+        return Err(VesperError::InvalidInput(
+            "On-demand mode is only available for local tunnels".to_string(),
+        ));
+    }
+    on_demand.validate().map_err(VesperError::InvalidInput)
+}
+
+fn validate_probe(probe: Option<&TunnelProbe>) -> VesperResult<()> {
+    probe
+        .map_or(Ok(()), TunnelProbe::validate)
+        .map_err(VesperError::InvalidInput)
+}
+
+fn validate_open_url(url: Option<&str>) -> VesperResult<()> {
+    url.map_or(Ok(()), crate::templates::validate_open_url)
+        .map_err(VesperError::InvalidInput)
+}
+
+// Every tunnel goes through these before it's saved, whether typed in or made from a template
+fn validate_tunnel(tunnel: &SSHTunnel) -> VesperResult<()> {
+    validate_bandwidth(tunnel.bandwidth.as_ref())?;
+    ClientAllowlist::parse(&tunnel.allowed_clients).map_err(VesperError::InvalidInput)?;
+    validate_client_limits(tunnel.client_limits.as_ref())?;
+    validate_on_demand(tunnel.on_demand.as_ref(), &tunnel.tunnel_type)?;
+    validate_schedule(tunnel.schedule.as_ref())?;
+    validate_probe(tunnel.probe.as_ref())?;
+    validate_open_url(tunnel.open_url.as_deref())?;
+    validate_port_mappings(tunnel).map_err(VesperError::InvalidInput)
+}
+
+fn validate_session_pool_size(size: Option<u8>) -> VesperResult<u8> {
+    match size {
+        None => Ok(default_session_pool_size()),
+        Some(size) if (1..=MAX_SESSION_POOL_SIZE).contains(&size) => Ok(size),
+        Some(_) => Err(VesperError::InvalidInput(format!(
+            "Session pool size must be between 1 and {}",
+            MAX_SESSION_POOL_SIZE
+        ))),
+    }
+}
+
+// Key-auth connections without their own key use the default key from settings
+async fn key_path_or_default(
+    auth_method: &AuthMethod,
+    key_path: Option<String>,
+    store: &Store,
+) -> VesperResult<Option<String>> {
+    let key_path = key_path.filter(|path| !path.trim().is_empty());
+    if key_path.is_some() || !matches!(auth_method, AuthMethod::Key) {
+        return Ok(key_path);
+    }
+
+    let settings = store.load().await?.settings;
+    Ok(settings.default_key_path().map(str::to_string))
+}
+
 #[tauri::command]
 pub async fn get_connections(
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<Vec<SSHConnection>, String> {
+) -> VesperResult<Vec<SSHConnection>> {
     Ok(manager.get_connections().await)
 }
 
@@ -98,7 +377,7 @@ pub async fn get_connections(
 pub async fn get_connection(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<Option<SSHConnection>, String> {
+) -> VesperResult<Option<SSHConnection>> {
     Ok(manager.get_connection(&id).await)
 }
 
@@ -106,17 +385,22 @@ pub async fn get_connection(
 pub async fn update_connection(
     request: UpdateConnectionRequest,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<(), String> {
+) -> VesperResult<()> {
     let existing_connection = manager
         .get_connection(&request.id)
         .await
-        .ok_or("Connection not found")?;
+        .ok_or_else(|| VesperError::not_found("Connection", &request.id))?;
 
     let auth_method = match request.auth_method.as_str() {
         "password" => AuthMethod::Password,
         "key" => AuthMethod::Key,
-        _ => return Err("Invalid auth method".to_string()),
+        _ => return Err(VesperError::InvalidInput("Invalid auth method".to_string())),
+    };
+    let session_pool_size = match request.session_pool_size {
+        Some(size) => validate_session_pool_size(Some(size))?,
+        None => existing_connection.session_pool_size,
     };
+    validate_bandwidth(request.bandwidth.as_ref())?;
 
     let updated_connection = SSHConnection {
         id: request.id,
@@ -130,6 +414,8 @@ pub async fn update_connection(
         status: existing_connection.status,
         last_connected: existing_connection.last_connected,
         created_at: existing_connection.created_at,
+        session_pool_size,
+        bandwidth: request.bandwidth,
     };
 
     manager
@@ -141,32 +427,46 @@ pub async fn update_connection(
 pub async fn delete_connection(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<(), String> {
+) -> VesperResult<()> {
     manager.delete_connection(id).await
 }
 
+// Copy a connection with all of its tunnels; conflicting ports are reported and nothing is saved
+#[tauri::command]
+pub async fn duplicate_connection(
+    id: String,
+    overrides: Option<DuplicateOverrides>,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<String> {
+    manager
+        .duplicate_connection(&id, &overrides.unwrap_or_default())
+        .await
+}
+
 #[tauri::command]
 pub async fn test_connection(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<crate::ssh::ConnectionResult, String> {
+) -> VesperResult<crate::ssh::ConnectionResult> {
     let connection = manager
         .get_connection(&id)
         .await
-        .ok_or("Connection not found")?;
+        .ok_or_else(|| VesperError::not_found("Connection", &id))?;
 
-    Ok(manager.test_connection(&connection).await)
+    manager.test_connection(&connection).await
 }
 
 #[tauri::command]
 pub async fn test_connection_data(
     request: CreateConnectionRequest,
-) -> Result<crate::ssh::ConnectionResult, String> {
+    store: State<'_, Store>,
+) -> VesperResult<crate::ssh::ConnectionResult> {
     let auth_method = match request.auth_method.as_str() {
         "password" => AuthMethod::Password,
         "key" => AuthMethod::Key,
-        _ => return Err("Invalid auth method".to_string()),
+        _ => return Err(VesperError::InvalidInput("Invalid auth method".to_string())),
     };
+    let key_path = key_path_or_default(&auth_method, request.key_path, &store).await?;
 
     // 创建临时连接对象用于测试
     let test_connection = SSHConnection {
@@ -177,30 +477,59 @@ pub async fn test_connection_data(
         username: request.username,
         auth_method,
         password: request.password,
-        key_path: request.key_path,
+        key_path,
         status: crate::ssh::ConnectionStatus::Disconnected,
         last_connected: None,
         created_at: std::time::SystemTime::now(),
+        session_pool_size: default_session_pool_size(),
+        bandwidth: None,
     };
 
     // 执行连接测试
-    Ok(crate::ssh::test_ssh_connection(&test_connection).await)
+    crate::ssh::test_ssh_connection(&test_connection).await
 }
 
 #[tauri::command]
 pub async fn connect_ssh(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<crate::ssh::ConnectionResult, String> {
-    Ok(manager.connect_ssh(&id).await)
+) -> VesperResult<crate::ssh::ConnectionResult> {
+    manager.connect_ssh(&id).await
 }
 
 #[tauri::command]
 pub async fn disconnect_ssh(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<crate::ssh::ConnectionResult, String> {
-    Ok(manager.disconnect_ssh(&id).await)
+) -> VesperResult<crate::ssh::ConnectionResult> {
+    manager.disconnect_ssh(&id).await
+}
+
+#[tauri::command]
+pub async fn export_openssh_config(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<OpenSshExport> {
+    let connection = manager
+        .get_connection(&id)
+        .await
+        .ok_or_else(|| VesperError::not_found("Connection", &id))?;
+    let tunnels = manager.get_tunnels_by_connection(&id).await;
+
+    Ok(export_openssh(&connection, &tunnels))
+}
+
+// Latency and resource history of a connected session, oldest first
+#[tauri::command]
+pub async fn get_connection_metrics(
+    connection_id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<Vec<MetricSample>> {
+    if manager.get_connection(&connection_id).await.is_none() {
+        return Err(VesperError::not_found("Connection", connection_id));
+    }
+
+    Ok(manager.get_connection_metrics(&connection_id).await)
 }
 
 // SSH Tunnel Commands
@@ -208,11 +537,11 @@ pub async fn disconnect_ssh(
 pub async fn create_tunnel(
     request: CreateTunnelRequest,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<String, String> {
+) -> VesperResult<String> {
     let tunnel_type = match request.tunnel_type.as_str() {
         "local" => TunnelType::Local,
         "remote" => TunnelType::Remote,
-        _ => return Err("Invalid tunnel type".to_string()),
+        _ => return Err(VesperError::InvalidInput("Invalid tunnel type".to_string())),
     };
 
     let tunnel = SSHTunnel {
@@ -225,7 +554,19 @@ pub async fn create_tunnel(
         remote_port: request.remote_port,
         status: crate::ssh::TunnelStatus::Inactive,
         auto_reconnect: request.auto_reconnect,
+        probe: request.probe,
+        bandwidth: request.bandwidth,
+        allowed_clients: request.allowed_clients,
+        client_limits: request.client_limits,
+        on_demand: request.on_demand,
+        port_count: request.port_count,
+        extra_ports: request.extra_ports,
+        open_url: request.open_url,
+        schedule: request.schedule,
+        port_errors: Vec::new(),
+        last_probe: None,
     };
+    validate_tunnel(&tunnel)?;
 
     manager.add_tunnel(tunnel).await
 }
@@ -234,18 +575,18 @@ pub async fn create_tunnel(
 pub async fn update_tunnel(
     request: UpdateTunnelRequest,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<(), String> {
+) -> VesperResult<()> {
     // Get existing tunnels to find the current status
     let existing_tunnels = manager.get_tunnels().await;
     let existing_tunnel = existing_tunnels
         .iter()
         .find(|t| t.id == request.id)
-        .ok_or("Tunnel not found")?;
+        .ok_or_else(|| VesperError::not_found("Tunnel", &request.id))?;
 
     let tunnel_type = match request.tunnel_type.as_str() {
         "local" => TunnelType::Local,
         "remote" => TunnelType::Remote,
-        _ => return Err("Invalid tunnel type".to_string()),
+        _ => return Err(VesperError::InvalidInput("Invalid tunnel type".to_string())),
     };
 
     let updated_tunnel = SSHTunnel {
@@ -258,7 +599,19 @@ pub async fn update_tunnel(
         remote_port: request.remote_port,
         status: existing_tunnel.status.clone(), // Preserve the current status
         auto_reconnect: request.auto_reconnect,
+        probe: request.probe,
+        bandwidth: request.bandwidth,
+        allowed_clients: request.allowed_clients,
+        client_limits: request.client_limits,
+        on_demand: request.on_demand,
+        port_count: request.port_count,
+        extra_ports: request.extra_ports,
+        open_url: request.open_url,
+        schedule: request.schedule,
+        port_errors: existing_tunnel.port_errors.clone(),
+        last_probe: existing_tunnel.last_probe.clone(),
     };
+    validate_tunnel(&updated_tunnel)?;
 
     manager
         .update_tunnel(updated_tunnel.id.clone(), updated_tunnel)
@@ -268,7 +621,7 @@ pub async fn update_tunnel(
 #[tauri::command]
 pub async fn get_tunnels(
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<Vec<SSHTunnel>, String> {
+) -> VesperResult<Vec<SSHTunnel>> {
     Ok(manager.get_tunnels().await)
 }
 
@@ -276,7 +629,7 @@ pub async fn get_tunnels(
 pub async fn get_tunnels_by_connection(
     connection_id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<Vec<SSHTunnel>, String> {
+) -> VesperResult<Vec<SSHTunnel>> {
     Ok(manager.get_tunnels_by_connection(&connection_id).await)
 }
 
@@ -284,7 +637,7 @@ pub async fn get_tunnels_by_connection(
 pub async fn delete_tunnel(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<(), String> {
+) -> VesperResult<()> {
     manager.delete_tunnel(id).await
 }
 
@@ -292,38 +645,494 @@ pub async fn delete_tunnel(
 pub async fn stop_tunnel(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<(), String> {
+) -> VesperResult<()> {
+    manager.override_schedule(&id).await;
     manager.stop_tunnel(id).await
 }
 
+#[tauri::command]
+pub async fn get_tunnel_stats(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<TunnelStats> {
+    manager.get_tunnel_stats(&id).await
+}
+
+#[tauri::command]
+pub async fn probe_tunnel(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<ProbeResult> {
+    manager.probe_tunnel(&id).await
+}
+
 #[tauri::command]
 pub async fn start_tunnel(
     id: String,
     manager: State<'_, Arc<ConnectionManager>>,
-) -> Result<crate::ssh::ConnectionResult, String> {
-    Ok(manager.start_tunnel(&id).await)
+) -> VesperResult<crate::ssh::ConnectionResult> {
+    manager.override_schedule(&id).await;
+    manager.start_tunnel(&id).await
+}
+
+// The tunnel's web page with its local port filled in
+#[tauri::command]
+pub async fn get_tunnel_open_url(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<Option<String>> {
+    let tunnel = manager
+        .get_tunnels()
+        .await
+        .into_iter()
+        .find(|tunnel| tunnel.id == id)
+        .ok_or_else(|| VesperError::not_found("Tunnel", &id))?;
+
+    Ok(tunnel
+        .open_url
+        .map(|url| render_open_url(&url, tunnel.local_port)))
+}
+
+#[tauri::command]
+pub async fn get_tunnel_schedule_status(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<ScheduleStatus> {
+    manager.get_schedule_status(&id).await
+}
+
+// Tunnel Template Commands
+#[tauri::command]
+pub async fn get_tunnel_templates(store: State<'_, Store>) -> VesperResult<Vec<TunnelTemplate>> {
+    let mut custom: Vec<TunnelTemplate> =
+        store.load().await?.tunnel_templates.into_values().collect();
+    custom.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut templates = builtin_templates();
+    templates.extend(custom);
+    Ok(templates)
+}
+
+#[tauri::command]
+pub async fn save_tunnel_template(
+    template: TunnelTemplate,
+    store: State<'_, Store>,
+) -> VesperResult<String> {
+    if template.builtin || is_builtin_id(&template.id) {
+        return Err(VesperError::InvalidInput(
+            "Built-in templates can't be changed".to_string(),
+        ));
+    }
+    if template.name.trim().is_empty() {
+        return Err(VesperError::InvalidInput(
+            "Template name is required".to_string(),
+        ));
+    }
+    validate_tunnel(&tunnel_from_template(
+        template.clone(),
+        String::new(),
+        template.local_port,
+    ))?;
+
+    let mut template = template;
+    if template.id.is_empty() {
+        template.id = generate_id();
+    }
+    let id = template.id.clone();
+    store.update(move |data| {
+        data.tunnel_templates.insert(template.id.clone(), template);
+    })?;
+    Ok(id)
+}
+
+#[tauri::command]
+pub async fn delete_tunnel_template(id: String, store: State<'_, Store>) -> VesperResult<()> {
+    if is_builtin_id(&id) {
+        return Err(VesperError::InvalidInput(
+            "Built-in templates can't be deleted".to_string(),
+        ));
+    }
+
+    store.update(move |data| {
+        data.tunnel_templates.remove(&id);
+    })
+}
+
+// Local tunnels get the template's port if it's free, otherwise a free one near it
+#[tauri::command]
+pub async fn create_tunnel_from_template(
+    request: CreateTunnelFromTemplateRequest,
+    manager: State<'_, Arc<ConnectionManager>>,
+    store: State<'_, Store>,
+) -> VesperResult<String> {
+    if manager
+        .get_connection(&request.connection_id)
+        .await
+        .is_none()
+    {
+        return Err(VesperError::not_found("Connection", request.connection_id));
+    }
+
+    let template = match builtin_templates()
+        .into_iter()
+        .find(|template| template.id == request.template_id)
+    {
+        Some(template) => template,
+        None => store
+            .load()
+            .await?
+            .tunnel_templates
+            .remove(&request.template_id)
+            .ok_or_else(|| VesperError::not_found("Tunnel template", &request.template_id))?,
+    };
+
+    let local_port = match template.tunnel_type {
+        TunnelType::Local => {
+            let taken: HashSet<u16> = manager
+                .get_tunnels()
+                .await
+                .iter()
+                .filter(|tunnel| matches!(tunnel.tunnel_type, TunnelType::Local))
+                .flat_map(port_mappings)
+                .map(|mapping| mapping.local_port)
+                .collect();
+            pick_free_local_port(template.local_port, &taken).ok_or_else(|| {
+                VesperError::Internal("No free local port is available".to_string())
+            })?
+        }
+        TunnelType::Remote => template.local_port,
+    };
+
+    let name = request.name;
+    let mut tunnel = tunnel_from_template(template, request.connection_id, local_port);
+    if let Some(name) = name {
+        tunnel.name = name;
+    }
+    validate_tunnel(&tunnel)?;
+
+    manager.add_tunnel(tunnel).await
+}
+
+fn tunnel_from_template(
+    template: TunnelTemplate,
+    connection_id: String,
+    local_port: u16,
+) -> SSHTunnel {
+    SSHTunnel {
+        id: generate_id(),
+        name: template.name,
+        connection_id,
+        tunnel_type: template.tunnel_type,
+        local_port,
+        remote_host: template.remote_host,
+        remote_port: template.remote_port,
+        status: crate::ssh::TunnelStatus::Inactive,
+        auto_reconnect: template.auto_reconnect,
+        probe: template.probe,
+        bandwidth: None,
+        allowed_clients: Vec::new(),
+        client_limits: None,
+        on_demand: None,
+        port_count: None,
+        extra_ports: Vec::new(),
+        open_url: template.open_url,
+        schedule: None,
+        port_errors: Vec::new(),
+        last_probe: None,
+    }
+}
+
+// Command Snippet Commands
+#[tauri::command]
+pub async fn create_snippet(
+    request: CreateSnippetRequest,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<String> {
+    let snippet = CommandSnippet {
+        id: generate_id(),
+        connection_id: request.connection_id,
+        name: request.name,
+        command: request.command,
+        timeout_secs: request.timeout_secs,
+        created_at: std::time::SystemTime::now(),
+    };
+
+    manager.add_snippet(snippet).await
+}
+
+#[tauri::command]
+pub async fn update_snippet(
+    request: UpdateSnippetRequest,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    let updated_snippet = CommandSnippet {
+        id: request.id,
+        connection_id: String::new(), // Snippets cannot move between connections
+        name: request.name,
+        command: request.command,
+        timeout_secs: request.timeout_secs,
+        created_at: std::time::SystemTime::now(),
+    };
+
+    manager
+        .update_snippet(updated_snippet.id.clone(), updated_snippet)
+        .await
+}
+
+#[tauri::command]
+pub async fn get_snippets_by_connection(
+    connection_id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<Vec<SnippetInfo>> {
+    Ok(manager
+        .get_snippets_by_connection(&connection_id)
+        .await
+        .into_iter()
+        .map(|snippet| SnippetInfo {
+            variables: snippet_variables(&snippet.command),
+            snippet,
+        })
+        .collect())
+}
+
+#[tauri::command]
+pub async fn delete_snippet(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    manager.delete_snippet(id).await
+}
+
+// Output chunks are emitted as `snippet-output` events when streaming is requested
+#[tauri::command]
+pub async fn run_snippet(
+    request: RunSnippetRequest,
+    app: AppHandle,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<SnippetRunResult> {
+    let run_id = request.run_id.unwrap_or_else(generate_id);
+    let output_sink: Option<crate::snippets::SnippetOutputSink> = if request.stream {
+        Some(Box::new(move |output| {
+            if let Err(e) = app.emit("snippet-output", output) {
+                log::error!("Failed to emit snippet output: {}", e);
+            }
+        }))
+    } else {
+        None
+    };
+
+    manager
+        .run_snippet(&request.id, run_id, &request.variables, output_sink)
+        .await
+}
+
+#[tauri::command]
+pub async fn cancel_snippet(
+    run_id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<bool> {
+    Ok(manager.cancel_snippet_run(&run_id).await)
+}
+
+// Terminal Commands
+// Output is emitted as `terminal-output` events and shell exit as `terminal-exit`
+#[tauri::command]
+pub async fn open_terminal(
+    request: OpenTerminalRequest,
+    app: AppHandle,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<TerminalInfo> {
+    let event_sink: crate::terminal::TerminalEventSink = Arc::new(move |event| {
+        let event_name = match &event {
+            TerminalEvent::Output { .. } => "terminal-output",
+            TerminalEvent::Exit { .. } => "terminal-exit",
+        };
+        if let Err(e) = app.emit(event_name, event) {
+            log::error!("Failed to emit terminal event: {}", e);
+        }
+    });
+
+    manager
+        .open_terminal(
+            &request.connection_id,
+            request
+                .term
+                .unwrap_or_else(|| DEFAULT_TERMINAL_TYPE.to_string()),
+            request.cols,
+            request.rows,
+            event_sink,
+        )
+        .await
+}
+
+#[tauri::command]
+pub async fn get_terminals_by_connection(
+    connection_id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<Vec<TerminalInfo>> {
+    Ok(manager.get_terminals_by_connection(&connection_id).await)
+}
+
+#[tauri::command]
+pub async fn write_terminal(
+    id: String,
+    data: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    manager.write_terminal(&id, data.into_bytes()).await
+}
+
+#[tauri::command]
+pub async fn resize_terminal(
+    id: String,
+    cols: u32,
+    rows: u32,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    manager.resize_terminal(&id, cols, rows).await
+}
+
+#[tauri::command]
+pub async fn close_terminal(
+    id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<bool> {
+    Ok(manager.close_terminal(&id).await)
+}
+
+// SFTP Commands
+#[tauri::command]
+pub async fn sftp_list_dir(
+    connection_id: String,
+    path: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<Vec<SftpEntry>> {
+    let sftp = manager.sftp_session(&connection_id).await?;
+    crate::sftp::list_dir(&sftp, &path).await
+}
+
+#[tauri::command]
+pub async fn sftp_stat(
+    connection_id: String,
+    path: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<SftpEntry> {
+    let sftp = manager.sftp_session(&connection_id).await?;
+    crate::sftp::stat(&sftp, &path).await
+}
+
+#[tauri::command]
+pub async fn sftp_mkdir(
+    connection_id: String,
+    path: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    let sftp = manager.sftp_session(&connection_id).await?;
+    crate::sftp::mkdir(&sftp, &path).await
+}
+
+#[tauri::command]
+pub async fn sftp_rename(
+    connection_id: String,
+    from: String,
+    to: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    let sftp = manager.sftp_session(&connection_id).await?;
+    crate::sftp::rename(&sftp, &from, &to).await
+}
+
+#[tauri::command]
+pub async fn sftp_delete(
+    connection_id: String,
+    path: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<()> {
+    let sftp = manager.sftp_session(&connection_id).await?;
+    crate::sftp::delete(&sftp, &path).await
+}
+
+// Progress is emitted as `sftp-transfer-progress` events until the transfer settles
+#[tauri::command]
+pub async fn sftp_upload(
+    request: TransferRequest,
+    app: AppHandle,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<TransferProgress> {
+    start_transfer(request, TransferDirection::Upload, app, &manager).await
+}
+
+#[tauri::command]
+pub async fn sftp_download(
+    request: TransferRequest,
+    app: AppHandle,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<TransferProgress> {
+    start_transfer(request, TransferDirection::Download, app, &manager).await
+}
+
+async fn start_transfer(
+    request: TransferRequest,
+    direction: TransferDirection,
+    app: AppHandle,
+    manager: &ConnectionManager,
+) -> VesperResult<TransferProgress> {
+    let progress_sink: crate::sftp::TransferProgressSink = Arc::new(move |progress| {
+        if let Err(e) = app.emit("sftp-transfer-progress", progress) {
+            log::error!("Failed to emit transfer progress: {}", e);
+        }
+    });
+
+    manager
+        .start_transfer(
+            &request.connection_id,
+            direction,
+            request.local_path,
+            request.remote_path,
+            request.resume,
+            progress_sink,
+        )
+        .await
+}
+
+#[tauri::command]
+pub async fn cancel_transfer(
+    transfer_id: String,
+    manager: State<'_, Arc<ConnectionManager>>,
+) -> VesperResult<bool> {
+    Ok(manager.cancel_transfer(&transfer_id).await)
 }
 
 // Settings Commands
 #[tauri::command]
-pub async fn get_settings() -> Result<AppConfig, String> {
-    use crate::storage::DataManager;
-    let data_manager = DataManager::new()?;
-    data_manager.load_settings().await
+pub async fn get_settings(store: State<'_, Store>) -> VesperResult<AppConfig> {
+    Ok(store.load().await?.settings)
 }
 
+// Validates the new settings, then applies them to the running app
 #[tauri::command]
-pub async fn update_settings(settings: AppConfig) -> Result<(), String> {
-    use crate::storage::DataManager;
-    let data_manager = DataManager::new()?;
-    data_manager.save_settings(&settings).await
+pub async fn update_settings(
+    settings: serde_json::Value,
+    app: AppHandle,
+    store: State<'_, Store>,
+) -> VesperResult<()> {
+    let previous = store.load().await?.settings;
+    let mut settings =
+        AppConfig::from_update(settings, &previous).map_err(VesperError::InvalidInput)?;
+    settings.keep_unknown_keys(&previous);
+
+    apply_settings(&app, Some(&previous), &settings);
+    store.update(move |data| data.settings = settings)
 }
 
 #[tauri::command]
-pub async fn reset_settings() -> Result<AppConfig, String> {
-    use crate::storage::DataManager;
-    let data_manager = DataManager::new()?;
-    let default_settings = AppConfig::default();
-    data_manager.save_settings(&default_settings).await?;
+pub async fn reset_settings(app: AppHandle, store: State<'_, Store>) -> VesperResult<AppConfig> {
+    let previous = store.load().await?.settings;
+    let mut default_settings = AppConfig::default();
+    default_settings.keep_unknown_keys(&previous);
+
+    apply_settings(&app, Some(&previous), &default_settings);
+    let settings = default_settings.clone();
+    store.update(move |data| data.settings = settings)?;
     Ok(default_settings)
 }
@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::SystemTime;
+
+use crate::ports::{port_mappings, validate_port_mappings};
+use crate::ssh::{
+    generate_id, ConnectionStatus, SSHConnection, SSHTunnel, TunnelStatus, TunnelType,
+};
+
+// What to change on the copy of a connection
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DuplicateOverrides {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub host: Option<String>,
+    // Added to the ports local tunnels listen on, so the copy can run next to the original
+    #[serde(default)]
+    pub local_port_offset: i32,
+}
+
+// A copied connection and its tunnels, not saved yet
+pub struct Duplicate {
+    pub connection: SSHConnection,
+    pub tunnels: Vec<SSHTunnel>,
+}
+
+// A port something listens on: on this machine, or on an SSH server (host, ssh port)
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Listener {
+    Local(u16),
+    Server(String, u16, u16),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(port) => write!(f, "local port {}", port),
+            Self::Server(host, _, port) => write!(f, "remote port {} on {}", port, host),
+        }
+    }
+}
+
+// Copy a connection and its tunnels under new IDs, or list every port the copy would fight over
+pub fn plan_duplicate(
+    source: &SSHConnection,
+    tunnels: &[SSHTunnel],
+    overrides: &DuplicateOverrides,
+    connections: &HashMap<String, SSHConnection>,
+    existing: &[SSHTunnel],
+) -> Result<Duplicate, Vec<String>> {
+    let non_empty = |value: &Option<String>| value.clone().filter(|v| !v.trim().is_empty());
+
+    let mut connection = source.clone();
+    connection.id = generate_id();
+    connection.name =
+        non_empty(&overrides.name).unwrap_or_else(|| format!("{} (copy)", source.name));
+    connection.host = non_empty(&overrides.host).unwrap_or_else(|| source.host.clone());
+    connection.status = ConnectionStatus::Disconnected;
+    connection.last_connected = None;
+    connection.created_at = SystemTime::now();
+
+    let mut conflicts = Vec::new();
+    let mut copies = Vec::new();
+    for tunnel in tunnels {
+        let mut copy = tunnel.clone();
+        copy.id = generate_id();
+        copy.connection_id = connection.id.clone();
+        copy.status = TunnelStatus::Inactive;
+        copy.port_errors.clear();
+        copy.last_probe = None;
+
+        if matches!(copy.tunnel_type, TunnelType::Local) {
+            if let Err(e) = shift_local_ports(&mut copy, overrides.local_port_offset) {
+                conflicts.push(format!("Tunnel '{}': {}", tunnel.name, e));
+                continue;
+            }
+        }
+        copies.push(copy);
+    }
+
+    let mut taken: HashMap<Listener, &str> = HashMap::new();
+    for tunnel in existing {
+        let server = connections.get(&tunnel.connection_id);
+        for listener in listeners(tunnel, server) {
+            taken.entry(listener).or_insert(&tunnel.name);
+        }
+    }
+    for copy in &copies {
+        for listener in listeners(copy, Some(&connection)) {
+            let message = format!("Tunnel '{}': {}", copy.name, listener);
+            if let Some(owner) = taken.insert(listener, &copy.name) {
+                conflicts.push(format!("{} is already used by tunnel '{}'", message, owner));
+            }
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(Duplicate {
+            connection,
+            tunnels: copies,
+        })
+    } else {
+        Err(conflicts)
+    }
+}
+
+fn shift_local_ports(tunnel: &mut SSHTunnel, offset: i32) -> Result<(), String> {
+    let shift = |port: u16| {
+        u16::try_from(i32::from(port) + offset)
+            .ok()
+            .filter(|port| *port != 0)
+            .ok_or_else(|| format!("local port {} moved by {} is out of range", port, offset))
+    };
+
+    tunnel.local_port = shift(tunnel.local_port)?;
+    for mapping in &mut tunnel.extra_ports {
+        mapping.local_port = shift(mapping.local_port)?;
+    }
+    validate_port_mappings(tunnel)
+}
+
+fn listeners(tunnel: &SSHTunnel, server: Option<&SSHConnection>) -> Vec<Listener> {
+    let mappings = port_mappings(tunnel);
+    match (&tunnel.tunnel_type, server) {
+        (TunnelType::Local, _) => mappings
+            .iter()
+            .map(|mapping| Listener::Local(mapping.local_port))
+            .collect(),
+        (TunnelType::Remote, Some(server)) => mappings
+            .iter()
+            .map(|mapping| Listener::Server(server.host.clone(), server.port, mapping.remote_port))
+            .collect(),
+        (TunnelType::Remote, None) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::AuthMethod;
+
+    fn connection(id: &str, host: &str) -> SSHConnection {
+        SSHConnection {
+            name: "Prod".to_string(),
+            host: host.to_string(),
+            username: "deploy".to_string(),
+            auth_method: AuthMethod::Key,
+            password: None,
+            status: ConnectionStatus::Connected,
+            ..SSHConnection::fixture(id)
+        }
+    }
+
+    fn tunnel(name: &str, tunnel_type: TunnelType, local_port: u16, remote_port: u16) -> SSHTunnel {
+        SSHTunnel {
+            name: name.to_string(),
+            tunnel_type,
+            local_port,
+            remote_port,
+            status: TunnelStatus::Active,
+            auto_reconnect: true,
+            ..SSHTunnel::fixture(name, "prod")
+        }
+    }
+
+    fn setup() -> (HashMap<String, SSHConnection>, Vec<SSHTunnel>) {
+        let prod = connection("prod", "prod.example.com");
+        let connections = HashMap::from([(prod.id.clone(), prod)]);
+        let tunnels = vec![
+            tunnel("postgres", TunnelType::Local, 15432, 5432),
+            tunnel("webhook", TunnelType::Remote, 3000, 8080),
+        ];
+        (connections, tunnels)
+    }
+
+    #[test]
+    fn copy_gets_new_ids_and_overrides() {
+        let (connections, tunnels) = setup();
+        let overrides = DuplicateOverrides {
+            name: Some("Staging".to_string()),
+            host: Some("staging.example.com".to_string()),
+            local_port_offset: 1000,
+        };
+
+        let copy = plan_duplicate(
+            &connections["prod"],
+            &tunnels,
+            &overrides,
+            &connections,
+            &tunnels,
+        )
+        .unwrap();
+
+        assert_ne!(copy.connection.id, "prod");
+        assert_eq!(copy.connection.name, "Staging");
+        assert_eq!(copy.connection.host, "staging.example.com");
+        assert!(matches!(
+            copy.connection.status,
+            ConnectionStatus::Disconnected
+        ));
+        assert!(copy
+            .tunnels
+            .iter()
+            .all(|tunnel| tunnel.connection_id == copy.connection.id
+                && tunnel.id != tunnel.name
+                && matches!(tunnel.status, TunnelStatus::Inactive)));
+        // Remote tunnels keep their local target port
+        let ports: Vec<u16> = copy.tunnels.iter().map(|t| t.local_port).collect();
+        assert_eq!(ports, vec![16432, 3000]);
+    }
+
+    #[test]
+    fn conflicting_ports_are_all_reported() {
+        let (connections, tunnels) = setup();
+
+        // Same host and no offset: both the local and the remote listener are taken
+        let conflicts = plan_duplicate(
+            &connections["prod"],
+            &tunnels,
+            &DuplicateOverrides::default(),
+            &connections,
+            &tunnels,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(conflicts.len(), 2);
+
+        let conflicts = plan_duplicate(
+            &connections["prod"],
+            &tunnels,
+            &DuplicateOverrides {
+                local_port_offset: 60000,
+                ..DuplicateOverrides::default()
+            },
+            &connections,
+            &[],
+        )
+        .err()
+        .unwrap();
+        assert_eq!(conflicts.len(), 1);
+        assert!(conflicts[0].contains("out of range"));
+    }
+}
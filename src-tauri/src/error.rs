@@ -0,0 +1,299 @@
+use async_ssh2_lite::ssh2::ErrorCode;
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::error::Error as _;
+use std::io;
+use thiserror::Error;
+
+// LIBSSH2_ERROR_HOSTKEY_SIGN: the server could not prove it owns the host key
+const LIBSSH2_ERROR_HOSTKEY_SIGN: i32 = -11;
+
+pub type VesperResult<T> = Result<T, VesperError>;
+
+// Cause of an error that may come from more than one library
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+// Every error the backend reports to the frontend; `code()` is stable and safe to branch on
+#[derive(Debug, Error)]
+pub enum VesperError {
+    #[error("{kind} not found")]
+    NotFound { kind: &'static str, id: String },
+
+    #[error("{0}")]
+    InvalidInput(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("Password authentication requires providing a password")]
+    PasswordMissing,
+
+    #[error("Key authentication requires specifying a key file path")]
+    KeyPathMissing,
+
+    #[error("Key file does not exist: {0}")]
+    KeyFileNotFound(String),
+
+    #[error("SSH connection timed out after {0} seconds")]
+    Timeout(u64),
+
+    #[error("Unable to connect to server {addr}")]
+    Tcp {
+        addr: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Failed to create SSH session")]
+    SshSession(#[source] async_ssh2_lite::Error),
+
+    #[error("SSH handshake failed")]
+    Handshake(#[source] async_ssh2_lite::Error),
+
+    #[error("Server could not prove it owns its host key")]
+    HostKeySignature(#[source] async_ssh2_lite::Error),
+
+    #[error("SSH authentication failed")]
+    Auth(#[source] Option<async_ssh2_lite::Error>),
+
+    #[error("No active SSH session found for connection {0}")]
+    NotConnected(String),
+
+    #[error("{side} port {port} is already in use")]
+    PortInUse { side: &'static str, port: u16 },
+
+    #[error("Failed to bind local tunnel port {port}")]
+    PortBind {
+        port: u16,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Failed to create remote forwarding for tunnel {tunnel}")]
+    RemoteForward {
+        tunnel: String,
+        #[source]
+        source: async_ssh2_lite::Error,
+    },
+
+    #[error("{0}")]
+    Ssh(String),
+
+    #[error("Failed to {action}")]
+    Sftp {
+        action: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Failed to {action}")]
+    SnippetExec {
+        action: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("{0}")]
+    Storage(String),
+
+    #[error("Failed to {action}")]
+    StorageIo {
+        action: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{what} is unreadable")]
+    StorageCorrupt {
+        what: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error(
+        "Data uses schema v{found}, but this version of Vesper only supports up to v{supported}"
+    )]
+    SchemaTooNew { found: u32, supported: u32 },
+
+    #[error("Snapshot {0} not found")]
+    SnapshotNotFound(String),
+
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to {action}")]
+    Database {
+        action: String,
+        #[source]
+        source: rusqlite::Error,
+    },
+
+    #[error("Failed to serialize data")]
+    Serialization(#[source] serde_json::Error),
+
+    #[error("Another Vesper instance is using this data directory")]
+    ReadOnly,
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl VesperError {
+    pub fn not_found(kind: &'static str, id: impl Into<String>) -> Self {
+        Self::NotFound {
+            kind,
+            id: id.into(),
+        }
+    }
+
+    // Constructors for `map_err`, naming what was being done when the cause occurred
+    pub fn storage_io(action: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let action = action.into();
+        move |source| Self::StorageIo { action, source }
+    }
+
+    pub fn corrupt<E: Into<BoxError>>(what: impl Into<String>) -> impl FnOnce(E) -> Self {
+        let what = what.into();
+        move |source| Self::StorageCorrupt {
+            what,
+            source: source.into(),
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn database(action: impl Into<String>) -> impl FnOnce(rusqlite::Error) -> Self {
+        let action = action.into();
+        move |source| Self::Database { action, source }
+    }
+
+    pub fn sftp<E: Into<BoxError>>(action: impl Into<String>) -> impl FnOnce(E) -> Self {
+        let action = action.into();
+        move |source| Self::Sftp {
+            action,
+            source: source.into(),
+        }
+    }
+
+    pub fn snippet_exec<E: Into<BoxError>>(action: impl Into<String>) -> impl FnOnce(E) -> Self {
+        let action = action.into();
+        move |source| Self::SnippetExec {
+            action,
+            source: source.into(),
+        }
+    }
+
+    // A handshake failure caused by the host key signature gets its own code
+    pub fn handshake(error: async_ssh2_lite::Error) -> Self {
+        let host_key_failure = matches!(
+            error.as_ssh2().map(|e| e.code()),
+            Some(ErrorCode::Session(LIBSSH2_ERROR_HOSTKEY_SIGN))
+        );
+
+        if host_key_failure {
+            Self::HostKeySignature(error)
+        } else {
+            Self::Handshake(error)
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NotFound { .. } => "NOT_FOUND",
+            Self::InvalidInput(_) => "INVALID_INPUT",
+            Self::Conflict(_) => "CONFLICT",
+            Self::PasswordMissing => "PASSWORD_MISSING",
+            Self::KeyPathMissing => "KEY_PATH_MISSING",
+            Self::KeyFileNotFound(_) => "KEY_FILE_NOT_FOUND",
+            Self::Timeout(_) => "TIMEOUT",
+            Self::Tcp { source, .. } => match source.kind() {
+                io::ErrorKind::ConnectionRefused => "CONNECTION_REFUSED",
+                io::ErrorKind::TimedOut => "CONNECTION_TIMEOUT",
+                io::ErrorKind::HostUnreachable => "HOST_UNREACHABLE",
+                _ => "TCP_CONNECTION_ERROR",
+            },
+            Self::SshSession(_) => "SSH_SESSION_ERROR",
+            Self::Handshake(_) => "SSH_HANDSHAKE_ERROR",
+            Self::HostKeySignature(_) => "HOST_KEY_SIGNATURE",
+            Self::Auth(_) => "SSH_AUTH_ERROR",
+            Self::NotConnected(_) => "NOT_CONNECTED",
+            Self::PortInUse { .. } => "PORT_IN_USE",
+            Self::PortBind { .. } | Self::RemoteForward { .. } => "TUNNEL_START_FAILED",
+            Self::Ssh(_) => "SSH_ERROR",
+            Self::Sftp { .. } => "SFTP_ERROR",
+            Self::SnippetExec { .. } => "SNIPPET_EXEC_ERROR",
+            Self::Storage(_) => "STORAGE_ERROR",
+            Self::StorageIo { .. } => "STORAGE_IO_ERROR",
+            Self::StorageCorrupt { .. } => "STORAGE_CORRUPT",
+            Self::SchemaTooNew { .. } => "STORAGE_SCHEMA_TOO_NEW",
+            Self::SnapshotNotFound(_) => "SNAPSHOT_NOT_FOUND",
+            #[cfg(feature = "sqlite")]
+            Self::Database { .. } => "STORAGE_DATABASE_ERROR",
+            Self::Serialization(_) => "SERIALIZATION_ERROR",
+            Self::ReadOnly => "STORAGE_READ_ONLY",
+            Self::Io(_) => "IO_ERROR",
+            Self::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    // The messages of everything underneath this error, outermost first
+    pub fn causes(&self) -> Vec<String> {
+        let mut causes = Vec::new();
+        let mut source = self.source();
+        while let Some(error) = source {
+            causes.push(error.to_string());
+            source = error.source();
+        }
+        causes
+    }
+
+    // The message followed by its causes, for logs and places that only show text
+    pub fn full_message(&self) -> String {
+        std::iter::once(self.to_string())
+            .chain(self.causes())
+            .collect::<Vec<_>>()
+            .join(": ")
+    }
+}
+
+// Sent to the frontend as `{ code, message, causes }`
+impl Serialize for VesperError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("VesperError", 3)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.full_message())?;
+        state.serialize_field("causes", &self.causes())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tcp_errors_are_coded_by_kind() {
+        let error = VesperError::Tcp {
+            addr: "example.com:22".to_string(),
+            source: io::Error::from(io::ErrorKind::ConnectionRefused),
+        };
+
+        assert_eq!(error.code(), "CONNECTION_REFUSED");
+    }
+
+    #[test]
+    fn serializes_code_message_and_causes() {
+        let error = VesperError::PortBind {
+            port: 8080,
+            source: io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "code": "TUNNEL_START_FAILED",
+                "message": "Failed to bind local tunnel port 8080: permission denied",
+                "causes": ["permission denied"],
+            })
+        );
+    }
+}
@@ -1,17 +1,56 @@
+mod allowlist;
+mod bandwidth;
 mod commands;
+mod duplicate;
+mod error;
+mod limits;
+mod logging;
+mod migrations;
+mod monitor;
+mod netwatch;
+mod openssh;
+mod pool;
+mod ports;
+mod probe;
+mod profiles;
+mod schedule;
 mod settings;
+mod sftp;
+mod snippets;
+#[cfg(feature = "sqlite")]
+mod sqlite_storage;
 mod ssh;
+mod stats;
 mod storage;
+mod store;
+mod templates;
+mod terminal;
 // mod tray; // TODO: Re-enable when Tauri v2 tray API stabilizes
 
+use profiles::Profiles;
+use settings::LogLevel;
 use ssh::ConnectionManager;
 use std::sync::Arc;
-use tauri::Manager;
+use storage::DataManager;
+use store::Store;
+use tauri::{Manager, RunEvent, WindowEvent};
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Starts at the default level; the saved log_level is applied once settings load
+    logging::init(LogLevel::default());
+
+    // Data directory comes from --data-dir, VESPER_DATA_DIR, portable mode or the OS default
+    let profiles = Profiles::resolve().expect("failed to resolve data directory");
+    if profiles.is_portable() {
+        log::info!("Running in portable mode");
+    }
+
+    // All reads and writes of the data file go through one store task
+    let store = Store::spawn(DataManager::with_path(profiles.active_dir()));
+
     // Create shared ConnectionManager
-    let connection_manager = Arc::new(ConnectionManager::new());
+    let connection_manager = Arc::new(ConnectionManager::new(store.clone()));
 
     tauri::Builder::default()
         .plugin(tauri_plugin_process::init())
@@ -19,10 +58,21 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .manage(Arc::clone(&connection_manager))
+        .manage(store)
+        .manage(profiles)
         .setup(|app| {
+            let store = app.state::<Store>().inner().clone();
+            match tauri::async_runtime::block_on(store.load()) {
+                Ok(data) => settings::apply_settings(app.handle(), None, &data.settings),
+                Err(e) => log::error!("Failed to load settings: {}", e),
+            }
+
             let manager = app.state::<Arc<ConnectionManager>>().inner().clone();
             tauri::async_runtime::spawn(async move {
                 manager.start_health_monitoring().await;
+                manager.start_schedule_monitoring().await;
+                manager.start_network_monitoring().await;
+                manager.start_metrics_monitoring().await;
             });
 
             // TODO: Initialize system tray when API stabilizes
@@ -30,32 +80,84 @@ pub fn run() {
 
             Ok(())
         })
+        .on_window_event(|window, event| {
+            if let WindowEvent::CloseRequested { .. } = event {
+                let store = window.state::<Store>();
+                settings::save_window_geometry(window, &store);
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             // Data Storage
             commands::initialize_storage,
+            commands::list_snapshots,
+            commands::restore_snapshot,
+            commands::list_profiles,
+            commands::switch_profile,
             // SSH Connection Commands
             commands::create_connection,
             commands::get_connections,
             commands::get_connection,
             commands::update_connection,
             commands::delete_connection,
+            commands::duplicate_connection,
             commands::test_connection,
             commands::test_connection_data,
             commands::connect_ssh,
             commands::disconnect_ssh,
+            commands::export_openssh_config,
+            commands::get_connection_metrics,
             // SSH Tunnel Commands
             commands::create_tunnel,
             commands::update_tunnel,
             commands::get_tunnels,
             commands::get_tunnels_by_connection,
             commands::start_tunnel,
+            commands::get_tunnel_schedule_status,
+            commands::get_tunnel_open_url,
             commands::stop_tunnel,
+            commands::probe_tunnel,
+            commands::get_tunnel_stats,
+            // Tunnel Template Commands
+            commands::get_tunnel_templates,
+            commands::save_tunnel_template,
+            commands::delete_tunnel_template,
+            commands::create_tunnel_from_template,
             commands::delete_tunnel,
+            // Command Snippet Commands
+            commands::create_snippet,
+            commands::update_snippet,
+            commands::get_snippets_by_connection,
+            commands::delete_snippet,
+            commands::run_snippet,
+            commands::cancel_snippet,
+            // Terminal Commands
+            commands::open_terminal,
+            commands::get_terminals_by_connection,
+            commands::write_terminal,
+            commands::resize_terminal,
+            commands::close_terminal,
+            // SFTP Commands
+            commands::sftp_list_dir,
+            commands::sftp_stat,
+            commands::sftp_mkdir,
+            commands::sftp_rename,
+            commands::sftp_delete,
+            commands::sftp_upload,
+            commands::sftp_download,
+            commands::cancel_transfer,
             // Settings Commands
             commands::get_settings,
             commands::update_settings,
             commands::reset_settings,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::Exit = event {
+                let store = app.state::<Store>().inner().clone();
+                if let Err(e) = tauri::async_runtime::block_on(store.flush()) {
+                    log::error!("Failed to save data on exit: {}", e);
+                }
+            }
+        });
 }
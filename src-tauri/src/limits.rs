@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::future::{pending, Future};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Duration, Instant};
+
+// Bounds on the wait for a free slot when a queueing tunnel doesn't set its own
+const DEFAULT_MAX_QUEUED: u32 = 64;
+const DEFAULT_QUEUE_TIMEOUT_SECS: u64 = 30;
+
+// Per-tunnel limits on the clients it forwards; None means unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClientLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<u32>,
+    #[serde(default)]
+    pub when_full: WhenFull,
+    // Clients beyond this many waiting for a slot are turned away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_queued: Option<u32>,
+    // A waiting client that gets no slot within this long is turned away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_timeout_secs: Option<u64>,
+    // Close a forwarded connection after this long without traffic in either direction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_lifetime_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WhenFull {
+    // New clients wait until a forwarded connection closes
+    #[default]
+    Queue,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientExit {
+    IdleTimeout,
+    LifetimeExceeded,
+}
+
+impl ClientLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_connections == Some(0) {
+            return Err("Maximum connections must be at least 1".to_string());
+        }
+        if self.idle_timeout_secs == Some(0) || self.max_lifetime_secs == Some(0) {
+            return Err("Idle timeout and maximum lifetime must be at least 1 second".to_string());
+        }
+        if self.queue_timeout_secs == Some(0) {
+            return Err("Queue timeout must be at least 1 second".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn max_queued(&self) -> u64 {
+        u64::from(self.max_queued.unwrap_or(DEFAULT_MAX_QUEUED))
+    }
+
+    pub fn queue_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.queue_timeout_secs
+                .unwrap_or(DEFAULT_QUEUE_TIMEOUT_SECS),
+        )
+    }
+}
+
+// When a forwarded connection last moved data
+pub struct ActivityClock {
+    started: Instant,
+    last_activity_ms: AtomicU64,
+}
+
+impl ActivityClock {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_activity_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = self.started.elapsed().as_millis() as u64;
+        self.last_activity_ms.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn idle_for(&self) -> Duration {
+        let last_activity = Duration::from_millis(self.last_activity_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last_activity)
+    }
+}
+
+// Wraps one side of a forwarded connection and records every read and write on `clock`
+pub struct TrackedStream<'a, S> {
+    inner: &'a mut S,
+    clock: &'a ActivityClock,
+}
+
+impl<'a, S> TrackedStream<'a, S> {
+    pub fn new(inner: &'a mut S, clock: &'a ActivityClock) -> Self {
+        Self { inner, clock }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TrackedStream<'_, S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut *this.inner).poll_read(cx, buf);
+        if matches!(result, Poll::Ready(Ok(()))) && buf.filled().len() > filled {
+            this.clock.touch();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TrackedStream<'_, S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut *this.inner).poll_write(cx, buf);
+        if matches!(result, Poll::Ready(Ok(written)) if written > 0) {
+            this.clock.touch();
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// Run a forwarded connection until it finishes or breaks the idle or lifetime limit
+pub async fn supervise<T>(
+    copy: impl Future<Output = T>,
+    clock: &ActivityClock,
+    limits: &ClientLimits,
+) -> Result<T, ClientExit> {
+    let lifetime = async {
+        match limits.max_lifetime_secs {
+            Some(secs) => sleep(Duration::from_secs(secs)).await,
+            None => pending().await,
+        }
+    };
+    let idle = async {
+        let Some(timeout) = limits.idle_timeout_secs.map(Duration::from_secs) else {
+            return pending().await;
+        };
+        loop {
+            let idle_for = clock.idle_for();
+            if idle_for >= timeout {
+                return;
+            }
+            sleep(timeout - idle_for).await;
+        }
+    };
+
+    tokio::select! {
+        result = copy => Ok(result),
+        _ = lifetime => Err(ClientExit::LifetimeExceeded),
+        _ = idle => Err(ClientExit::IdleTimeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_connections_are_closed_but_busy_ones_are_not() {
+        let limits = ClientLimits {
+            idle_timeout_secs: Some(30),
+            ..Default::default()
+        };
+        let clock = ActivityClock::new();
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let mut tracked = TrackedStream::new(&mut client, &clock);
+
+        // Traffic every 20 seconds keeps the connection open past the idle timeout
+        let busy = async {
+            for _ in 0..3 {
+                sleep(Duration::from_secs(20)).await;
+                tracked.write_all(b"ping").await.unwrap();
+                let mut reply = [0u8; 4];
+                server.read_exact(&mut reply).await.unwrap();
+            }
+        };
+        assert_eq!(supervise(busy, &clock, &limits).await, Ok(()));
+
+        let result = supervise(pending::<()>(), &clock, &limits).await;
+        assert_eq!(result, Err(ClientExit::IdleTimeout));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lifetime_limit_applies_even_with_traffic() {
+        let limits = ClientLimits {
+            max_lifetime_secs: Some(60),
+            idle_timeout_secs: Some(30),
+            ..Default::default()
+        };
+        let clock = ActivityClock::new();
+        let busy = async {
+            loop {
+                clock.touch();
+                sleep(Duration::from_secs(10)).await;
+            }
+        };
+
+        assert_eq!(
+            supervise(busy, &clock, &limits).await,
+            Err::<(), _>(ClientExit::LifetimeExceeded)
+        );
+    }
+}
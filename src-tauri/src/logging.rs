@@ -0,0 +1,50 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::settings::LogLevel;
+
+// Info and below go to stdout, warnings and errors to stderr
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("[{}] {}", record.level(), record.args()),
+            _ => println!("[{}] {}", record.level(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: ConsoleLogger = ConsoleLogger;
+
+pub fn init(level: LogLevel) {
+    if log::set_logger(&LOGGER).is_err() {
+        log::warn!("Logger was already initialized");
+    }
+    set_level(level);
+}
+
+// Called from the settings hook whenever `log_level` changes
+pub fn set_level(level: LogLevel) {
+    log::set_max_level(level.into());
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Debug => LevelFilter::Debug,
+            LogLevel::Info => LevelFilter::Info,
+            LogLevel::Warn => LevelFilter::Warn,
+            LogLevel::Error => LevelFilter::Error,
+        }
+    }
+}
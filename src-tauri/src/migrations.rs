@@ -0,0 +1,167 @@
+use serde_json::{json, Value};
+
+// Bump together with a new entry in MIGRATIONS whenever AppData changes shape
+pub const CURRENT_SCHEMA_VERSION: u32 = 3;
+
+type Migration = fn(&mut Value) -> Result<(), String>;
+
+// MIGRATIONS[n] upgrades a schema version n document to version n + 1
+const MIGRATIONS: [Migration; CURRENT_SCHEMA_VERSION as usize] =
+    [migrate_v0_to_v1, migrate_v1_to_v2, migrate_v2_to_v3];
+
+// Files written before versioning have no marker and are treated as version 0
+pub fn schema_version_of(data: &Value) -> Result<u32, String> {
+    match data.get("schema_version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| format!("Invalid schema_version in data file: {}", version)),
+    }
+}
+
+pub fn migrate(data: &mut Value, from_version: u32) -> Result<(), String> {
+    if from_version > CURRENT_SCHEMA_VERSION {
+        return Err(format!(
+            "Data file uses schema version {}, but this version of Vesper only supports up to {}. \
+             Refusing to load it to avoid losing data; please upgrade Vesper.",
+            from_version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    if !data.is_object() {
+        return Err("Data file does not contain a JSON object".to_string());
+    }
+
+    for version in from_version..CURRENT_SCHEMA_VERSION {
+        MIGRATIONS[version as usize](data)
+            .map_err(|e| format!("Failed to migrate data from v{}: {}", version, e))?;
+        data["schema_version"] = json!(version + 1);
+    }
+
+    Ok(())
+}
+
+// v1 introduces the version marker and saved command snippets
+fn migrate_v0_to_v1(data: &mut Value) -> Result<(), String> {
+    let object = data
+        .as_object_mut()
+        .ok_or("Data file does not contain a JSON object")?;
+
+    for key in ["connections", "tunnels", "snippets"] {
+        object.entry(key).or_insert_with(|| json!({}));
+    }
+
+    Ok(())
+}
+
+// v2 stops persisting runtime connection and tunnel status
+fn migrate_v1_to_v2(data: &mut Value) -> Result<(), String> {
+    strip_runtime_fields(data);
+    Ok(())
+}
+
+// v3 types theme, language and log level; "system" becomes "auto" and unknown values
+// are dropped so they fall back to their defaults
+fn migrate_v2_to_v3(data: &mut Value) -> Result<(), String> {
+    let Some(settings) = data.get_mut("settings").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+
+    if settings.get("theme").and_then(Value::as_str) == Some("system") {
+        settings.insert("theme".to_string(), json!("auto"));
+    }
+
+    let allowed: [(&str, &[&str]); 3] = [
+        ("theme", &["light", "dark", "auto"]),
+        ("language", &["en", "zh"]),
+        ("log_level", &["debug", "info", "warn", "error"]),
+    ];
+    for (key, values) in allowed {
+        let valid = settings
+            .get(key)
+            .and_then(Value::as_str)
+            .is_some_and(|value| values.contains(&value));
+        if !valid {
+            settings.remove(key);
+        }
+    }
+
+    Ok(())
+}
+
+// Remove fields that only describe the running process from a serialized AppData
+pub fn strip_runtime_fields(data: &mut Value) {
+    for key in ["connections", "tunnels"] {
+        if let Some(items) = data.get_mut(key).and_then(Value::as_object_mut) {
+            for item in items.values_mut().filter_map(Value::as_object_mut) {
+                item.remove("status");
+                item.remove("last_probe");
+                item.remove("port_errors");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unversioned_file_is_upgraded_to_current() {
+        let mut data = json!({
+            "connections": {},
+            "tunnels": {},
+            "settings": {}
+        });
+
+        assert_eq!(schema_version_of(&data).unwrap(), 0);
+        migrate(&mut data, 0).unwrap();
+
+        assert_eq!(schema_version_of(&data).unwrap(), CURRENT_SCHEMA_VERSION);
+        assert_eq!(data["snippets"], json!({}));
+    }
+
+    #[test]
+    fn v1_status_fields_are_dropped() {
+        let mut data = json!({
+            "schema_version": 1,
+            "connections": { "a": { "id": "a", "status": "connected" } },
+            "tunnels": { "t": { "id": "t", "status": "active" } }
+        });
+
+        migrate(&mut data, 1).unwrap();
+
+        assert_eq!(data["connections"]["a"], json!({ "id": "a" }));
+        assert_eq!(data["tunnels"]["t"], json!({ "id": "t" }));
+    }
+
+    #[test]
+    fn v2_settings_are_normalized() {
+        let mut data = json!({
+            "schema_version": 2,
+            "settings": { "theme": "system", "language": "en", "log_level": "verbose" }
+        });
+
+        migrate(&mut data, 2).unwrap();
+
+        assert_eq!(
+            data["settings"],
+            json!({ "theme": "auto", "language": "en" })
+        );
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        let mut data = json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1 });
+
+        let error = migrate(&mut data, CURRENT_SCHEMA_VERSION + 1).unwrap_err();
+
+        assert!(error.contains("upgrade Vesper"));
+    }
+
+    #[test]
+    fn invalid_version_marker_is_an_error() {
+        assert!(schema_version_of(&json!({ "schema_version": "two" })).is_err());
+    }
+}
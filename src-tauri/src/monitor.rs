@@ -0,0 +1,215 @@
+use async_ssh2_lite::{AsyncSession, TokioTcpStream};
+use serde::Serialize;
+use std::collections::VecDeque;
+use tokio::io::AsyncReadExt;
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::storage::unix_millis;
+
+// At the default 30 second interval this keeps the last three hours
+pub const METRICS_HISTORY_LEN: usize = 360;
+const METRICS_EXEC_TIMEOUT_SECS: u64 = 10;
+const RTT_COMMAND: &str = "echo vesper";
+// One line of /proc/loadavg, the two /proc/meminfo lines we need, then `df` for the root filesystem
+const RESOURCES_COMMAND: &str = "cat /proc/loadavg; \
+    grep -E '^(MemTotal|MemAvailable):' /proc/meminfo; \
+    df -Pk / | tail -n 1";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSample {
+    pub timestamp: u64,
+    pub rtt_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ServerResources>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ServerResources {
+    pub load_1: f64,
+    pub load_5: f64,
+    pub load_15: f64,
+    pub memory_total: u64, // Bytes
+    pub memory_used: u64,
+    pub disk_total: u64, // Bytes, filesystem mounted at /
+    pub disk_used: u64,
+}
+
+// Fixed-size history per connection; the oldest sample is dropped when full
+pub struct MetricsHistory {
+    samples: VecDeque<MetricSample>,
+    capacity: usize,
+}
+
+impl MetricsHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, sample: MetricSample) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn samples(&self) -> Vec<MetricSample> {
+        self.samples.iter().cloned().collect()
+    }
+}
+
+impl Default for MetricsHistory {
+    fn default() -> Self {
+        Self::new(METRICS_HISTORY_LEN)
+    }
+}
+
+// Time an exec round trip, and optionally read load, memory and disk usage on the same session
+pub async fn collect_sample(
+    session: &AsyncSession<TokioTcpStream>,
+    with_resources: bool,
+) -> MetricSample {
+    let mut sample = MetricSample {
+        timestamp: unix_millis(),
+        rtt_ms: None,
+        resources: None,
+        error: None,
+    };
+
+    let started = Instant::now();
+    match exec_output(session, RTT_COMMAND).await {
+        Ok(_) => sample.rtt_ms = Some(started.elapsed().as_millis() as u64),
+        Err(e) => {
+            sample.error = Some(e);
+            return sample;
+        }
+    }
+
+    if with_resources {
+        match exec_output(session, RESOURCES_COMMAND).await {
+            Ok(output) => match parse_resources(&output) {
+                Some(resources) => sample.resources = Some(resources),
+                None => sample.error = Some("Unrecognized resource output".to_string()),
+            },
+            Err(e) => sample.error = Some(e),
+        }
+    }
+
+    sample
+}
+
+async fn exec_output(
+    session: &AsyncSession<TokioTcpStream>,
+    command: &str,
+) -> Result<String, String> {
+    let exec = async {
+        let mut channel = session
+            .channel_session()
+            .await
+            .map_err(|e| format!("Failed to open exec channel: {}", e))?;
+        channel
+            .exec(command)
+            .await
+            .map_err(|e| format!("Failed to execute command: {}", e))?;
+
+        let mut output = String::new();
+        channel
+            .read_to_string(&mut output)
+            .await
+            .map_err(|e| format!("Failed to read command output: {}", e))?;
+        if let Err(e) = channel.wait_close().await {
+            log::debug!("Failed to close metrics channel: {}", e);
+        }
+        Ok(output)
+    };
+
+    timeout(Duration::from_secs(METRICS_EXEC_TIMEOUT_SECS), exec)
+        .await
+        .map_err(|_| {
+            format!(
+                "Command timed out after {} seconds",
+                METRICS_EXEC_TIMEOUT_SECS
+            )
+        })?
+}
+
+fn parse_resources(output: &str) -> Option<ServerResources> {
+    let mut lines = output.lines();
+    let mut resources = ServerResources::default();
+
+    let mut load = lines.next()?.split_whitespace().map(str::parse::<f64>);
+    resources.load_1 = load.next()?.ok()?;
+    resources.load_5 = load.next()?.ok()?;
+    resources.load_15 = load.next()?.ok()?;
+
+    let mut memory_available = None;
+    for _ in 0..2 {
+        let mut fields = lines.next()?.split_whitespace();
+        let name = fields.next()?;
+        let kilobytes: u64 = fields.next()?.parse().ok()?;
+        match name {
+            "MemTotal:" => resources.memory_total = kilobytes * 1024,
+            "MemAvailable:" => memory_available = Some(kilobytes * 1024),
+            _ => return None,
+        }
+    }
+    resources.memory_used = resources.memory_total.saturating_sub(memory_available?);
+
+    // Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let mut disk = lines.next()?.split_whitespace().skip(1);
+    resources.disk_total = disk.next()?.parse::<u64>().ok()? * 1024;
+    resources.disk_used = disk.next()?.parse::<u64>().ok()? * 1024;
+
+    Some(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: u64) -> MetricSample {
+        MetricSample {
+            timestamp,
+            rtt_ms: Some(10),
+            resources: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn history_keeps_only_the_newest_samples() {
+        let mut history = MetricsHistory::new(3);
+        for timestamp in 0..5 {
+            history.push(sample(timestamp));
+        }
+
+        let timestamps: Vec<u64> = history.samples().iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn parses_linux_resource_output() {
+        let output = "0.52 0.58 0.59 1/389 12345\n\
+            MemTotal:        8000000 kB\n\
+            MemAvailable:    6000000 kB\n\
+            /dev/sda1         41152736 10485760  28551488      27% /\n";
+
+        assert_eq!(
+            parse_resources(output),
+            Some(ServerResources {
+                load_1: 0.52,
+                load_5: 0.58,
+                load_15: 0.59,
+                memory_total: 8_000_000 * 1024,
+                memory_used: 2_000_000 * 1024,
+                disk_total: 41_152_736 * 1024,
+                disk_used: 10_485_760 * 1024,
+            })
+        );
+        assert_eq!(parse_resources("sh: 1: cat: not found\n"), None);
+    }
+}
@@ -0,0 +1,183 @@
+use futures_util::StreamExt;
+use if_watch::tokio::IfWatcher;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{interval, timeout, Duration, Instant};
+
+// How often the wall clock is compared with the monotonic clock
+const SLEEP_CHECK_INTERVAL_SECS: u64 = 5;
+// Wall time gained over monotonic time that counts as a suspend
+const SLEEP_JUMP_THRESHOLD_SECS: u64 = 10;
+// Address changes come in bursts; report once they settle
+const NETWORK_SETTLE_MILLIS: u64 = 1500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkEvent {
+    // The machine woke up after sleeping this long
+    Resumed(Duration),
+    // Interface addresses changed
+    Changed { online: bool },
+}
+
+// Whether any interface has an address that can reach other hosts; assumed online until told otherwise
+#[derive(Clone)]
+pub struct NetworkState(Arc<watch::Sender<bool>>);
+
+impl Default for NetworkState {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(true).0))
+    }
+}
+
+impl NetworkState {
+    pub fn set_online(&self, online: bool) {
+        self.0.send_replace(online);
+    }
+
+    pub fn is_online(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    pub async fn wait_online(&self) {
+        let mut online = self.0.subscribe();
+        let _ = online.wait_for(|online| *online).await;
+    }
+}
+
+// The monotonic clock stops while the machine is suspended, the wall clock doesn't
+pub struct SleepDetector {
+    wall: SystemTime,
+    monotonic: Instant,
+}
+
+impl SleepDetector {
+    pub fn at(wall: SystemTime, monotonic: Instant) -> Self {
+        Self { wall, monotonic }
+    }
+
+    // How long the machine slept since the last check, if long enough to matter
+    pub fn check(&mut self, wall: SystemTime, monotonic: Instant) -> Option<Duration> {
+        let wall_elapsed = wall.duration_since(self.wall).unwrap_or_default();
+        let monotonic_elapsed = monotonic.saturating_duration_since(self.monotonic);
+        self.wall = wall;
+        self.monotonic = monotonic;
+
+        let slept = wall_elapsed.saturating_sub(monotonic_elapsed);
+        (slept >= Duration::from_secs(SLEEP_JUMP_THRESHOLD_SECS)).then_some(slept)
+    }
+}
+
+// Loopback and link-local addresses exist even without a network
+pub fn is_routable(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => !addr.is_loopback() && !addr.is_link_local() && !addr.is_unspecified(),
+        IpAddr::V6(addr) => {
+            !addr.is_loopback() && !addr.is_unspecified() && addr.segments()[0] & 0xffc0 != 0xfe80
+        }
+    }
+}
+
+// Report suspends and address changes (netlink on Linux) until the receiver is dropped
+pub fn spawn_network_watch() -> mpsc::Receiver<NetworkEvent> {
+    let (events_tx, events_rx) = mpsc::channel(8);
+
+    let sleep_tx = events_tx.clone();
+    tokio::spawn(async move {
+        let mut detector = SleepDetector::at(SystemTime::now(), Instant::now());
+        let mut ticks = interval(Duration::from_secs(SLEEP_CHECK_INTERVAL_SECS));
+        loop {
+            ticks.tick().await;
+            if let Some(slept) = detector.check(SystemTime::now(), Instant::now()) {
+                if sleep_tx.send(NetworkEvent::Resumed(slept)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut watcher = match IfWatcher::new() {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                log::warn!("Network changes won't be detected: {}", e);
+                return;
+            }
+        };
+
+        loop {
+            if !matches!(watcher.next().await, Some(Ok(_))) {
+                log::warn!("Network change watcher stopped");
+                return;
+            }
+            while let Ok(event) =
+                timeout(Duration::from_millis(NETWORK_SETTLE_MILLIS), watcher.next()).await
+            {
+                if !matches!(event, Some(Ok(_))) {
+                    log::warn!("Network change watcher stopped");
+                    return;
+                }
+            }
+
+            let online = watcher.iter().any(|net| is_routable(net.addr()));
+            if events_tx
+                .send(NetworkEvent::Changed { online })
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+
+    events_rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wall_clock_jump_is_reported_as_sleep() {
+        let wall = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let monotonic = Instant::now();
+        let mut detector = SleepDetector::at(wall, monotonic);
+
+        let step = Duration::from_secs(SLEEP_CHECK_INTERVAL_SECS);
+        assert_eq!(detector.check(wall + step, monotonic + step), None);
+
+        // Five seconds of monotonic time, an hour of wall time
+        let slept = detector.check(
+            wall + step + Duration::from_secs(3600),
+            monotonic + step * 2,
+        );
+        assert_eq!(slept, Some(Duration::from_secs(3600) - step));
+
+        // The wall clock being set back isn't a sleep
+        assert_eq!(detector.check(wall, monotonic + step * 3), None);
+    }
+
+    #[tokio::test]
+    async fn waiting_for_the_network_ends_when_it_comes_back() {
+        assert!(!is_routable("127.0.0.1".parse().unwrap()));
+        assert!(!is_routable("fe80::1".parse().unwrap()));
+        assert!(is_routable("192.168.1.20".parse().unwrap()));
+
+        let network = NetworkState::default();
+        network.set_online(false);
+        let waiter = tokio::spawn({
+            let network = network.clone();
+            async move { network.wait_online().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        network.set_online(true);
+        timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(network.is_online());
+    }
+}
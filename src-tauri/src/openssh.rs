@@ -0,0 +1,365 @@
+use serde::{Deserialize, Serialize};
+
+use crate::ports::{port_mappings, PortMapping};
+use crate::ssh::{AuthMethod, SSHConnection, SSHTunnel, TunnelType};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenSshExport {
+    pub host_alias: String,
+    pub command: String,
+    pub config: String,
+    pub unsupported: Vec<String>,
+}
+
+// Render a connection and its tunnels as an equivalent `ssh` invocation and `~/.ssh/config` block
+pub fn export_openssh(connection: &SSHConnection, tunnels: &[SSHTunnel]) -> OpenSshExport {
+    let host_alias = host_alias(connection);
+    let mut unsupported = Vec::new();
+    let mut args: Vec<String> = vec!["ssh".to_string()];
+    let mut config = vec![format!("Host {}", host_alias)];
+
+    config.push(format!("    HostName {}", config_value(&connection.host)));
+    config.push(format!("    User {}", config_value(&connection.username)));
+    if connection.port != 22 {
+        args.push("-p".to_string());
+        args.push(connection.port.to_string());
+    }
+    config.push(format!("    Port {}", connection.port));
+
+    match connection.auth_method {
+        AuthMethod::Key => {
+            if let Some(key_path) = &connection.key_path {
+                // ssh expands %-tokens in identity paths, on the command line as well
+                let key_path = key_path.replace('%', "%%");
+                args.push("-i".to_string());
+                args.push(key_path.clone());
+                args.push("-o".to_string());
+                args.push("IdentitiesOnly=yes".to_string());
+                config.push(format!("    IdentityFile {}", config_value(&key_path)));
+                config.push("    IdentitiesOnly yes".to_string());
+            }
+        }
+        AuthMethod::Password => {
+            args.push("-o".to_string());
+            args.push("PreferredAuthentications=password,keyboard-interactive".to_string());
+            config.push("    PreferredAuthentications password,keyboard-interactive".to_string());
+            if connection.password.is_some() {
+                unsupported.push(
+                    "The saved password cannot be passed to OpenSSH; it will be prompted for"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    for option in KEEPALIVE_OPTIONS {
+        args.push("-o".to_string());
+        args.push(format!("{}={}", option.0, option.1));
+        config.push(format!("    {} {}", option.0, option.1));
+    }
+
+    if connection.session_pool_size > 1 {
+        unsupported.push(format!(
+            "Forwarded connections are spread over {} SSH sessions in Vesper; OpenSSH uses one",
+            connection.session_pool_size
+        ));
+    }
+    if connection.bandwidth.is_some() {
+        unsupported.push(
+            "The connection's bandwidth limit only applies in Vesper; OpenSSH forwards at full speed"
+                .to_string(),
+        );
+    }
+
+    let mut tunnels: Vec<&SSHTunnel> = tunnels.iter().collect();
+    tunnels.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+
+    if !tunnels.is_empty() {
+        args.insert(1, "-N".to_string());
+        args.push("-o".to_string());
+        args.push("ExitOnForwardFailure=yes".to_string());
+        config.push("    ExitOnForwardFailure yes".to_string());
+    }
+
+    for tunnel in &tunnels {
+        for mapping in port_mappings(tunnel) {
+            let (flag, keyword, spec) = forward_spec(tunnel, mapping);
+            args.push(flag.to_string());
+            args.push(spec.replace(' ', ":"));
+            config.push(format!("    {} {}", keyword, spec));
+        }
+
+        if tunnel.auto_reconnect {
+            unsupported.push(format!(
+                "Tunnel '{}' reconnects automatically in Vesper; OpenSSH exits instead (consider autossh)",
+                tunnel.name
+            ));
+        }
+        if tunnel.bandwidth.is_some() {
+            unsupported.push(format!(
+                "Tunnel '{}' is bandwidth-limited in Vesper; OpenSSH forwards at full speed",
+                tunnel.name
+            ));
+        }
+        if !tunnel.allowed_clients.is_empty() {
+            unsupported.push(format!(
+                "Tunnel '{}' only accepts clients from its allowlist in Vesper; OpenSSH accepts any client that reaches the port",
+                tunnel.name
+            ));
+        }
+        if tunnel.client_limits.is_some() {
+            unsupported.push(format!(
+                "Tunnel '{}' limits and times out its clients in Vesper; OpenSSH doesn't",
+                tunnel.name
+            ));
+        }
+        if tunnel.on_demand.is_some() {
+            unsupported.push(format!(
+                "Tunnel '{}' connects on demand in Vesper; OpenSSH keeps the session open the whole time",
+                tunnel.name
+            ));
+        }
+        if tunnel.schedule.is_some() {
+            unsupported.push(format!(
+                "Tunnel '{}' runs on a schedule in Vesper; OpenSSH keeps it up the whole time",
+                tunnel.name
+            ));
+        }
+    }
+
+    // A username or host starting with '-' would otherwise be read as an option
+    args.push("--".to_string());
+    args.push(format!(
+        "{}@{}",
+        connection.username,
+        connection
+            .host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+    ));
+
+    if connection.username.contains('"') || connection.host.contains('"') {
+        unsupported
+            .push("Values containing double quotes cannot be written to ssh_config".to_string());
+    }
+
+    OpenSshExport {
+        host_alias,
+        command: args
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" "),
+        config: config.join("\n") + "\n",
+        unsupported,
+    }
+}
+
+const KEEPALIVE_OPTIONS: [(&str, &str); 2] =
+    [("ServerAliveInterval", "30"), ("ServerAliveCountMax", "3")];
+
+// Local forwards bind every interface and remote forwards target loopback, matching the runtime
+fn forward_spec(tunnel: &SSHTunnel, mapping: PortMapping) -> (&'static str, &'static str, String) {
+    match tunnel.tunnel_type {
+        TunnelType::Local => (
+            "-L",
+            "LocalForward",
+            format!(
+                "0.0.0.0:{} {}:{}",
+                mapping.local_port,
+                bracket_ipv6(&tunnel.remote_host),
+                mapping.remote_port
+            ),
+        ),
+        TunnelType::Remote => (
+            "-R",
+            "RemoteForward",
+            format!("{} 127.0.0.1:{}", mapping.remote_port, mapping.local_port),
+        ),
+    }
+}
+
+fn bracket_ipv6(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+fn host_alias(connection: &SSHConnection) -> String {
+    let mut alias = String::new();
+    for c in connection.name.trim().chars() {
+        if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+            alias.push(c.to_ascii_lowercase());
+        } else if !alias.ends_with('-') {
+            alias.push('-');
+        }
+    }
+
+    let alias = alias.trim_matches('-');
+    if alias.is_empty() {
+        format!(
+            "vesper-{}",
+            connection.id.chars().take(8).collect::<String>()
+        )
+    } else {
+        alias.to_string()
+    }
+}
+
+// ssh_config splits on whitespace unless the value is double-quoted
+fn config_value(value: &str) -> String {
+    if value.chars().any(char::is_whitespace) {
+        format!("\"{}\"", value)
+    } else {
+        value.to_string()
+    }
+}
+
+// Quote an argument for POSIX shells
+fn shell_quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_[]".contains(c));
+
+    if is_safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::BandwidthLimit;
+    use crate::limits::ClientLimits;
+    use crate::ssh::OnDemand;
+
+    fn key_connection() -> SSHConnection {
+        SSHConnection {
+            name: "Prod DB (eu)".to_string(),
+            host: "db.example.com".to_string(),
+            port: 2222,
+            username: "deploy".to_string(),
+            auth_method: AuthMethod::Key,
+            password: None,
+            key_path: Some("/home/me/My Keys/id_ed25519".to_string()),
+            ..SSHConnection::fixture("0123456789")
+        }
+    }
+
+    fn tunnel(name: &str, tunnel_type: TunnelType, local_port: u16, remote_port: u16) -> SSHTunnel {
+        SSHTunnel {
+            name: name.to_string(),
+            tunnel_type,
+            local_port,
+            remote_host: "10.0.0.5".to_string(),
+            remote_port,
+            ..SSHTunnel::fixture(name, "0123456789")
+        }
+    }
+
+    #[test]
+    fn command_includes_port_identity_and_forwards() {
+        let export = export_openssh(
+            &key_connection(),
+            &[
+                tunnel("postgres", TunnelType::Local, 15432, 5432),
+                tunnel("webhook", TunnelType::Remote, 3000, 8080),
+            ],
+        );
+
+        assert_eq!(
+            export.command,
+            "ssh -N -p 2222 -i '/home/me/My Keys/id_ed25519' -o IdentitiesOnly=yes \
+             -o ServerAliveInterval=30 -o ServerAliveCountMax=3 -o ExitOnForwardFailure=yes \
+             -L 0.0.0.0:15432:10.0.0.5:5432 -R 8080:127.0.0.1:3000 -- deploy@db.example.com"
+        );
+        assert!(export.unsupported.is_empty());
+    }
+
+    #[test]
+    fn config_block_quotes_values_with_spaces() {
+        let export = export_openssh(
+            &key_connection(),
+            &[tunnel("postgres", TunnelType::Local, 15432, 5432)],
+        );
+
+        assert_eq!(export.host_alias, "prod-db-eu");
+        assert!(export
+            .config
+            .starts_with("Host prod-db-eu\n    HostName db.example.com\n"));
+        assert!(export
+            .config
+            .contains("    IdentityFile \"/home/me/My Keys/id_ed25519\"\n"));
+        assert!(export
+            .config
+            .contains("    LocalForward 0.0.0.0:15432 10.0.0.5:5432\n"));
+    }
+
+    #[test]
+    fn vesper_only_options_are_reported() {
+        let mut connection = key_connection();
+        connection.auth_method = AuthMethod::Password;
+        connection.password = Some("secret".to_string());
+        let mut auto = tunnel("auto", TunnelType::Local, 8080, 80);
+        auto.auto_reconnect = true;
+
+        let export = export_openssh(&connection, &[auto]);
+
+        assert_eq!(export.unsupported.len(), 2);
+        assert!(!export.command.contains("secret"));
+    }
+
+    #[test]
+    fn runtime_only_features_are_reported() {
+        let mut connection = key_connection();
+        connection.session_pool_size = 4;
+        connection.bandwidth = Some(BandwidthLimit::default());
+        let mut limited = tunnel("limited", TunnelType::Local, 8080, 80);
+        limited.bandwidth = Some(BandwidthLimit::default());
+        limited.allowed_clients = vec!["10.0.0.0/8".to_string()];
+        limited.client_limits = Some(ClientLimits::default());
+        limited.on_demand = Some(OnDemand {
+            idle_timeout_secs: 300,
+        });
+
+        let export = export_openssh(&connection, &[limited]);
+
+        assert_eq!(
+            export.unsupported,
+            vec![
+                "Forwarded connections are spread over 4 SSH sessions in Vesper; OpenSSH uses one",
+                "The connection's bandwidth limit only applies in Vesper; OpenSSH forwards at full speed",
+                "Tunnel 'limited' is bandwidth-limited in Vesper; OpenSSH forwards at full speed",
+                "Tunnel 'limited' only accepts clients from its allowlist in Vesper; OpenSSH accepts any client that reaches the port",
+                "Tunnel 'limited' limits and times out its clients in Vesper; OpenSSH doesn't",
+                "Tunnel 'limited' connects on demand in Vesper; OpenSSH keeps the session open the whole time",
+            ]
+        );
+    }
+
+    #[test]
+    fn option_like_usernames_and_percent_paths_stay_literal() {
+        let mut connection = key_connection();
+        connection.username = "-oProxyCommand=touch /tmp/x".to_string();
+        connection.key_path = Some("/keys/100%/id".to_string());
+
+        let export = export_openssh(&connection, &[]);
+
+        assert!(export
+            .command
+            .ends_with(" -- '-oProxyCommand=touch /tmp/x@db.example.com'"));
+        assert!(export.command.contains("-i /keys/100%%/id "));
+        assert!(export.config.contains("    IdentityFile /keys/100%%/id\n"));
+    }
+
+    #[test]
+    fn shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+}
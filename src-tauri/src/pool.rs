@@ -0,0 +1,305 @@
+use async_ssh2_lite::{AsyncSession, TokioTcpStream};
+use futures_util::future::join_all;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use tokio::time::{interval, sleep, Duration};
+
+use crate::ssh::{establish_ssh_session, verify_session, SSHConnection};
+
+pub const MAX_SESSION_POOL_SIZE: u8 = 8;
+const POOL_CHECK_INTERVAL_SECS: u64 = 30;
+const POOL_REPLACE_DELAY_SECS: u64 = 5;
+
+type Session = Arc<AsyncSession<TokioTcpStream>>;
+
+// Extra SSH sessions of one connection that forwarded channels are spread over.
+// Member 0 is the connection's primary session, which ConnectionManager owns and monitors
+pub struct SessionPool {
+    connection: SSHConnection,
+    members: Vec<Arc<PoolMember>>,
+    closed: AtomicBool,
+}
+
+struct PoolMember {
+    index: usize,
+    session: Mutex<Option<Session>>, // None while the session is being (re)opened
+    channels: AtomicUsize,
+    replacing: AtomicBool,
+}
+
+// A forwarded channel's claim on a pool member, released when dropped
+pub struct PoolLease {
+    member: Arc<PoolMember>,
+    session: Session,
+}
+
+impl PoolMember {
+    fn session(&self) -> Option<Session> {
+        self.session.lock().unwrap().clone()
+    }
+}
+
+impl PoolLease {
+    pub fn session(&self) -> Session {
+        self.session.clone()
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.member.index == 0
+    }
+}
+
+impl Drop for PoolLease {
+    fn drop(&mut self) {
+        self.member.channels.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl SessionPool {
+    // Opens the extra sessions in the background so connecting isn't slowed down
+    pub fn start(connection: SSHConnection, primary: Session, size: u8) -> Arc<Self> {
+        let size = size.clamp(1, MAX_SESSION_POOL_SIZE) as usize;
+        let members = (0..size)
+            .map(|index| {
+                Arc::new(PoolMember {
+                    index,
+                    session: Mutex::new((index == 0).then(|| primary.clone())),
+                    channels: AtomicUsize::new(0),
+                    replacing: AtomicBool::new(false),
+                })
+            })
+            .collect();
+
+        let pool = Arc::new(Self {
+            connection,
+            members,
+            closed: AtomicBool::new(false),
+        });
+        for member in &pool.members[1..] {
+            pool.replace(member.clone());
+        }
+        tokio::spawn(check_members_periodically(Arc::downgrade(&pool)));
+
+        pool
+    }
+
+    // The open session with the fewest forwarded channels
+    pub fn acquire(&self) -> PoolLease {
+        let (member, session) = self
+            .members
+            .iter()
+            .filter_map(|member| member.session().map(|session| (member, session)))
+            .min_by_key(|(member, _)| member.channels.load(Ordering::SeqCst))
+            .expect("the primary session is always in the pool");
+
+        member.channels.fetch_add(1, Ordering::SeqCst);
+        PoolLease {
+            member: member.clone(),
+            session,
+        }
+    }
+
+    // A channel failed on this lease's session; replace the session if it is dead
+    pub fn check(self: &Arc<Self>, lease: &PoolLease) {
+        let pool = self.clone();
+        let member = lease.member.clone();
+        tokio::spawn(async move { pool.check_member(&member).await });
+    }
+
+    async fn check_member(self: &Arc<Self>, member: &Arc<PoolMember>) {
+        // 主会话的故障由 ConnectionManager 处理
+        if member.index == 0 {
+            return;
+        }
+        let Some(session) = member.session() else {
+            return;
+        };
+
+        if let Err(e) = session.keepalive_send().await {
+            log::warn!(
+                "Pooled SSH session {} of connection {} is dead: {}",
+                member.index,
+                self.connection.id,
+                e
+            );
+            self.replace(member.clone());
+        }
+    }
+
+    // After a wake-up or network change, replace every extra session that no longer answers
+    pub async fn verify(self: &Arc<Self>) {
+        let checks = self.members[1..].iter().map(|member| async move {
+            let session = member.session()?;
+            verify_session(&session)
+                .await
+                .err()
+                .map(|error| (member, error))
+        });
+
+        for (member, error) in join_all(checks).await.into_iter().flatten() {
+            log::warn!(
+                "Pooled SSH session {} of connection {} did not survive a network change: {}",
+                member.index,
+                self.connection.id,
+                error
+            );
+            self.replace(member.clone());
+        }
+    }
+
+    // Drop a member's session and reopen it, retrying until it succeeds or the pool closes
+    fn replace(self: &Arc<Self>, member: Arc<PoolMember>) {
+        if member.replacing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let old_session = member.session.lock().unwrap().take();
+        let pool = Arc::downgrade(self);
+        let connection = self.connection.clone();
+        tokio::spawn(async move {
+            if let Some(session) = old_session {
+                let _ = session
+                    .disconnect(None, "Replacing pooled SSH session", None)
+                    .await;
+            }
+
+            loop {
+                let result = establish_ssh_session(&connection).await;
+                let open = pool
+                    .upgrade()
+                    .is_some_and(|pool| !pool.closed.load(Ordering::SeqCst));
+
+                match result {
+                    Ok(session) if open => {
+                        *member.session.lock().unwrap() = Some(Arc::new(session));
+                        member.replacing.store(false, Ordering::SeqCst);
+                        log::debug!(
+                            "Opened pooled SSH session {} for connection {}",
+                            member.index,
+                            connection.id
+                        );
+                        return;
+                    }
+                    Ok(session) => {
+                        let _ = session.disconnect(None, "Session pool closed", None).await;
+                        return;
+                    }
+                    Err(_) if !open => return,
+                    Err(e) => {
+                        log::warn!(
+                            "Failed to open pooled SSH session for connection {}: {}",
+                            connection.id,
+                            e.full_message()
+                        );
+                        sleep(Duration::from_secs(POOL_REPLACE_DELAY_SECS)).await;
+                    }
+                }
+            }
+        });
+    }
+
+    // Disconnect the extra sessions; the primary one is left to ConnectionManager
+    pub async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        for member in &self.members[1..] {
+            let session = member.session.lock().unwrap().take();
+            if let Some(session) = session {
+                let _ = session.disconnect(None, "Session pool closed", None).await;
+            }
+        }
+    }
+}
+
+// Keepalive every extra session so dead ones are replaced before a channel needs them
+async fn check_members_periodically(pool: Weak<SessionPool>) {
+    let mut ticker = interval(Duration::from_secs(POOL_CHECK_INTERVAL_SECS));
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        if pool.closed.load(Ordering::SeqCst) {
+            return;
+        }
+        for member in &pool.members[1..] {
+            pool.check_member(member).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::ConnectionStatus;
+    use tokio::net::{TcpListener, TcpStream};
+
+    // An unauthenticated session over a loopback socket, enough to hand out leases
+    async fn loopback_session(listener: &TcpListener) -> Session {
+        let addr = listener.local_addr().unwrap();
+        let (stream, _) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        Arc::new(AsyncSession::new(stream.unwrap(), None).unwrap())
+    }
+
+    async fn test_pool(size: usize) -> SessionPool {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let mut members = Vec::new();
+        for index in 0..size {
+            members.push(Arc::new(PoolMember {
+                index,
+                session: Mutex::new(Some(loopback_session(&listener).await)),
+                channels: AtomicUsize::new(0),
+                replacing: AtomicBool::new(false),
+            }));
+        }
+
+        SessionPool {
+            connection: SSHConnection {
+                name: "Pool".to_string(),
+                password: None,
+                status: ConnectionStatus::Connected,
+                session_pool_size: size as u8,
+                ..SSHConnection::fixture("conn-pool")
+            },
+            members,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    #[tokio::test]
+    async fn channels_go_to_the_least_loaded_session() {
+        let pool = test_pool(3).await;
+
+        let mut leases: Vec<PoolLease> = (0..3).map(|_| pool.acquire()).collect();
+        let mut indexes: Vec<usize> = leases.iter().map(|lease| lease.member.index).collect();
+        indexes.sort();
+        assert_eq!(indexes, vec![0, 1, 2]);
+
+        // Closing a channel makes its session the next choice
+        let released = leases.remove(1).member.index;
+        assert_eq!(pool.acquire().member.index, released);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sessions_that_stop_answering_are_replaced() {
+        let pool = Arc::new(test_pool(2).await);
+
+        pool.verify().await;
+
+        assert!(pool.members[0].session().is_some());
+        assert!(pool.members[1].replacing.load(Ordering::SeqCst));
+        assert!(pool.members[1].session().is_none());
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn sessions_being_replaced_are_skipped() {
+        let pool = test_pool(2).await;
+        pool.members[1].session.lock().unwrap().take();
+
+        let leases: Vec<PoolLease> = (0..3).map(|_| pool.acquire()).collect();
+        assert!(leases.iter().all(PoolLease::is_primary));
+        assert_eq!(pool.members[0].channels.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::task::Poll;
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::ssh::{SSHTunnel, TunnelType};
+
+pub const MAX_PORT_MAPPINGS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    pub local_port: u16,
+    pub remote_port: u16,
+}
+
+// Why one mapping of a tunnel failed to start
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortError {
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub error: String,
+}
+
+impl PortError {
+    pub fn new(mapping: PortMapping, error: String) -> Self {
+        Self {
+            local_port: mapping.local_port,
+            remote_port: mapping.remote_port,
+            error,
+        }
+    }
+}
+
+// A bound local port and the remote port its clients are forwarded to
+pub struct LocalPort {
+    pub mapping: PortMapping,
+    pub listener: TcpListener,
+}
+
+// Every mapping of a tunnel: the range starting at its local and remote port, then the extra ones
+pub fn port_mappings(tunnel: &SSHTunnel) -> Vec<PortMapping> {
+    (0..tunnel.port_count.unwrap_or(1))
+        .map_while(|offset| {
+            Some(PortMapping {
+                local_port: tunnel.local_port.checked_add(offset)?,
+                remote_port: tunnel.remote_port.checked_add(offset)?,
+            })
+        })
+        .chain(tunnel.extra_ports.iter().copied())
+        .collect()
+}
+
+pub fn validate_port_mappings(tunnel: &SSHTunnel) -> Result<(), String> {
+    let count = tunnel.port_count.unwrap_or(1);
+    if count == 0 {
+        return Err("A port range must contain at least one port".to_string());
+    }
+    let last_offset = count - 1;
+    if tunnel.local_port.checked_add(last_offset).is_none()
+        || tunnel.remote_port.checked_add(last_offset).is_none()
+    {
+        return Err("Port range goes past port 65535".to_string());
+    }
+
+    let mappings = port_mappings(tunnel);
+    if mappings.len() > MAX_PORT_MAPPINGS {
+        return Err(format!(
+            "A tunnel can forward at most {} ports",
+            MAX_PORT_MAPPINGS
+        ));
+    }
+
+    // The side we listen on can't bind the same port twice
+    let mut listening = HashSet::new();
+    for mapping in mappings {
+        if mapping.local_port == 0 || mapping.remote_port == 0 {
+            return Err("Ports must be between 1 and 65535".to_string());
+        }
+        let port = match tunnel.tunnel_type {
+            TunnelType::Local => mapping.local_port,
+            TunnelType::Remote => mapping.remote_port,
+        };
+        if !listening.insert(port) {
+            return Err(format!("Port {} is forwarded more than once", port));
+        }
+    }
+    Ok(())
+}
+
+// Wait for a client on any of the ports
+pub async fn accept_any(ports: &[LocalPort]) -> (PortMapping, io::Result<(TcpStream, SocketAddr)>) {
+    poll_fn(|cx| {
+        for port in ports {
+            if let Poll::Ready(result) = port.listener.poll_accept(cx) {
+                return Poll::Ready((port.mapping, result));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tunnel(local_port: u16, remote_port: u16, port_count: Option<u16>) -> SSHTunnel {
+        SSHTunnel {
+            name: "Kafka".to_string(),
+            local_port,
+            remote_host: "10.0.0.5".to_string(),
+            remote_port,
+            port_count,
+            ..SSHTunnel::fixture("tunnel-ports", "conn-ports")
+        }
+    }
+
+    #[test]
+    fn ranges_and_extra_ports_are_expanded() {
+        let mut tunnel = tunnel(19092, 9092, Some(3));
+        tunnel.extra_ports.push(PortMapping {
+            local_port: 12181,
+            remote_port: 2181,
+        });
+
+        let ports: Vec<(u16, u16)> = port_mappings(&tunnel)
+            .into_iter()
+            .map(|mapping| (mapping.local_port, mapping.remote_port))
+            .collect();
+        assert_eq!(
+            ports,
+            vec![(19092, 9092), (19093, 9093), (19094, 9094), (12181, 2181)]
+        );
+        assert!(validate_port_mappings(&tunnel).is_ok());
+
+        tunnel.extra_ports[0].local_port = 19093;
+        assert!(validate_port_mappings(&tunnel).is_err());
+        assert!(validate_port_mappings(&self::tunnel(65534, 80, Some(3))).is_err());
+        assert!(validate_port_mappings(&self::tunnel(8080, 80, Some(0))).is_err());
+        assert!(validate_port_mappings(&self::tunnel(0, 80, None)).is_err());
+    }
+
+    #[tokio::test]
+    async fn clients_are_accepted_on_any_port() {
+        let mut ports = Vec::new();
+        for remote_port in [9092, 9093] {
+            ports.push(LocalPort {
+                mapping: PortMapping {
+                    local_port: 0,
+                    remote_port,
+                },
+                listener: TcpListener::bind(("127.0.0.1", 0)).await.unwrap(),
+            });
+        }
+
+        let addr = ports[1].listener.local_addr().unwrap();
+        let (client, (mapping, accepted)) =
+            tokio::join!(TcpStream::connect(addr), accept_any(&ports));
+        client.unwrap();
+        accepted.unwrap();
+        assert_eq!(mapping.remote_port, 9093);
+    }
+}
@@ -0,0 +1,361 @@
+use async_ssh2_lite::{AsyncSession, TokioTcpStream};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::ssh::{SSHTunnel, TunnelType};
+use crate::storage::unix_millis;
+
+const DEFAULT_PROBE_INTERVAL_SECS: u64 = 60;
+const MIN_PROBE_INTERVAL_SECS: u64 = 5;
+const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 10;
+// Responses are only scanned this far for the status line or expected text
+const MAX_PROBE_RESPONSE_BYTES: usize = 64 * 1024;
+
+// Periodic check that the service behind a tunnel answers, not just the SSH session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelProbe {
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub check: ProbeCheck,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProbeCheck {
+    // Opening a connection to the target is enough
+    #[default]
+    Connect,
+    // Send a GET request; any 2xx or 3xx passes unless a specific status is expected
+    Http {
+        #[serde(default = "default_http_path")]
+        path: String,
+        #[serde(default)]
+        expected_status: Option<u16>,
+    },
+    // Send a custom payload; when `expect` is set the response must contain it
+    Payload {
+        #[serde(default)]
+        send: String,
+        #[serde(default)]
+        expect: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    pub reachable: bool,
+    pub latency_ms: u64,
+    pub checked_at: u64,
+    pub error: Option<String>,
+}
+
+// Periodic probe of an active tunnel; stops when the tunnel is dropped
+pub struct ProbeTask(JoinHandle<()>);
+
+impl ProbeTask {
+    pub fn new(handle: JoinHandle<()>) -> Self {
+        Self(handle)
+    }
+}
+
+impl Drop for ProbeTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn default_interval_secs() -> u64 {
+    DEFAULT_PROBE_INTERVAL_SECS
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_PROBE_TIMEOUT_SECS
+}
+
+fn default_http_path() -> String {
+    "/".to_string()
+}
+
+impl Default for TunnelProbe {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_PROBE_INTERVAL_SECS,
+            timeout_secs: DEFAULT_PROBE_TIMEOUT_SECS,
+            check: ProbeCheck::default(),
+        }
+    }
+}
+
+impl TunnelProbe {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_secs < MIN_PROBE_INTERVAL_SECS {
+            return Err(format!(
+                "Probes can run at most every {} seconds",
+                MIN_PROBE_INTERVAL_SECS
+            ));
+        }
+        if self.timeout_secs == 0 {
+            return Err("Probe timeout must be at least 1 second".to_string());
+        }
+        match &self.check {
+            ProbeCheck::Http { path, .. } if !path.starts_with('/') => {
+                Err("HTTP probe path must start with '/'".to_string())
+            }
+            ProbeCheck::Http {
+                expected_status: Some(status),
+                ..
+            } if !(100..=599).contains(status) => {
+                Err(format!("{} is not an HTTP status code", status))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(MIN_PROBE_INTERVAL_SECS))
+    }
+}
+
+// Reach the tunnel target without going through the local listener and run the check
+pub async fn probe_target(
+    session: &AsyncSession<TokioTcpStream>,
+    tunnel: &SSHTunnel,
+    probe: &TunnelProbe,
+) -> ProbeResult {
+    let started = Instant::now();
+    let timeout_secs = probe.timeout_secs.max(1);
+    let outcome = match timeout(
+        Duration::from_secs(timeout_secs),
+        check_target(session, tunnel, &probe.check),
+    )
+    .await
+    {
+        Ok(outcome) => outcome,
+        Err(_) => Err(format!("Probe timed out after {} seconds", timeout_secs)),
+    };
+
+    ProbeResult {
+        reachable: outcome.is_ok(),
+        latency_ms: started.elapsed().as_millis() as u64,
+        checked_at: unix_millis(),
+        error: outcome.err(),
+    }
+}
+
+async fn check_target(
+    session: &AsyncSession<TokioTcpStream>,
+    tunnel: &SSHTunnel,
+    check: &ProbeCheck,
+) -> Result<(), String> {
+    match tunnel.tunnel_type {
+        TunnelType::Local => {
+            let channel = session
+                .channel_direct_tcpip(&tunnel.remote_host, tunnel.remote_port, None)
+                .await
+                .map_err(|e| {
+                    format!(
+                        "Failed to reach {}:{} through SSH: {}",
+                        tunnel.remote_host, tunnel.remote_port, e
+                    )
+                })?;
+            run_check(channel, &tunnel.remote_host, check).await
+        }
+        // 远程转发的目标是本机上的服务
+        TunnelType::Remote => {
+            let stream = TcpStream::connect(("127.0.0.1", tunnel.local_port))
+                .await
+                .map_err(|e| format!("Failed to reach 127.0.0.1:{}: {}", tunnel.local_port, e))?;
+            run_check(stream, "127.0.0.1", check).await
+        }
+    }
+}
+
+async fn run_check<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    host: &str,
+    check: &ProbeCheck,
+) -> Result<(), String> {
+    let result = match check {
+        ProbeCheck::Connect => Ok(()),
+        ProbeCheck::Http {
+            path,
+            expected_status,
+        } => {
+            let request = format!(
+                "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: vesper-probe\r\nConnection: close\r\n\r\n",
+                path, host
+            );
+            send(&mut stream, request.as_bytes()).await?;
+            let response = read_until(&mut stream, |data| find(data, b"\r\n").is_some()).await?;
+            check_http_status(&response, *expected_status)
+        }
+        ProbeCheck::Payload {
+            send: payload,
+            expect,
+        } => {
+            if !payload.is_empty() {
+                send(&mut stream, payload.as_bytes()).await?;
+            }
+            match expect {
+                None => Ok(()),
+                Some(expect) => {
+                    let expect = expect.as_bytes();
+                    let response =
+                        read_until(&mut stream, |data| find(data, expect).is_some()).await?;
+                    if find(&response, expect).is_some() {
+                        Ok(())
+                    } else {
+                        Err("Response did not contain the expected text".to_string())
+                    }
+                }
+            }
+        }
+    };
+
+    let _ = stream.shutdown().await;
+    result
+}
+
+async fn send<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8]) -> Result<(), String> {
+    stream
+        .write_all(data)
+        .await
+        .map_err(|e| format!("Failed to send probe: {}", e))?;
+    stream
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to send probe: {}", e))
+}
+
+async fn read_until<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    done: impl Fn(&[u8]) -> bool,
+) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut buffer = [0u8; 4096];
+
+    while !done(&data) && data.len() < MAX_PROBE_RESPONSE_BYTES {
+        let read = stream
+            .read(&mut buffer)
+            .await
+            .map_err(|e| format!("Failed to read probe response: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buffer[..read]);
+    }
+
+    Ok(data)
+}
+
+fn check_http_status(response: &[u8], expected_status: Option<u16>) -> Result<(), String> {
+    let line_end = find(response, b"\r\n").unwrap_or(response.len());
+    let status_line = String::from_utf8_lossy(&response[..line_end]);
+    let mut parts = status_line.split_whitespace();
+
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => code.parse::<u16>().ok(),
+        _ => None,
+    }
+    .ok_or("Target did not answer with an HTTP response")?;
+
+    let accepted = match expected_status {
+        Some(expected) => status == expected,
+        None => (200..400).contains(&status),
+    };
+    if accepted {
+        Ok(())
+    } else {
+        Err(format!("Target answered with HTTP {}", status))
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    async fn check_against(check: ProbeCheck, response: &'static [u8]) -> Result<(), String> {
+        let (client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut request = [0u8; 512];
+            let _ = server.read(&mut request).await;
+            let _ = server.write_all(response).await;
+        });
+
+        run_check(client, "localhost", &check).await
+    }
+
+    #[test]
+    fn invalid_probes_are_rejected() {
+        assert!(TunnelProbe::default().validate().is_ok());
+
+        let probe = |check| TunnelProbe {
+            check,
+            ..TunnelProbe::default()
+        };
+        assert!(probe(ProbeCheck::Http {
+            path: "health".to_string(),
+            expected_status: None,
+        })
+        .validate()
+        .is_err());
+        assert!(probe(ProbeCheck::Http {
+            path: "/health".to_string(),
+            expected_status: Some(999),
+        })
+        .validate()
+        .is_err());
+        assert!(TunnelProbe {
+            interval_secs: 1,
+            ..TunnelProbe::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn http_probe_checks_the_status_code() {
+        let http = || ProbeCheck::Http {
+            path: "/health".to_string(),
+            expected_status: None,
+        };
+
+        assert!(check_against(http(), b"HTTP/1.1 204 No Content\r\n\r\n")
+            .await
+            .is_ok());
+        assert_eq!(
+            check_against(http(), b"HTTP/1.1 503 Service Unavailable\r\n\r\n").await,
+            Err("Target answered with HTTP 503".to_string())
+        );
+        assert!(check_against(http(), b"SSH-2.0-OpenSSH_9.6\r\n")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn payload_probe_looks_for_expected_text() {
+        let payload = |expect: &str| ProbeCheck::Payload {
+            send: "PING\r\n".to_string(),
+            expect: Some(expect.to_string()),
+        };
+
+        assert!(check_against(payload("PONG"), b"+PONG\r\n").await.is_ok());
+        assert!(check_against(payload("PONG"), b"-ERR\r\n").await.is_err());
+    }
+}
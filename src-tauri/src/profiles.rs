@@ -0,0 +1,238 @@
+use crate::error::{VesperError, VesperResult};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+const DATA_DIR_FLAG: &str = "--data-dir";
+const DATA_DIR_ENV: &str = "VESPER_DATA_DIR";
+const PROFILE_FLAG: &str = "--profile";
+const PROFILE_ENV: &str = "VESPER_PROFILE";
+// Placed next to the executable to keep all data in a `data` folder beside it
+const PORTABLE_MARKER: &str = "vesper.portable";
+const ACTIVE_PROFILE_FILE: &str = "active_profile";
+const MAX_PROFILE_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub path: String,
+    pub active: bool,
+}
+
+// Where data lives: a base directory holding the default profile and any named ones
+pub struct Profiles {
+    base_dir: PathBuf,
+    portable: bool,
+    active: Mutex<String>,
+}
+
+impl Profiles {
+    // Resolve the base directory and starting profile from flags, environment and marker file
+    pub fn resolve() -> VesperResult<Self> {
+        let args: Vec<String> = std::env::args().collect();
+
+        let (base_dir, portable) =
+            match flag_value(&args, DATA_DIR_FLAG).or_else(|| env_value(DATA_DIR_ENV)) {
+                Some(dir) => (PathBuf::from(dir), false),
+                None => match portable_data_dir() {
+                    Some(dir) => (dir, true),
+                    None => (
+                        dirs::data_dir()
+                            .ok_or_else(|| {
+                                VesperError::Storage("Failed to get data directory".to_string())
+                            })?
+                            .join("vesper"),
+                        false,
+                    ),
+                },
+            };
+
+        let active = flag_value(&args, PROFILE_FLAG)
+            .or_else(|| env_value(PROFILE_ENV))
+            .or_else(|| read_active_profile(&base_dir))
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        validate_profile_name(&active)?;
+
+        Ok(Self::new(base_dir, portable, active))
+    }
+
+    pub fn new(base_dir: PathBuf, portable: bool, active: String) -> Self {
+        Self {
+            base_dir,
+            portable,
+            active: Mutex::new(active),
+        }
+    }
+
+    pub fn is_portable(&self) -> bool {
+        self.portable
+    }
+
+    pub fn active(&self) -> String {
+        self.active.lock().unwrap().clone()
+    }
+
+    pub fn active_dir(&self) -> PathBuf {
+        self.profile_dir(&self.active())
+    }
+
+    // The default profile uses the base directory so existing data keeps working
+    pub fn profile_dir(&self, name: &str) -> PathBuf {
+        if name == DEFAULT_PROFILE {
+            self.base_dir.clone()
+        } else {
+            self.base_dir.join("profiles").join(name)
+        }
+    }
+
+    pub fn list(&self) -> Vec<ProfileInfo> {
+        let mut names = vec![DEFAULT_PROFILE.to_string()];
+        if let Ok(entries) = fs::read_dir(self.base_dir.join("profiles")) {
+            let mut named: Vec<String> = entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| validate_profile_name(name).is_ok() && name != DEFAULT_PROFILE)
+                .collect();
+            named.sort();
+            names.extend(named);
+        }
+
+        let active = self.active();
+        if !names.contains(&active) {
+            names.push(active);
+        }
+
+        names.into_iter().map(|name| self.info(&name)).collect()
+    }
+
+    pub fn info(&self, name: &str) -> ProfileInfo {
+        ProfileInfo {
+            name: name.to_string(),
+            path: self.profile_dir(name).to_string_lossy().into_owned(),
+            active: name == self.active(),
+        }
+    }
+
+    // Remember the profile for the next start as well
+    pub fn set_active(&self, name: &str) -> VesperResult<()> {
+        validate_profile_name(name)?;
+        fs::create_dir_all(&self.base_dir)
+            .map_err(VesperError::storage_io("create data directory"))?;
+        fs::write(self.base_dir.join(ACTIVE_PROFILE_FILE), name)
+            .map_err(VesperError::storage_io("save active profile"))?;
+
+        *self.active.lock().unwrap() = name.to_string();
+        Ok(())
+    }
+}
+
+pub fn validate_profile_name(name: &str) -> VesperResult<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_PROFILE_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(VesperError::InvalidInput(format!(
+            "Invalid profile name '{}': use up to {} letters, digits, '-' or '_'",
+            name, MAX_PROFILE_NAME_LEN
+        )))
+    }
+}
+
+// Accepts both `--flag value` and `--flag=value`
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg
+            .strip_prefix(flag)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn portable_data_dir() -> Option<PathBuf> {
+    let exe_dir = std::env::current_exe().ok()?.parent()?.to_path_buf();
+    exe_dir
+        .join(PORTABLE_MARKER)
+        .exists()
+        .then(|| exe_dir.join("data"))
+}
+
+fn read_active_profile(base_dir: &Path) -> Option<String> {
+    let name = fs::read_to_string(base_dir.join(ACTIVE_PROFILE_FILE)).ok()?;
+    let name = name.trim();
+    validate_profile_name(name).ok()?;
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_accept_separate_and_inline_values() {
+        assert_eq!(
+            flag_value(&args(&["vesper", "--data-dir", "/tmp/v"]), DATA_DIR_FLAG),
+            Some("/tmp/v".to_string())
+        );
+        assert_eq!(
+            flag_value(&args(&["vesper", "--profile=work"]), PROFILE_FLAG),
+            Some("work".to_string())
+        );
+        assert_eq!(
+            flag_value(&args(&["vesper", "--profiled"]), PROFILE_FLAG),
+            None
+        );
+    }
+
+    #[test]
+    fn profile_names_cannot_escape_the_data_directory() {
+        assert!(validate_profile_name("work_2").is_ok());
+        assert!(validate_profile_name("../etc").is_err());
+        assert!(validate_profile_name("").is_err());
+    }
+
+    #[test]
+    fn switching_profiles_is_remembered() {
+        let base_dir =
+            std::env::temp_dir().join(format!("vesper-test-{}", crate::ssh::generate_id()));
+        let profiles = Profiles::new(base_dir.clone(), false, DEFAULT_PROFILE.to_string());
+        assert_eq!(profiles.active_dir(), base_dir);
+
+        profiles.set_active("work").unwrap();
+
+        assert_eq!(
+            profiles.active_dir(),
+            base_dir.join("profiles").join("work")
+        );
+        assert_eq!(read_active_profile(&base_dir).as_deref(), Some("work"));
+        let names: Vec<String> = profiles.list().into_iter().map(|p| p.name).collect();
+        assert_eq!(names, vec!["default".to_string(), "work".to_string()]);
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,458 @@
+use chrono::{
+    DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+// Days looked at when searching for the next transition
+const WEEKLY_HORIZON_DAYS: i64 = 8;
+const CRON_HORIZON_DAYS: i64 = 366;
+const MAX_CRON_DURATION_MINUTES: u32 = 7 * 24 * 60;
+
+// When a tunnel should be running
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelSchedule {
+    // IANA name such as "Europe/Berlin"
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(flatten)]
+    pub rule: ScheduleRule,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduleRule {
+    // Up during any of the windows
+    Weekly {
+        windows: Vec<WeeklyWindow>,
+    },
+    // Up for `duration_minutes` from every minute the five-field cron expression matches
+    Cron {
+        expression: String,
+        duration_minutes: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeeklyWindow {
+    pub days: Vec<Weekday>,
+    // "HH:MM" local time; an end at or before the start runs past midnight
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScheduleState {
+    pub active: bool,
+    // None when nothing changes within the horizon
+    pub next_transition: Option<DateTime<Utc>>,
+}
+
+// What the UI shows for a scheduled tunnel
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleStatus {
+    pub in_window: bool,
+    pub next_transition_at: Option<u64>,
+    // A manual start or stop holds until the next transition
+    pub overridden: bool,
+}
+
+type Window = (DateTime<Utc>, DateTime<Utc>);
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+impl TunnelSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        self.time_zone()?;
+        match &self.rule {
+            ScheduleRule::Weekly { windows } => {
+                if windows.is_empty() {
+                    return Err("A weekly schedule needs at least one window".to_string());
+                }
+                for window in windows {
+                    if window.days.is_empty() {
+                        return Err("A schedule window needs at least one day".to_string());
+                    }
+                    parse_time(&window.start)?;
+                    parse_time(&window.end)?;
+                }
+            }
+            ScheduleRule::Cron {
+                expression,
+                duration_minutes,
+            } => {
+                CronSpec::parse(expression)?;
+                if *duration_minutes == 0 || *duration_minutes > MAX_CRON_DURATION_MINUTES {
+                    return Err(format!(
+                        "Window duration must be between 1 and {} minutes",
+                        MAX_CRON_DURATION_MINUTES
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn state_at(&self, now: DateTime<Utc>) -> Result<ScheduleState, String> {
+        let tz = self.time_zone()?;
+        let today = now.with_timezone(&tz).date_naive();
+        let horizon =
+            |days: i64| to_utc(&tz, (today + Duration::days(days)).and_time(NaiveTime::MIN));
+
+        match &self.rule {
+            ScheduleRule::Weekly { windows } => {
+                let mut spans = Vec::new();
+                // Start a day back so a window running past midnight is seen
+                for offset in -1..=WEEKLY_HORIZON_DAYS {
+                    let date = today + Duration::days(offset);
+                    for window in windows.iter().filter(|w| w.days.contains(&date.weekday())) {
+                        let start = parse_time(&window.start)?;
+                        let end = parse_time(&window.end)?;
+                        let end_date = if end <= start {
+                            date + Duration::days(1)
+                        } else {
+                            date
+                        };
+                        spans.push((
+                            to_utc(&tz, date.and_time(start)),
+                            to_utc(&tz, end_date.and_time(end)),
+                        ));
+                    }
+                }
+                spans.sort();
+                Ok(resolve(spans, now, horizon(WEEKLY_HORIZON_DAYS)))
+            }
+            ScheduleRule::Cron {
+                expression,
+                duration_minutes,
+            } => {
+                let spec = CronSpec::parse(expression)?;
+                let duration = Duration::minutes(i64::from(*duration_minutes));
+                let lookback = duration.num_days() + 1;
+                let runs = spec.runs(*duration_minutes);
+                let spans = (-lookback..=CRON_HORIZON_DAYS)
+                    .map(move |offset| today + Duration::days(offset))
+                    .filter(|date| spec.matches_date(*date))
+                    .flat_map(move |date| {
+                        runs.clone().into_iter().map(move |(first, last)| {
+                            (
+                                to_utc(&tz, date.and_time(first)),
+                                to_utc(&tz, date.and_time(last)) + duration,
+                            )
+                        })
+                    });
+                Ok(resolve(spans, now, horizon(CRON_HORIZON_DAYS)))
+            }
+        }
+    }
+
+    fn time_zone(&self) -> Result<Tz, String> {
+        self.timezone
+            .parse()
+            .map_err(|_| format!("Unknown time zone '{}'", self.timezone))
+    }
+}
+
+// Merge windows (sorted by start) until the one around or after `now` is known
+fn resolve(
+    windows: impl IntoIterator<Item = Window>,
+    now: DateTime<Utc>,
+    horizon: DateTime<Utc>,
+) -> ScheduleState {
+    let settled = |(start, end): Window| {
+        if now < start {
+            Some(ScheduleState {
+                active: false,
+                next_transition: Some(start),
+            })
+        } else if now < end {
+            Some(ScheduleState {
+                active: true,
+                next_transition: Some(end),
+            })
+        } else {
+            None
+        }
+    };
+
+    let mut current: Option<Window> = None;
+    for (start, end) in windows {
+        if let Some((_, current_end)) = current.as_mut() {
+            if start <= *current_end {
+                *current_end = (*current_end).max(end);
+                // Nothing past the horizon is known, so dense rules stop here instead of merging every match
+                if *current_end >= horizon {
+                    break;
+                }
+                continue;
+            }
+        }
+        if let Some(state) = current.and_then(settled) {
+            return state;
+        }
+        current = Some((start, end));
+    }
+
+    match current {
+        // Windows past the horizon weren't looked at and may continue this one
+        Some((start, end)) if start <= now && end >= horizon => ScheduleState {
+            active: true,
+            next_transition: None,
+        },
+        _ => current.and_then(settled).unwrap_or(ScheduleState {
+            active: false,
+            next_transition: None,
+        }),
+    }
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| format!("Invalid time '{}', expected HH:MM", value))
+}
+
+// Local times skipped by a DST change move forward to the next valid hour
+fn to_utc(tz: &Tz, local: NaiveDateTime) -> DateTime<Utc> {
+    (0..=2)
+        .find_map(|hours| {
+            tz.from_local_datetime(&(local + Duration::hours(hours)))
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+fn bits(set: u64, min: u32, max: u32) -> impl Iterator<Item = u32> + Clone {
+    (min..=max).filter(move |value| set & (1 << value) != 0)
+}
+
+// minute hour day-of-month month day-of-week, each a bit set
+#[derive(Debug, Clone, Copy)]
+struct CronSpec {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSpec {
+    fn parse(expression: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "Cron expression '{}' must have five fields",
+                expression
+            ));
+        };
+
+        let mut weekdays = parse_cron_field(weekday, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_cron_field(minute, 0, 59)?,
+            hours: parse_cron_field(hour, 0, 23)?,
+            days: parse_cron_field(day, 1, 31)?,
+            months: parse_cron_field(month, 1, 12)?,
+            weekdays,
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    // First and last start of each run of matching minutes whose windows overlap, within one day
+    fn runs(&self, duration_minutes: u32) -> Vec<(NaiveTime, NaiveTime)> {
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        let minutes = bits(self.hours, 0, 23)
+            .flat_map(|hour| bits(self.minutes, 0, 59).map(move |minute| hour * 60 + minute));
+        for minute in minutes {
+            match runs.last_mut() {
+                Some((_, last)) if minute <= *last + duration_minutes => *last = minute,
+                _ => runs.push((minute, minute)),
+            }
+        }
+
+        let time = |minute: u32| {
+            NaiveTime::from_hms_opt(minute / 60, minute % 60, 0).unwrap_or(NaiveTime::MIN)
+        };
+        runs.into_iter()
+            .map(|(first, last)| (time(first), time(last)))
+            .collect()
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+
+        // 和 cron 一样：两者都限定时满足其一即可
+        if self.days_restricted && self.weekdays_restricted {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+// Lists of `*`, `n`, `a-b`, each with an optional `/step`
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("Invalid cron field '{}'", field);
+    let number = |value: &str| value.parse::<u32>().map_err(|_| invalid());
+
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, number(step)?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (number(start)?, number(end)?)
+        } else {
+            let start = number(range)?;
+            (start, if step > 1 { max } else { start })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("'{}' is outside {}-{}", part, min, max));
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn weekly_windows_follow_the_time_zone() {
+        let schedule = TunnelSchedule {
+            timezone: "Europe/Berlin".to_string(),
+            rule: ScheduleRule::Weekly {
+                windows: vec![
+                    WeeklyWindow {
+                        days: vec![
+                            Weekday::Mon,
+                            Weekday::Tue,
+                            Weekday::Wed,
+                            Weekday::Thu,
+                            Weekday::Fri,
+                        ],
+                        start: "09:00".to_string(),
+                        end: "17:00".to_string(),
+                    },
+                    WeeklyWindow {
+                        days: vec![Weekday::Sat],
+                        start: "22:00".to_string(),
+                        end: "02:00".to_string(),
+                    },
+                ],
+            },
+        };
+        assert!(schedule.validate().is_ok());
+
+        // Monday 2026-10-19, Berlin is UTC+2
+        assert_eq!(
+            schedule.state_at(at("2026-10-19T08:00:00Z")).unwrap(),
+            ScheduleState {
+                active: true,
+                next_transition: Some(at("2026-10-19T15:00:00Z")),
+            }
+        );
+        assert_eq!(
+            schedule.state_at(at("2026-10-23T16:00:00Z")).unwrap(),
+            ScheduleState {
+                active: false,
+                next_transition: Some(at("2026-10-24T20:00:00Z")),
+            }
+        );
+        // The Saturday window runs into Sunday; 02:00 happens twice that night and the first one ends it
+        assert_eq!(
+            schedule.state_at(at("2026-10-24T23:30:00Z")).unwrap(),
+            ScheduleState {
+                active: true,
+                next_transition: Some(at("2026-10-25T00:00:00Z")),
+            }
+        );
+    }
+
+    #[test]
+    fn cron_windows_last_for_their_duration() {
+        let schedule = TunnelSchedule {
+            timezone: default_timezone(),
+            rule: ScheduleRule::Cron {
+                expression: "30 1 * * 1-5".to_string(),
+                duration_minutes: 90,
+            },
+        };
+
+        // Tuesday
+        assert_eq!(
+            schedule.state_at(at("2026-10-20T02:00:00Z")).unwrap(),
+            ScheduleState {
+                active: true,
+                next_transition: Some(at("2026-10-20T03:00:00Z")),
+            }
+        );
+        // Friday after the window: the next one is on Monday
+        assert_eq!(
+            schedule.state_at(at("2026-10-23T04:00:00Z")).unwrap(),
+            ScheduleState {
+                active: false,
+                next_transition: Some(at("2026-10-26T01:30:00Z")),
+            }
+        );
+
+        // Every minute merges into one window; the weekday form still ends on Friday night
+        let every_minute = |expression: &str| TunnelSchedule {
+            timezone: default_timezone(),
+            rule: ScheduleRule::Cron {
+                expression: expression.to_string(),
+                duration_minutes: 1,
+            },
+        };
+        assert_eq!(
+            every_minute("* * * * *")
+                .state_at(at("2026-10-20T02:00:00Z"))
+                .unwrap(),
+            ScheduleState {
+                active: true,
+                next_transition: None,
+            }
+        );
+        assert_eq!(
+            every_minute("* * * * 1-5")
+                .state_at(at("2026-10-20T02:00:00Z"))
+                .unwrap(),
+            ScheduleState {
+                active: true,
+                next_transition: Some(at("2026-10-24T00:00:00Z")),
+            }
+        );
+
+        for expression in ["* * * *", "60 * * * *", "*/0 * * * *", "1-x * * * *"] {
+            assert!(CronSpec::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
@@ -1,26 +1,305 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+use std::time::Duration;
+use tauri::{AppHandle, LogicalPosition, LogicalSize, Manager, Window};
+
+use crate::store::Store;
+
+pub const MAIN_WINDOW_LABEL: &str = "main";
+const MIN_WINDOW_WIDTH: u32 = 400;
+const MIN_WINDOW_HEIGHT: u32 = 300;
+const MAX_WINDOW_SIZE: u32 = 16384;
+const MIN_METRICS_INTERVAL_SECS: u64 = 5;
+const MAX_METRICS_INTERVAL_SECS: u64 = 3600;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
-    pub theme: String,     // "light", "dark", "auto"
-    pub language: String,  // "en", "zh", etc.
-    pub auto_start: bool,  // Start with system
-    pub log_level: String, // "debug", "info", "warn", "error"
-    pub default_key_path: Option<String>,
+    pub theme: Theme,
+    pub language: Language,
+    pub auto_start: bool, // Start with system
+    pub log_level: LogLevel,
+    pub default_key_path: Option<String>, // Used by key-auth connections created without a key
     pub window_width: u32,
     pub window_height: u32,
+    pub window_x: Option<i32>, // Last position, saved when the window closes
+    pub window_y: Option<i32>,
+    pub window_maximized: bool,
+    pub restore_last_session: bool, // Reconnect what was active at shutdown
+    pub storage_backend: StorageBackendKind, // Takes effect on next start
+    pub metrics_interval_secs: u64, // Latency sampling period, 0 turns monitoring off
+    pub server_metrics: bool,       // Also sample load, memory and disk usage
+    // Keys this version doesn't know about, e.g. written by a newer Vesper
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    Light,
+    Dark,
+    #[default]
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    Zh,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackendKind {
+    Json,
+    Sqlite,
+}
+
+// Builds with the `sqlite` feature move existing data into SQLite by default
+impl Default for StorageBackendKind {
+    fn default() -> Self {
+        if cfg!(feature = "sqlite") {
+            StorageBackendKind::Sqlite
+        } else {
+            StorageBackendKind::Json
+        }
+    }
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
-            theme: "system".to_string(),
-            language: "en".to_string(),
+            theme: Theme::default(),
+            language: Language::default(),
             auto_start: false,
-            log_level: "info".to_string(),
+            log_level: LogLevel::default(),
             default_key_path: None,
             window_width: 1200,
             window_height: 800,
+            window_x: None,
+            window_y: None,
+            window_maximized: false,
+            restore_last_session: false,
+            storage_backend: StorageBackendKind::default(),
+            metrics_interval_secs: 30,
+            server_metrics: false,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl AppConfig {
+    // Parse settings sent by the frontend, rejecting unknown enum values and bad ranges
+    pub fn from_update(value: Value, previous: &AppConfig) -> Result<Self, String> {
+        let settings: AppConfig =
+            serde_json::from_value(value).map_err(|e| format!("Invalid settings: {}", e))?;
+        settings.validate()?;
+
+        // A key that went missing since it was set shouldn't block saving unrelated settings
+        if let Some(key_path) = settings.default_key_path() {
+            if settings.default_key_path() != previous.default_key_path()
+                && !Path::new(key_path).is_file()
+            {
+                return Err(format!("Default key file does not exist: {}", key_path));
+            }
+        }
+        Ok(settings)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value, min) in [
+            ("width", self.window_width, MIN_WINDOW_WIDTH),
+            ("height", self.window_height, MIN_WINDOW_HEIGHT),
+        ] {
+            if !(min..=MAX_WINDOW_SIZE).contains(&value) {
+                return Err(format!(
+                    "Window {} must be between {} and {}",
+                    name, min, MAX_WINDOW_SIZE
+                ));
+            }
+        }
+
+        if self.metrics_interval_secs != 0
+            && !(MIN_METRICS_INTERVAL_SECS..=MAX_METRICS_INTERVAL_SECS)
+                .contains(&self.metrics_interval_secs)
+        {
+            return Err(format!(
+                "Metrics interval must be 0 or between {} and {} seconds",
+                MIN_METRICS_INTERVAL_SECS, MAX_METRICS_INTERVAL_SECS
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn default_key_path(&self) -> Option<&str> {
+        self.default_key_path
+            .as_deref()
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+    }
+
+    pub fn metrics_interval(&self) -> Option<Duration> {
+        (self.metrics_interval_secs != 0).then(|| {
+            Duration::from_secs(
+                self.metrics_interval_secs
+                    .clamp(MIN_METRICS_INTERVAL_SECS, MAX_METRICS_INTERVAL_SECS),
+            )
+        })
+    }
+
+    // Keep keys from newer versions that the frontend didn't send back
+    pub fn keep_unknown_keys(&mut self, previous: &AppConfig) {
+        for (key, value) in &previous.extra {
+            self.extra
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+    }
+
+    fn geometry(&self) -> (u32, u32, Option<i32>, Option<i32>, bool) {
+        (
+            self.window_width,
+            self.window_height,
+            self.window_x,
+            self.window_y,
+            self.window_maximized,
+        )
+    }
+}
+
+// Settings-change hook: make the running app match `settings`. `previous` is None at startup
+pub fn apply_settings(app: &AppHandle, previous: Option<&AppConfig>, settings: &AppConfig) {
+    crate::logging::set_level(settings.log_level);
+
+    if previous.is_none_or(|previous| previous.geometry() != settings.geometry()) {
+        restore_window_geometry(app, settings);
+    }
+}
+
+fn restore_window_geometry(app: &AppHandle, settings: &AppConfig) {
+    let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) else {
+        return;
+    };
+
+    let width = settings
+        .window_width
+        .clamp(MIN_WINDOW_WIDTH, MAX_WINDOW_SIZE);
+    let height = settings
+        .window_height
+        .clamp(MIN_WINDOW_HEIGHT, MAX_WINDOW_SIZE);
+    if let Err(e) = window.set_size(LogicalSize::new(width as f64, height as f64)) {
+        log::warn!("Failed to restore window size: {}", e);
+    }
+
+    if let (Some(x), Some(y)) = (settings.window_x, settings.window_y) {
+        if let Err(e) = window.set_position(LogicalPosition::new(x as f64, y as f64)) {
+            log::warn!("Failed to restore window position: {}", e);
+        }
+    }
+
+    if settings.window_maximized {
+        if let Err(e) = window.maximize() {
+            log::warn!("Failed to maximize window: {}", e);
         }
     }
 }
+
+// Remember where the main window was when it closes; a maximized window keeps its normal size
+pub fn save_window_geometry(window: &Window, store: &Store) {
+    if window.label() != MAIN_WINDOW_LABEL {
+        return;
+    }
+
+    let geometry = (|| -> tauri::Result<_> {
+        let scale_factor = window.scale_factor()?;
+        let size = window.inner_size()?.to_logical::<f64>(scale_factor);
+        let position = window.outer_position()?.to_logical::<f64>(scale_factor);
+        Ok((size, position, window.is_maximized()?))
+    })();
+
+    let (size, position, maximized) = match geometry {
+        Ok(geometry) => geometry,
+        Err(e) => {
+            log::warn!("Failed to read window geometry: {}", e);
+            return;
+        }
+    };
+
+    let result = store.update(move |data| {
+        let settings = &mut data.settings;
+        settings.window_maximized = maximized;
+        if !maximized {
+            settings.window_width = size.width.round() as u32;
+            settings.window_height = size.height.round() as u32;
+            settings.window_x = Some(position.x.round() as i32);
+            settings.window_y = Some(position.y.round() as i32);
+        }
+    });
+    if let Err(e) = result {
+        log::error!("Failed to save window geometry: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unknown_keys_survive_a_round_trip() {
+        let settings: AppConfig = serde_json::from_value(json!({
+            "theme": "dark",
+            "sync_provider": { "kind": "webdav" }
+        }))
+        .unwrap();
+
+        assert_eq!(settings.theme, Theme::Dark);
+        assert_eq!(settings.language, Language::En);
+        let value = serde_json::to_value(&settings).unwrap();
+        assert_eq!(value["sync_provider"], json!({ "kind": "webdav" }));
+    }
+
+    #[test]
+    fn updates_with_invalid_values_are_rejected() {
+        let previous = AppConfig::default();
+        let mut value = serde_json::to_value(&previous).unwrap();
+        value["log_level"] = json!("verbose");
+        assert!(AppConfig::from_update(value, &previous).is_err());
+
+        let mut value = serde_json::to_value(&previous).unwrap();
+        value["window_width"] = json!(10);
+        assert!(AppConfig::from_update(value, &previous).is_err());
+
+        let mut value = serde_json::to_value(&previous).unwrap();
+        value["default_key_path"] = json!("/nonexistent/vesper/id_ed25519");
+        assert!(AppConfig::from_update(value, &previous).is_err());
+    }
+
+    #[test]
+    fn a_missing_key_only_blocks_the_update_that_sets_it() {
+        let previous = AppConfig {
+            default_key_path: Some("/nonexistent/vesper/id_ed25519".to_string()),
+            ..AppConfig::default()
+        };
+        let mut value = serde_json::to_value(&previous).unwrap();
+        value["theme"] = json!("dark");
+
+        let settings = AppConfig::from_update(value, &previous).unwrap();
+        assert_eq!(settings.theme, Theme::Dark);
+    }
+}
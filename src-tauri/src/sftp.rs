@@ -0,0 +1,404 @@
+use futures_util::io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+use crate::error::{VesperError, VesperResult};
+use async_ssh2_lite::ssh2::{FileStat, OpenFlags, OpenType};
+use async_ssh2_lite::{AsyncSftp, TokioTcpStream};
+
+const SFTP_DIR_MODE: i32 = 0o755;
+const SFTP_FILE_MODE: i32 = 0o644;
+const SFTP_TRANSFER_CHUNK_SIZE: usize = 32 * 1024;
+const SFTP_PROGRESS_INTERVAL_MS: u64 = 200;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpEntry {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+}
+
+impl SftpEntry {
+    fn from_stat(path: &Path, stat: &FileStat) -> Self {
+        Self {
+            name: entry_name(path),
+            path: path.to_string_lossy().into_owned(),
+            is_dir: stat.is_dir(),
+            is_symlink: stat.file_type().is_symlink(),
+            size: stat.size,
+            permissions: stat.perm.map(|perm| perm & 0o7777),
+            modified: stat.mtime,
+        }
+    }
+}
+
+// The last path component, or the whole path for roots like "/"
+fn entry_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string_lossy().into_owned())
+}
+
+// Where a resumed transfer picks up: the partial destination, unless it is larger than the source
+fn resume_offset(existing: u64, total: Option<u64>) -> u64 {
+    match total {
+        Some(total) if existing <= total => existing,
+        _ => 0,
+    }
+}
+
+// Run one step of a transfer, or None once it is cancelled (or its canceller is gone)
+async fn unless_cancelled<T>(
+    step: impl Future<Output = T>,
+    cancel_rx: &mut watch::Receiver<bool>,
+) -> Option<T> {
+    tokio::select! {
+        output = step => Some(output),
+        _ = cancel_rx.changed() => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferState {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferProgress {
+    pub transfer_id: String,
+    pub connection_id: String,
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub transferred: u64,
+    pub total: Option<u64>,
+    pub resumed_from: u64,
+    pub state: TransferState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub type TransferProgressSink = Arc<dyn Fn(TransferProgress) + Send + Sync>;
+
+pub async fn list_dir(
+    sftp: &AsyncSftp<TokioTcpStream>,
+    path: &str,
+) -> VesperResult<Vec<SftpEntry>> {
+    let mut entries: Vec<SftpEntry> = sftp
+        .readdir(Path::new(path))
+        .await
+        .map_err(VesperError::sftp(format!("list directory {}", path)))?
+        .iter()
+        .map(|(entry_path, stat)| SftpEntry::from_stat(entry_path, stat))
+        .filter(|entry| entry.name != "." && entry.name != "..")
+        .collect();
+
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+    Ok(entries)
+}
+
+pub async fn stat(sftp: &AsyncSftp<TokioTcpStream>, path: &str) -> VesperResult<SftpEntry> {
+    let stat = sftp
+        .lstat(Path::new(path))
+        .await
+        .map_err(VesperError::sftp(format!("stat {}", path)))?;
+
+    Ok(SftpEntry::from_stat(Path::new(path), &stat))
+}
+
+pub async fn mkdir(sftp: &AsyncSftp<TokioTcpStream>, path: &str) -> VesperResult<()> {
+    sftp.mkdir(Path::new(path), SFTP_DIR_MODE)
+        .await
+        .map_err(VesperError::sftp(format!("create directory {}", path)))
+}
+
+pub async fn rename(sftp: &AsyncSftp<TokioTcpStream>, from: &str, to: &str) -> VesperResult<()> {
+    sftp.rename(Path::new(from), Path::new(to), None)
+        .await
+        .map_err(VesperError::sftp(format!("rename {} to {}", from, to)))
+}
+
+// Directories must be empty before they can be deleted
+pub async fn delete(sftp: &AsyncSftp<TokioTcpStream>, path: &str) -> VesperResult<()> {
+    let entry = stat(sftp, path).await?;
+
+    let result = if entry.is_dir && !entry.is_symlink {
+        sftp.rmdir(Path::new(path)).await
+    } else {
+        sftp.unlink(Path::new(path)).await
+    };
+
+    result.map_err(VesperError::sftp(format!("delete {}", path)))
+}
+
+// Copy a file in either direction, appending to a partial destination when resuming
+pub async fn run_transfer(
+    sftp: Arc<AsyncSftp<TokioTcpStream>>,
+    mut progress: TransferProgress,
+    resume: bool,
+    mut cancel_rx: watch::Receiver<bool>,
+    progress_sink: TransferProgressSink,
+) -> TransferProgress {
+    let result = match progress.direction {
+        TransferDirection::Download => {
+            download(&sftp, &mut progress, resume, &mut cancel_rx, &progress_sink).await
+        }
+        TransferDirection::Upload => {
+            upload(&sftp, &mut progress, resume, &mut cancel_rx, &progress_sink).await
+        }
+    };
+
+    match result {
+        Ok(TransferState::Cancelled) => progress.state = TransferState::Cancelled,
+        Ok(_) => progress.state = TransferState::Completed,
+        Err(error) => {
+            progress.state = TransferState::Failed;
+            progress.error = Some(error.full_message());
+        }
+    }
+
+    progress_sink(progress.clone());
+    progress
+}
+
+async fn download(
+    sftp: &AsyncSftp<TokioTcpStream>,
+    progress: &mut TransferProgress,
+    resume: bool,
+    cancel_rx: &mut watch::Receiver<bool>,
+    progress_sink: &TransferProgressSink,
+) -> VesperResult<TransferState> {
+    let remote_path = Path::new(&progress.remote_path);
+    let total = sftp
+        .stat(remote_path)
+        .await
+        .map_err(VesperError::sftp(format!("stat {}", progress.remote_path)))?
+        .size;
+    progress.total = total;
+
+    let existing = match tokio::fs::metadata(&progress.local_path).await {
+        Ok(metadata) if resume && metadata.is_file() => metadata.len(),
+        _ => 0,
+    };
+    let offset = resume_offset(existing, total);
+
+    let mut remote = sftp
+        .open(remote_path)
+        .await
+        .map_err(VesperError::sftp(format!("open {}", progress.remote_path)))?;
+    let mut local = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(offset == 0)
+        .open(&progress.local_path)
+        .await
+        .map_err(VesperError::sftp(format!("open {}", progress.local_path)))?;
+
+    if offset > 0 {
+        remote
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(VesperError::sftp(format!("seek {}", progress.remote_path)))?;
+        local
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(VesperError::sftp(format!("seek {}", progress.local_path)))?;
+    }
+    progress.resumed_from = offset;
+    progress.transferred = offset;
+
+    let mut buf = vec![0u8; SFTP_TRANSFER_CHUNK_SIZE];
+    let mut last_report = Instant::now();
+    loop {
+        let Some(read) = unless_cancelled(remote.read(&mut buf), cancel_rx).await else {
+            return Ok(TransferState::Cancelled);
+        };
+        let read = read.map_err(VesperError::sftp(format!("read {}", progress.remote_path)))?;
+        if read == 0 {
+            break;
+        }
+
+        local
+            .write_all(&buf[..read])
+            .await
+            .map_err(VesperError::sftp(format!("write {}", progress.local_path)))?;
+        progress.transferred += read as u64;
+        report_progress(progress, &mut last_report, progress_sink);
+    }
+
+    local
+        .sync_all()
+        .await
+        .map_err(VesperError::sftp(format!("flush {}", progress.local_path)))?;
+
+    Ok(TransferState::Completed)
+}
+
+async fn upload(
+    sftp: &AsyncSftp<TokioTcpStream>,
+    progress: &mut TransferProgress,
+    resume: bool,
+    cancel_rx: &mut watch::Receiver<bool>,
+    progress_sink: &TransferProgressSink,
+) -> VesperResult<TransferState> {
+    let remote_path = Path::new(&progress.remote_path);
+    let mut local = tokio::fs::File::open(&progress.local_path)
+        .await
+        .map_err(VesperError::sftp(format!("open {}", progress.local_path)))?;
+    let total = local
+        .metadata()
+        .await
+        .map_err(VesperError::sftp(format!("stat {}", progress.local_path)))?
+        .len();
+    progress.total = Some(total);
+
+    let existing = if resume {
+        match sftp.stat(remote_path).await {
+            Ok(stat) if stat.is_file() => stat.size.unwrap_or(0),
+            _ => 0,
+        }
+    } else {
+        0
+    };
+    let offset = resume_offset(existing, Some(total));
+
+    let mut flags = OpenFlags::WRITE | OpenFlags::CREATE;
+    if offset == 0 {
+        flags |= OpenFlags::TRUNCATE;
+    }
+    let mut remote = sftp
+        .open_mode(remote_path, flags, SFTP_FILE_MODE, OpenType::File)
+        .await
+        .map_err(VesperError::sftp(format!("open {}", progress.remote_path)))?;
+
+    if offset > 0 {
+        remote
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(VesperError::sftp(format!("seek {}", progress.remote_path)))?;
+        local
+            .seek(SeekFrom::Start(offset))
+            .await
+            .map_err(VesperError::sftp(format!("seek {}", progress.local_path)))?;
+    }
+    progress.resumed_from = offset;
+    progress.transferred = offset;
+
+    let mut buf = vec![0u8; SFTP_TRANSFER_CHUNK_SIZE];
+    let mut last_report = Instant::now();
+    loop {
+        let read = local
+            .read(&mut buf)
+            .await
+            .map_err(VesperError::sftp(format!("read {}", progress.local_path)))?;
+        if read == 0 {
+            break;
+        }
+
+        let Some(write) = unless_cancelled(remote.write_all(&buf[..read]), cancel_rx).await else {
+            return Ok(TransferState::Cancelled);
+        };
+        write.map_err(VesperError::sftp(format!("write {}", progress.remote_path)))?;
+        progress.transferred += read as u64;
+        report_progress(progress, &mut last_report, progress_sink);
+    }
+
+    remote
+        .flush()
+        .await
+        .map_err(VesperError::sftp(format!("flush {}", progress.remote_path)))?;
+
+    Ok(TransferState::Completed)
+}
+
+fn report_progress(
+    progress: &TransferProgress,
+    last_report: &mut Instant,
+    progress_sink: &TransferProgressSink,
+) {
+    if last_report.elapsed() >= Duration::from_millis(SFTP_PROGRESS_INTERVAL_MS) {
+        *last_report = Instant::now();
+        progress_sink(progress.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::{pending, ready};
+
+    #[test]
+    fn resume_starts_from_a_partial_destination_no_larger_than_the_source() {
+        assert_eq!(resume_offset(0, Some(100)), 0);
+        assert_eq!(resume_offset(40, Some(100)), 40);
+        assert_eq!(resume_offset(100, Some(100)), 100);
+        // A bigger destination is a different file, and an unknown size can't be checked
+        assert_eq!(resume_offset(150, Some(100)), 0);
+        assert_eq!(resume_offset(40, None), 0);
+    }
+
+    #[tokio::test]
+    async fn cancelling_interrupts_a_pending_step() {
+        let (cancel_tx, mut cancel_rx) = watch::channel(false);
+        assert_eq!(unless_cancelled(ready(7), &mut cancel_rx).await, Some(7));
+
+        cancel_tx.send(true).unwrap();
+        assert_eq!(
+            unless_cancelled(pending::<()>(), &mut cancel_rx).await,
+            None
+        );
+
+        // Dropping the canceller stops the transfer too
+        let (cancel_tx, mut cancel_rx) = watch::channel(false);
+        drop(cancel_tx);
+        assert_eq!(
+            unless_cancelled(pending::<()>(), &mut cancel_rx).await,
+            None
+        );
+    }
+
+    #[test]
+    fn entries_are_named_after_their_last_component() {
+        assert_eq!(entry_name(Path::new("/var/log/syslog")), "syslog");
+        assert_eq!(entry_name(Path::new("/var/log/")), "log");
+        assert_eq!(entry_name(Path::new("/")), "/");
+
+        let stat = FileStat {
+            size: Some(12),
+            uid: None,
+            gid: None,
+            perm: Some(0o040755),
+            atime: None,
+            mtime: Some(1_700_000_000),
+        };
+        let entry = SftpEntry::from_stat(Path::new("/home/me/src"), &stat);
+        assert_eq!(entry.name, "src");
+        assert_eq!(entry.path, "/home/me/src");
+        assert!(entry.is_dir && !entry.is_symlink);
+        assert_eq!(entry.permissions, Some(0o755));
+    }
+}
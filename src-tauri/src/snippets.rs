@@ -0,0 +1,407 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime};
+use tokio::io::AsyncReadExt;
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+
+use async_ssh2_lite::{AsyncChannel, AsyncSession, TokioTcpStream};
+
+use crate::error::{VesperError, VesperResult};
+
+pub const SNIPPET_DEFAULT_TIMEOUT_SECS: u64 = 60;
+const SNIPPET_MAX_CAPTURED_BYTES: usize = 1024 * 1024;
+const SNIPPET_READ_BUFFER_SIZE: usize = 8192;
+// An abandoned channel gets this long to close before it is left to the session
+const SNIPPET_CHANNEL_CLOSE_TIMEOUT_SECS: u64 = 5;
+// `{{raw:name}}` inserts the value as typed instead of shell-quoting it
+const RAW_VARIABLE_PREFIX: &str = "raw:";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandSnippet {
+    pub id: String,
+    pub connection_id: String,
+    pub name: String,
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+    #[serde(default = "default_created_at")]
+    pub created_at: SystemTime,
+}
+
+fn default_created_at() -> SystemTime {
+    SystemTime::now()
+}
+
+impl CommandSnippet {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs.unwrap_or(SNIPPET_DEFAULT_TIMEOUT_SECS))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnippetRunResult {
+    pub run_id: String,
+    pub snippet_id: String,
+    pub command: String,
+    pub exit_status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub truncated: bool,
+    pub timed_out: bool,
+    pub cancelled: bool,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnippetOutput {
+    pub run_id: String,
+    pub stream: OutputStream,
+    pub data: String,
+}
+
+pub type SnippetOutputSink = Box<dyn Fn(SnippetOutput) + Send + Sync>;
+
+// Replace `{{name}}` placeholders with the provided values, shell-quoted unless marked raw.
+// Quoting can't stop a value from being read as an option, so quoted values can't start with '-'
+pub fn render_snippet_command(
+    template: &str,
+    variables: &HashMap<String, String>,
+) -> VesperResult<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+
+        let (name, raw) = placeholder(&rest[start + 2..start + 2 + end]);
+        let value = variables.get(name).ok_or_else(|| {
+            VesperError::InvalidInput(format!("Missing value for variable '{}'", name))
+        })?;
+
+        if !raw && value.starts_with('-') {
+            return Err(VesperError::InvalidInput(format!(
+                "Value for variable '{}' starts with '-' and would be read as an option; \
+                 use {{{{raw:{}}}}} if that is intended",
+                name, name
+            )));
+        }
+
+        rendered.push_str(&rest[..start]);
+        if raw {
+            rendered.push_str(value);
+        } else {
+            rendered.push_str(&shell_quote(value));
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+// List the distinct `{{name}}` placeholders in order of first appearance
+pub fn snippet_variables(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+
+        let name = placeholder(&rest[start + 2..start + 2 + end]).0.to_string();
+        if !name.is_empty() && !names.contains(&name) {
+            names.push(name);
+        }
+        rest = &rest[start + 2 + end + 2..];
+    }
+
+    names
+}
+
+// The variable name inside `{{...}}` and whether it's inserted raw
+fn placeholder(inner: &str) -> (&str, bool) {
+    let inner = inner.trim();
+    match inner.strip_prefix(RAW_VARIABLE_PREFIX) {
+        Some(name) => (name.trim(), true),
+        None => (inner, false),
+    }
+}
+
+// Plain words are left alone; anything else is single-quoted for a POSIX shell
+fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+struct CapturedOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    truncated: bool,
+}
+
+impl CapturedOutput {
+    fn append(&mut self, stream: OutputStream, data: &[u8]) {
+        let captured = self.stdout.len() + self.stderr.len();
+        let remaining = SNIPPET_MAX_CAPTURED_BYTES.saturating_sub(captured);
+        if data.len() > remaining {
+            self.truncated = true;
+        }
+
+        let data = &data[..data.len().min(remaining)];
+        match stream {
+            OutputStream::Stdout => self.stdout.extend_from_slice(data),
+            OutputStream::Stderr => self.stderr.extend_from_slice(data),
+        }
+    }
+}
+
+enum ExecOutcome {
+    Finished(VesperResult<Option<i32>>),
+    TimedOut,
+    Cancelled,
+}
+
+// Run a command through an exec channel on an existing SSH session
+pub async fn execute_on_session(
+    session: &AsyncSession<TokioTcpStream>,
+    run_id: &str,
+    snippet_id: &str,
+    command: &str,
+    run_timeout: Duration,
+    cancel_rx: oneshot::Receiver<()>,
+    output_sink: Option<&SnippetOutputSink>,
+) -> VesperResult<SnippetRunResult> {
+    let started_at = Instant::now();
+    let mut captured = CapturedOutput {
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        truncated: false,
+    };
+
+    let mut channel = None;
+    let outcome = {
+        let exec = exec_and_collect(
+            session,
+            run_id,
+            command,
+            &mut channel,
+            &mut captured,
+            output_sink,
+        );
+        tokio::select! {
+            result = timeout(run_timeout, exec) => match result {
+                Ok(result) => ExecOutcome::Finished(result),
+                Err(_) => ExecOutcome::TimedOut,
+            },
+            _ = cancel_rx => ExecOutcome::Cancelled,
+        }
+    };
+
+    let (exit_status, timed_out, cancelled) = match outcome {
+        ExecOutcome::Finished(result) => (result?, false, false),
+        ExecOutcome::TimedOut => (None, true, false),
+        ExecOutcome::Cancelled => (None, false, true),
+    };
+    if timed_out || cancelled {
+        if let Some(channel) = channel.as_mut() {
+            abandon_channel(channel, run_id).await;
+        }
+    }
+
+    Ok(SnippetRunResult {
+        run_id: run_id.to_string(),
+        snippet_id: snippet_id.to_string(),
+        command: command.to_string(),
+        exit_status,
+        stdout: String::from_utf8_lossy(&captured.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&captured.stderr).into_owned(),
+        truncated: captured.truncated,
+        timed_out,
+        cancelled,
+        duration_ms: started_at.elapsed().as_millis() as u64,
+    })
+}
+
+// The channel is left in `slot` so the caller can close it if the run is abandoned
+async fn exec_and_collect(
+    session: &AsyncSession<TokioTcpStream>,
+    run_id: &str,
+    command: &str,
+    slot: &mut Option<AsyncChannel<TokioTcpStream>>,
+    captured: &mut CapturedOutput,
+    output_sink: Option<&SnippetOutputSink>,
+) -> VesperResult<Option<i32>> {
+    let channel = slot.insert(
+        session
+            .channel_session()
+            .await
+            .map_err(VesperError::snippet_exec("open exec channel"))?,
+    );
+
+    channel
+        .exec(command)
+        .await
+        .map_err(VesperError::snippet_exec("execute command"))?;
+
+    let mut stderr = channel.stderr();
+    let mut stdout_buf = vec![0u8; SNIPPET_READ_BUFFER_SIZE];
+    let mut stderr_buf = vec![0u8; SNIPPET_READ_BUFFER_SIZE];
+    let mut stdout_open = true;
+    let mut stderr_open = true;
+
+    while stdout_open || stderr_open {
+        let (stream, read_result, buf) = tokio::select! {
+            result = channel.read(&mut stdout_buf), if stdout_open => {
+                (OutputStream::Stdout, result, &stdout_buf)
+            }
+            result = stderr.read(&mut stderr_buf), if stderr_open => {
+                (OutputStream::Stderr, result, &stderr_buf)
+            }
+        };
+
+        let read = read_result.map_err(VesperError::snippet_exec("read command output"))?;
+        if read == 0 {
+            match stream {
+                OutputStream::Stdout => stdout_open = false,
+                OutputStream::Stderr => stderr_open = false,
+            }
+            continue;
+        }
+
+        captured.append(stream, &buf[..read]);
+        if let Some(sink) = output_sink {
+            sink(SnippetOutput {
+                run_id: run_id.to_string(),
+                stream,
+                data: String::from_utf8_lossy(&buf[..read]).into_owned(),
+            });
+        }
+    }
+
+    if let Err(e) = channel.wait_close().await {
+        log::error!("Failed to close exec channel for run {}: {}", run_id, e);
+    }
+
+    Ok(channel.exit_status().ok())
+}
+
+// Send EOF and close the channel so the server stops the command's pipes. Without a pty no
+// SIGHUP is sent, so a command that neither reads input nor writes output may keep running
+async fn abandon_channel(channel: &mut AsyncChannel<TokioTcpStream>, run_id: &str) {
+    let close = async {
+        channel.send_eof().await?;
+        channel.close().await
+    };
+    match timeout(
+        Duration::from_secs(SNIPPET_CHANNEL_CLOSE_TIMEOUT_SECS),
+        close,
+    )
+    .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::warn!("Failed to close exec channel for run {}: {}", run_id, e),
+        Err(_) => log::warn!("Exec channel for run {} did not close in time", run_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn render_replaces_all_placeholders() {
+        let rendered = render_snippet_command(
+            "sudo systemctl restart {{service}} && journalctl -u {{ service }} -n {{lines}}",
+            &variables(&[("service", "nginx"), ("lines", "50")]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            rendered,
+            "sudo systemctl restart nginx && journalctl -u nginx -n 50"
+        );
+    }
+
+    #[test]
+    fn values_are_shell_quoted_unless_raw() {
+        let values = variables(&[("path", "/tmp/a b; rm -rf ~"), ("name", "it's")]);
+
+        assert_eq!(
+            render_snippet_command("ls {{path}} {{name}}", &values).unwrap(),
+            r"ls '/tmp/a b; rm -rf ~' 'it'\''s'"
+        );
+        assert_eq!(
+            render_snippet_command("ls {{ raw:path }}", &values).unwrap(),
+            "ls /tmp/a b; rm -rf ~"
+        );
+        assert_eq!(
+            render_snippet_command("echo {{empty}}", &variables(&[("empty", "")])).unwrap(),
+            "echo ''"
+        );
+        assert_eq!(
+            snippet_variables("{{raw:path}} {{path}}"),
+            vec!["path".to_string()]
+        );
+    }
+
+    #[test]
+    fn render_fails_on_missing_variable() {
+        let error = render_snippet_command("tail -f {{path}}", &HashMap::new()).unwrap_err();
+
+        assert_eq!(error.code(), "INVALID_INPUT");
+        assert!(error.to_string().contains("path"));
+    }
+
+    #[test]
+    fn values_that_look_like_options_are_rejected_unless_raw() {
+        let values = variables(&[("target", "-rf /"), ("lines", "-5")]);
+
+        let error = render_snippet_command("rm {{target}}", &values).unwrap_err();
+        assert_eq!(error.code(), "INVALID_INPUT");
+        assert!(error.to_string().contains("target"));
+        assert_eq!(
+            render_snippet_command("head {{raw:lines}} log", &values).unwrap(),
+            "head -5 log"
+        );
+    }
+
+    #[test]
+    fn unterminated_placeholder_is_left_untouched() {
+        let rendered = render_snippet_command("echo {{oops", &HashMap::new()).unwrap();
+
+        assert_eq!(rendered, "echo {{oops");
+    }
+
+    #[test]
+    fn variables_are_listed_once_in_order() {
+        assert_eq!(
+            snippet_variables("{{b}} {{a}} {{ b }}"),
+            vec!["b".to_string(), "a".to_string()]
+        );
+    }
+}
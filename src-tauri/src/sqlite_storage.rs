@@ -0,0 +1,476 @@
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::error::{VesperError, VesperResult};
+use crate::storage::{
+    app_data_from_value, persisted_value, unix_millis, AppData, SessionState, SnapshotInfo,
+    StorageBackend,
+};
+
+// Older history rows are pruned once the table grows past this
+const HISTORY_RETENTION: i64 = 10_000;
+
+const SNAPSHOTS_UNSUPPORTED: &str = "Snapshots are not supported with SQLite storage";
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS meta (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS connections (
+        id TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tunnels (
+        id TEXT PRIMARY KEY,
+        connection_id TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS tunnels_by_connection ON tunnels (connection_id);
+    CREATE TABLE IF NOT EXISTS snippets (
+        id TEXT PRIMARY KEY,
+        connection_id TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS settings (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        recorded_at INTEGER NOT NULL,
+        event TEXT NOT NULL,
+        entity_id TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS history_by_entity ON history (entity_id, recorded_at);
+";
+
+// Rows hold the same JSON as data.json entries, so the document migrations apply unchanged
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+    // Set while the stored data can't be read, so a save can't replace it with less
+    load_failed: AtomicBool,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> VesperResult<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(VesperError::storage_io("create data directory"))?;
+        }
+
+        let connection = Connection::open(path).map_err(VesperError::database("open database"))?;
+        connection
+            .execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .and_then(|_| connection.execute_batch(SCHEMA))
+            .map_err(VesperError::database("initialize database"))?;
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+            load_failed: AtomicBool::new(false),
+        })
+    }
+
+    // None only when the settings row is absent; an unreadable row is an error
+    fn read_document(connection: &Connection) -> VesperResult<Option<Value>> {
+        let Some(settings) = read_value(connection, "settings", "app")? else {
+            // 空数据库
+            return Ok(None);
+        };
+
+        let mut document = Map::new();
+        if let Some(version) = read_value(connection, "meta", "schema_version")? {
+            document.insert("schema_version".to_string(), version);
+        }
+        document.insert("settings".to_string(), settings);
+        for table in ["connections", "tunnels", "snippets"] {
+            document.insert(table.to_string(), read_rows(connection, table)?);
+        }
+        if let Some(last_session) = read_value(connection, "settings", "last_session")? {
+            document.insert("last_session".to_string(), last_session);
+        }
+        if let Some(templates) = read_value(connection, "settings", "tunnel_templates")? {
+            document.insert("tunnel_templates".to_string(), templates);
+        }
+
+        Ok(Some(Value::Object(document)))
+    }
+}
+
+impl StorageBackend for SqliteStorage {
+    fn load(&self) -> VesperResult<AppData> {
+        let loaded = {
+            let connection = self.connection.lock().unwrap();
+            Self::read_document(&connection)
+        }
+        .and_then(|document| match document {
+            Some(document) => app_data_from_value(document),
+            None => Ok((AppData::default(), None)),
+        });
+        self.load_failed.store(loaded.is_err(), Ordering::SeqCst);
+
+        let (data, migrated_from) = loaded?;
+        if migrated_from.is_some() {
+            self.save(&data)?;
+        }
+
+        Ok(data)
+    }
+
+    // Only rows whose content changed are rewritten, all within one transaction
+    fn save(&self, data: &AppData) -> VesperResult<()> {
+        if self.load_failed.load(Ordering::SeqCst) {
+            return Err(VesperError::Storage(
+                "Refusing to overwrite a database that failed to load".to_string(),
+            ));
+        }
+
+        let document = persisted_value(data)?;
+        let mut connection = self.connection.lock().unwrap();
+
+        let result = (|| {
+            let tx = connection.transaction()?;
+
+            // Only used to derive history events, so an unreadable value counts as empty
+            let previous_session: SessionState = read_text(&tx, "settings", "last_session")?
+                .and_then(|text| serde_json::from_str(&text).ok())
+                .unwrap_or_default();
+
+            sync_rows(&tx, "connections", &document["connections"], false)?;
+            sync_rows(&tx, "tunnels", &document["tunnels"], true)?;
+            sync_rows(&tx, "snippets", &document["snippets"], true)?;
+            write_value(&tx, "settings", "app", &document["settings"])?;
+            write_value(&tx, "settings", "last_session", &document["last_session"])?;
+            write_value(
+                &tx,
+                "settings",
+                "tunnel_templates",
+                &document["tunnel_templates"],
+            )?;
+            write_value(&tx, "meta", "schema_version", &document["schema_version"])?;
+            record_history(&tx, &previous_session, &data.last_session)?;
+
+            tx.commit()
+        })();
+
+        result.map_err(VesperError::database("write database"))
+    }
+
+    // The database keeps its own history; data.json snapshots don't apply
+    fn list_snapshots(&self) -> VesperResult<Vec<SnapshotInfo>> {
+        Err(VesperError::Storage(SNAPSHOTS_UNSUPPORTED.to_string()))
+    }
+
+    fn restore_snapshot(&self, _id: &str) -> VesperResult<AppData> {
+        Err(VesperError::Storage(SNAPSHOTS_UNSUPPORTED.to_string()))
+    }
+}
+
+fn read_text(
+    connection: &Connection,
+    table: &str,
+    key: &str,
+) -> Result<Option<String>, rusqlite::Error> {
+    connection
+        .query_row(
+            &format!("SELECT value FROM {} WHERE key = ?1", table),
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+fn read_value(connection: &Connection, table: &str, key: &str) -> VesperResult<Option<Value>> {
+    let Some(text) =
+        read_text(connection, table, key).map_err(VesperError::database("read database"))?
+    else {
+        return Ok(None);
+    };
+
+    serde_json::from_str(&text)
+        .map(Some)
+        .map_err(VesperError::corrupt(format!(
+            "Database value {}.{}",
+            table, key
+        )))
+}
+
+fn write_value(
+    connection: &Connection,
+    table: &str,
+    key: &str,
+    value: &Value,
+) -> Result<(), rusqlite::Error> {
+    connection.execute(
+        &format!(
+            "INSERT INTO {} (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            table
+        ),
+        params![key, value.to_string()],
+    )?;
+    Ok(())
+}
+
+// A skipped row would be deleted by the next save, so an unreadable one fails the load
+fn read_rows(connection: &Connection, table: &str) -> VesperResult<Value> {
+    let rows: Vec<(String, String)> = connection
+        .prepare(&format!("SELECT id, data FROM {}", table))
+        .and_then(|mut statement| {
+            statement
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect()
+        })
+        .map_err(VesperError::database("read database"))?;
+
+    let mut items = Map::new();
+    for (id, data) in rows {
+        let value = serde_json::from_str(&data).map_err(VesperError::corrupt(format!(
+            "Database row {}.{}",
+            table, id
+        )))?;
+        items.insert(id, value);
+    }
+
+    Ok(Value::Object(items))
+}
+
+fn sync_rows(
+    tx: &Transaction,
+    table: &str,
+    items: &Value,
+    with_connection_id: bool,
+) -> Result<(), rusqlite::Error> {
+    let empty = Map::new();
+    let items = items.as_object().unwrap_or(&empty);
+
+    let existing: Vec<String> = tx
+        .prepare(&format!("SELECT id FROM {}", table))?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for id in existing.iter().filter(|id| !items.contains_key(*id)) {
+        tx.execute(&format!("DELETE FROM {} WHERE id = ?1", table), params![id])?;
+    }
+
+    for (id, item) in items {
+        let data = item.to_string();
+        if with_connection_id {
+            let connection_id = item["connection_id"].as_str().unwrap_or_default();
+            tx.execute(
+                &format!(
+                    "INSERT INTO {} (id, connection_id, data) VALUES (?1, ?2, ?3)
+                     ON CONFLICT(id) DO UPDATE SET
+                         connection_id = excluded.connection_id, data = excluded.data
+                     WHERE data != excluded.data",
+                    table
+                ),
+                params![id, connection_id, data],
+            )?;
+        } else {
+            tx.execute(
+                &format!(
+                    "INSERT INTO {} (id, data) VALUES (?1, ?2)
+                     ON CONFLICT(id) DO UPDATE SET data = excluded.data
+                     WHERE data != excluded.data",
+                    table
+                ),
+                params![id, data],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+// Append connect/disconnect and tunnel start/stop events derived from the session state
+fn record_history(
+    tx: &Transaction,
+    previous: &SessionState,
+    current: &SessionState,
+) -> Result<(), rusqlite::Error> {
+    let recorded_at = unix_millis() as i64;
+    let changes = [
+        (
+            &previous.connections,
+            &current.connections,
+            "connected",
+            "disconnected",
+        ),
+        (
+            &previous.tunnels,
+            &current.tunnels,
+            "tunnel_started",
+            "tunnel_stopped",
+        ),
+    ];
+
+    for (before, after, up, down) in changes {
+        let before: HashSet<&String> = before.iter().collect();
+        let after: HashSet<&String> = after.iter().collect();
+        let events = after
+            .difference(&before)
+            .map(|id| (up, id))
+            .chain(before.difference(&after).map(|id| (down, id)));
+
+        for (event, id) in events {
+            tx.execute(
+                "INSERT INTO history (recorded_at, event, entity_id) VALUES (?1, ?2, ?3)",
+                params![recorded_at, event, id],
+            )?;
+        }
+    }
+
+    tx.execute(
+        "DELETE FROM history WHERE id <= (SELECT MAX(id) FROM history) - ?1",
+        params![HISTORY_RETENTION],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::Theme;
+    use crate::ssh::{generate_id, AuthMethod, ConnectionStatus, SSHConnection};
+    use crate::storage::DataManager;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn temp_dir() -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vesper-test-{}", generate_id()));
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn connection(id: &str) -> SSHConnection {
+        SSHConnection {
+            name: id.to_string(),
+            host: "example.com".to_string(),
+            username: "me".to_string(),
+            auth_method: AuthMethod::Key,
+            password: None,
+            key_path: Some("/home/me/.ssh/id_ed25519".to_string()),
+            status: ConnectionStatus::Connected,
+            ..SSHConnection::fixture(id)
+        }
+    }
+
+    fn history(storage: &SqliteStorage) -> Vec<(String, String)> {
+        let connection = storage.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT event, entity_id FROM history ORDER BY id")
+            .unwrap();
+        statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn saved_data_round_trips_and_removed_rows_are_deleted() {
+        let dir = temp_dir();
+        let storage = SqliteStorage::open(&dir.join("vesper.db")).unwrap();
+        let mut data = AppData::default();
+        data.connections.insert("a".to_string(), connection("a"));
+        data.connections.insert("b".to_string(), connection("b"));
+        storage.save(&data).unwrap();
+
+        data.connections.remove("b");
+        data.settings.theme = Theme::Dark;
+        storage.save(&data).unwrap();
+
+        let loaded = storage.load().unwrap();
+        assert_eq!(loaded.connections.len(), 1);
+        assert!(matches!(
+            loaded.connections["a"].status,
+            ConnectionStatus::Disconnected
+        ));
+        assert_eq!(loaded.settings.theme, Theme::Dark);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn session_changes_are_recorded_in_history() {
+        let dir = temp_dir();
+        let storage = SqliteStorage::open(&dir.join("vesper.db")).unwrap();
+        let mut data = AppData::default();
+        data.last_session.connections = vec!["a".to_string()];
+        storage.save(&data).unwrap();
+        storage.save(&data).unwrap();
+
+        data.last_session.connections.clear();
+        storage.save(&data).unwrap();
+
+        assert_eq!(
+            history(&storage),
+            vec![
+                ("connected".to_string(), "a".to_string()),
+                ("disconnected".to_string(), "a".to_string()),
+            ]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_settings_fail_the_load_and_block_saves() {
+        let dir = temp_dir();
+        let storage = SqliteStorage::open(&dir.join("vesper.db")).unwrap();
+        let mut data = AppData::default();
+        data.connections.insert("a".to_string(), connection("a"));
+        storage.save(&data).unwrap();
+        storage
+            .connection
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE settings SET value = '{ broken' WHERE key = 'app'",
+                [],
+            )
+            .unwrap();
+
+        let error = storage.load().unwrap_err();
+        assert_eq!(error.code(), "STORAGE_CORRUPT");
+        assert!(storage.save(&AppData::default()).is_err());
+
+        let connections: i64 = storage
+            .connection
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM connections", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(connections, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn data_json_is_imported_once() {
+        let dir = temp_dir();
+        let legacy = json!({
+            "connections": { "a": connection("a") },
+            "tunnels": {},
+            "settings": {
+                "theme": "dark", "language": "en", "auto_start": false, "log_level": "info",
+                "window_width": 1200, "window_height": 800, "storage_backend": "sqlite"
+            }
+        });
+        fs::write(dir.join("data.json"), legacy.to_string()).unwrap();
+
+        let data_manager = DataManager::with_path(dir.clone());
+        data_manager.open(false).await.unwrap();
+        let data = data_manager.load_data().await.unwrap();
+
+        assert!(data.connections.contains_key("a"));
+        assert!(dir.join("vesper.db").exists());
+        assert!(!dir.join("data.json").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
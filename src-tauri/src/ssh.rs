@@ -1,15 +1,49 @@
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::SystemTime;
 use tokio::net::{TcpListener, TcpSocket, TcpStream};
-use tokio::sync::{oneshot, RwLock};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify, OwnedSemaphorePermit, RwLock, Semaphore};
 use tokio::task::{JoinHandle, JoinSet};
-use tokio::time::{interval, timeout, Duration};
+use tokio::time::{interval, sleep_until, timeout, Duration, Instant};
 use uuid::Uuid;
 
-use async_ssh2_lite::{AsyncListener, AsyncSession, SessionConfiguration, TokioTcpStream};
+use async_ssh2_lite::{
+    AsyncChannel, AsyncListener, AsyncSession, AsyncSftp, SessionConfiguration, TokioTcpStream,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::{join, join_all, select_all, BoxFuture, FutureExt};
+
+use crate::allowlist::ClientAllowlist;
+use crate::bandwidth::{copy_bidirectional_limited, BandwidthLimit, BandwidthLimiter};
+use crate::duplicate::{plan_duplicate, DuplicateOverrides};
+use crate::error::{VesperError, VesperResult};
+use crate::limits::{supervise, ActivityClock, ClientExit, ClientLimits, TrackedStream, WhenFull};
+use crate::monitor::{collect_sample, MetricSample, MetricsHistory};
+use crate::netwatch::{spawn_network_watch, NetworkEvent, NetworkState};
+use crate::pool::SessionPool;
+use crate::ports::{
+    accept_any, port_mappings, validate_port_mappings, LocalPort, PortError, PortMapping,
+};
+use crate::probe::{probe_target, ProbeResult, ProbeTask, TunnelProbe};
+use crate::schedule::{ScheduleStatus, TunnelSchedule};
+use crate::sftp::{
+    run_transfer, TransferDirection, TransferProgress, TransferProgressSink, TransferState,
+};
+use crate::snippets::{
+    execute_on_session, render_snippet_command, CommandSnippet, SnippetOutputSink, SnippetRunResult,
+};
+use crate::stats::{Gauge, TunnelCounters, TunnelStats};
+use crate::storage::{DataManager, SessionState};
+use crate::store::Store;
+use crate::terminal::{
+    open_shell_channel, run_terminal, TerminalCommand, TerminalEventSink, TerminalHandle,
+    TerminalInfo, TERMINAL_INPUT_QUEUE_SIZE,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SSHConnection {
@@ -23,17 +57,56 @@ pub struct SSHConnection {
     pub password: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub key_path: Option<String>,
+    #[serde(default)]
     pub status: ConnectionStatus,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub last_connected: Option<SystemTime>,
     #[serde(default = "default_created_at")]
     pub created_at: SystemTime,
+    // Sessions opened for forwarded channels; 1 means everything shares one session
+    #[serde(default = "default_session_pool_size")]
+    pub session_pool_size: u8,
+    // Aggregate limit over all tunnels of this connection
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<BandwidthLimit>,
 }
 
 fn default_created_at() -> SystemTime {
     SystemTime::now()
 }
 
+pub fn default_session_pool_size() -> u8 {
+    1
+}
+
+// Local tunnels in this mode bind their listener without a session and connect for the first client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OnDemand {
+    // Give the session up after this long without forwarded connections
+    #[serde(default = "default_on_demand_idle_secs")]
+    pub idle_timeout_secs: u64,
+}
+
+impl OnDemand {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.idle_timeout_secs < MIN_ON_DEMAND_IDLE_SECS {
+            return Err(format!(
+                "On-demand idle timeout must be at least {} seconds",
+                MIN_ON_DEMAND_IDLE_SECS
+            ));
+        }
+        Ok(())
+    }
+
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout_secs)
+    }
+}
+
+fn default_on_demand_idle_secs() -> u64 {
+    DEFAULT_ON_DEMAND_IDLE_SECS
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum AuthMethod {
@@ -41,9 +114,10 @@ pub enum AuthMethod {
     Key,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ConnectionStatus {
+    #[default]
     Disconnected,
     Connecting,
     Connected,
@@ -59,14 +133,162 @@ pub struct SSHTunnel {
     pub local_port: u16,
     pub remote_host: String,
     pub remote_port: u16,
+    #[serde(default)]
     pub status: TunnelStatus,
     pub auto_reconnect: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<TunnelProbe>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<BandwidthLimit>,
+    // IPs or CIDR ranges allowed to use a local forward; empty allows every client
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_clients: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_limits: Option<ClientLimits>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_demand: Option<OnDemand>,
+    // Forward this many consecutive ports, starting at local_port -> remote_port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port_count: Option<u16>,
+    // More mappings to the same remote host, started together with the range
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_ports: Vec<PortMapping>,
+    // Web page for the service behind the tunnel; `{local_port}` is replaced with the local port
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_url: Option<String>,
+    // Started and stopped automatically by the scheduler
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<TunnelSchedule>,
+    // Runtime only, stripped before saving
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub port_errors: Vec<PortError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_probe: Option<ProbeResult>,
 }
 
 pub struct ActiveTunnel {
     pub tunnel: SSHTunnel,
     shutdown_tx: Option<oneshot::Sender<TunnelControl>>,
     task_handle: JoinHandle<()>,
+    probe_task: Option<ProbeTask>,
+    // On-demand tunnels only: drop the session and go back to standby instead of stopping
+    session_lost: Option<Arc<Notify>>,
+}
+
+// A snippet run in flight, so an on-demand session isn't closed under it
+struct RunningSnippet {
+    connection_id: String,
+    cancel_tx: oneshot::Sender<()>,
+}
+
+// An SFTP transfer in flight; it is cancelled when its connection's session closes
+struct RunningTransfer {
+    connection_id: String,
+    cancel_tx: watch::Sender<bool>,
+}
+
+// What applies to every client a running tunnel forwards
+struct ForwardingPolicy {
+    tunnel_id: String,
+    allowlist: ClientAllowlist,
+    limiters: Vec<Arc<BandwidthLimiter>>,
+    counters: Arc<TunnelCounters>,
+    limits: ClientLimits,
+    slots: Option<Arc<Semaphore>>, // None without a connection limit
+}
+
+// A forwarded client's place under the tunnel's connection limit
+struct ClientSlot {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ForwardingPolicy {
+    // Count and log a client that isn't in the allowlist
+    fn rejects(&self, peer: SocketAddr) -> bool {
+        if self.allowlist.allows(peer.ip()) {
+            return false;
+        }
+
+        self.counters
+            .rejected_clients
+            .fetch_add(1, Ordering::Relaxed);
+        log::warn!(
+            "Tunnel {} rejected client {}: not in the allowlist",
+            self.tunnel_id,
+            peer
+        );
+        true
+    }
+
+    // Waits for a free slot when the tunnel queues clients; None means the client is turned away
+    async fn client_slot(&self) -> Option<ClientSlot> {
+        let Some(slots) = &self.slots else {
+            return Some(ClientSlot { _permit: None });
+        };
+
+        let permit = match slots.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) if self.limits.when_full == WhenFull::Reject => {
+                return self.turn_away("connection limit reached");
+            }
+            Err(_) => {
+                let _queued = Gauge::enter(&self.counters.queued_connections);
+                let queued = self.counters.queued_connections.load(Ordering::Relaxed);
+                if queued > self.limits.max_queued() {
+                    return self.turn_away("too many clients waiting for a slot");
+                }
+                match timeout(self.limits.queue_timeout(), slots.clone().acquire_owned()).await {
+                    Ok(permit) => permit.ok()?,
+                    Err(_) => return self.turn_away("no slot freed up in time"),
+                }
+            }
+        };
+        Some(ClientSlot {
+            _permit: Some(permit),
+        })
+    }
+
+    fn turn_away(&self, reason: &str) -> Option<ClientSlot> {
+        self.counters
+            .rejected_over_limit
+            .fetch_add(1, Ordering::Relaxed);
+        log::warn!("Tunnel {} rejected a client: {}", self.tunnel_id, reason);
+        None
+    }
+
+    // Copy one client's traffic, closing it early when it idles or outlives the tunnel's limits
+    async fn forward_client<C, S>(&self, channel: &mut C, stream: &mut S) -> std::io::Result<()>
+    where
+        C: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    {
+        self.counters
+            .total_connections
+            .fetch_add(1, Ordering::Relaxed);
+        let _active = Gauge::enter(&self.counters.active_connections);
+
+        let clock = ActivityClock::new();
+        let mut stream = TrackedStream::new(stream, &clock);
+        let copy = copy_bidirectional_limited(channel, &mut stream, &self.limiters);
+        match supervise(copy, &clock, &self.limits).await {
+            Ok(result) => result.map(|_| ()),
+            Err(exit) => {
+                let (counter, reason) = match exit {
+                    ClientExit::IdleTimeout => (&self.counters.idle_timeouts, "idle timeout"),
+                    ClientExit::LifetimeExceeded => {
+                        (&self.counters.lifetime_exceeded, "maximum lifetime")
+                    }
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                log::info!(
+                    "Tunnel {} closed a forwarded connection: {} reached",
+                    self.tunnel_id,
+                    reason
+                );
+                Ok(())
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,11 +298,16 @@ pub enum TunnelType {
     Remote,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TunnelStatus {
+    #[default]
     Inactive,
     Active,
+    // Forwarding is up but the target failed its last probe
+    Degraded,
+    // On-demand tunnel listening without an SSH session
+    Standby,
     Error,
 }
 
@@ -104,12 +331,31 @@ enum TunnelExitReason {
     TunnelError(String),
 }
 
+// What noticed that a connection's SSH session is gone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureCause {
+    // A keepalive, health check or tunnel; only auto-reconnect tunnels bring the session back
+    SessionLost,
+    // The session didn't survive a wake-up or network change; every connected session comes back
+    NetworkEvent,
+}
+
 const HEALTH_CHECK_INTERVAL_SECS: u64 = 60;
+// How often to look at the settings again while metrics are turned off
+const METRICS_DISABLED_RECHECK_SECS: u64 = 30;
 const SSH_KEEPALIVE_INTERVAL_SECS: u64 = 30;
 const SSH_KEEPALIVE_FAILURE_THRESHOLD: u8 = 3;
 const SSH_CONNECT_TIMEOUT_SECS: u64 = 30;
 const TUNNEL_STOP_TIMEOUT_SECS: u64 = 5;
 const RECONNECT_DELAY_SECS: u64 = 5;
+const RECONNECT_MAX_DELAY_SECS: u64 = 60;
+const RECONNECT_MAX_ATTEMPTS: u32 = 6;
+const TERMINAL_CLOSE_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_ON_DEMAND_IDLE_SECS: u64 = 300;
+const MIN_ON_DEMAND_IDLE_SECS: u64 = 10;
+const SCHEDULE_CHECK_INTERVAL_SECS: u64 = 30;
+// How long a session gets to answer after a wake-up or network change; slow links may need a while
+const SESSION_VERIFY_TIMEOUT_SECS: u64 = 20;
 
 #[derive(Clone)]
 pub struct ConnectionManager {
@@ -118,16 +364,51 @@ pub struct ConnectionManager {
     ssh_sessions: Arc<RwLock<HashMap<String, Arc<AsyncSession<TokioTcpStream>>>>>,
     active_tunnels: Arc<RwLock<HashMap<String, ActiveTunnel>>>,
     reconnecting_connections: Arc<RwLock<HashSet<String>>>,
+    snippets: Arc<RwLock<HashMap<String, CommandSnippet>>>,
+    running_snippets: Arc<RwLock<HashMap<String, RunningSnippet>>>,
+    terminals: Arc<RwLock<HashMap<String, TerminalHandle>>>,
+    sftp_sessions: Arc<RwLock<HashMap<String, Arc<AsyncSftp<TokioTcpStream>>>>>,
+    transfers: Arc<RwLock<HashMap<String, RunningTransfer>>>,
+    metrics: Arc<RwLock<HashMap<String, MetricsHistory>>>,
+    session_pools: Arc<RwLock<HashMap<String, Arc<SessionPool>>>>,
+    // Keyed by tunnel or connection id, shared by every stream they forward
+    bandwidth_limiters: Arc<RwLock<HashMap<String, Arc<BandwidthLimiter>>>>,
+    tunnel_counters: Arc<RwLock<HashMap<String, Arc<TunnelCounters>>>>,
+    // Connections whose session was opened by an on-demand tunnel and may be closed when idle
+    on_demand_sessions: Arc<RwLock<HashSet<String>>>,
+    // Scheduled tunnels started or stopped by hand, left alone until the time stored (forever if None)
+    schedule_overrides: Arc<RwLock<HashMap<String, Option<DateTime<Utc>>>>>,
+    network: NetworkState,
+    store: Store,
+    // Held from reading the maps until the update is queued, so saves reach the store in order;
+    // also held while switching profiles so the old profile's data can't be saved into the new one
+    save_lock: Arc<Mutex<()>>,
+    session_restored: Arc<AtomicBool>,
 }
 
 impl ConnectionManager {
-    pub fn new() -> Self {
+    pub fn new(store: Store) -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             tunnels: Arc::new(RwLock::new(HashMap::new())),
             ssh_sessions: Arc::new(RwLock::new(HashMap::new())),
             active_tunnels: Arc::new(RwLock::new(HashMap::new())),
             reconnecting_connections: Arc::new(RwLock::new(HashSet::new())),
+            snippets: Arc::new(RwLock::new(HashMap::new())),
+            running_snippets: Arc::new(RwLock::new(HashMap::new())),
+            terminals: Arc::new(RwLock::new(HashMap::new())),
+            sftp_sessions: Arc::new(RwLock::new(HashMap::new())),
+            transfers: Arc::new(RwLock::new(HashMap::new())),
+            metrics: Arc::new(RwLock::new(HashMap::new())),
+            session_pools: Arc::new(RwLock::new(HashMap::new())),
+            bandwidth_limiters: Arc::new(RwLock::new(HashMap::new())),
+            tunnel_counters: Arc::new(RwLock::new(HashMap::new())),
+            on_demand_sessions: Arc::new(RwLock::new(HashSet::new())),
+            schedule_overrides: Arc::new(RwLock::new(HashMap::new())),
+            network: NetworkState::default(),
+            store,
+            save_lock: Arc::new(Mutex::new(())),
+            session_restored: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -156,26 +437,316 @@ impl ConnectionManager {
         });
     }
 
-    pub async fn initialize(&self) -> Result<(), String> {
+    // Check sessions right after a wake-up or network change instead of waiting for keepalives
+    pub async fn start_network_monitoring(&self) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut events = spawn_network_watch();
+            while let Some(event) = events.recv().await {
+                manager.handle_network_event(event).await;
+            }
+        });
+    }
+
+    async fn handle_network_event(&self, event: NetworkEvent) {
+        match event {
+            NetworkEvent::Resumed(slept) => {
+                log::info!(
+                    "Resumed after {}s asleep, checking SSH sessions",
+                    slept.as_secs()
+                );
+            }
+            NetworkEvent::Changed { online } => {
+                self.network.set_online(online);
+                if !online {
+                    log::warn!("No network available, reconnects are paused");
+                    return;
+                }
+                log::info!("Network changed, checking SSH sessions");
+            }
+        }
+        self.verify_sessions().await;
+    }
+
+    // Primary sessions that fail are reconnected; pooled ones are replaced by their pool
+    async fn verify_sessions(&self) {
+        let sessions: Vec<(String, Arc<AsyncSession<TokioTcpStream>>)> = self
+            .ssh_sessions
+            .read()
+            .await
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+        let pools: Vec<Arc<SessionPool>> =
+            self.session_pools.read().await.values().cloned().collect();
+
+        let checks = sessions.into_iter().map(|(id, session)| async move {
+            verify_session(&session)
+                .await
+                .err()
+                .map(|error| (id, error))
+        });
+        let pool_checks = pools.iter().map(|pool| pool.verify());
+        let (failed, _) = join(join_all(checks), join_all(pool_checks)).await;
+
+        for (id, error) in failed.into_iter().flatten() {
+            let reason = format!(
+                "SSH session of connection {} did not survive a network change: {}",
+                id, error
+            );
+            log::warn!("{}", reason);
+            self.handle_connection_failure(&id, reason, FailureCause::NetworkEvent)
+                .await;
+        }
+    }
+
+    // Start and stop scheduled tunnels as their windows open and close
+    pub async fn start_schedule_monitoring(&self) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = interval(Duration::from_secs(SCHEDULE_CHECK_INTERVAL_SECS));
+            loop {
+                interval.tick().await;
+                manager.apply_schedules(Utc::now()).await;
+            }
+        });
+    }
+
+    async fn apply_schedules(&self, now: DateTime<Utc>) {
+        let scheduled: Vec<SSHTunnel> = self
+            .tunnels
+            .read()
+            .await
+            .values()
+            .filter(|tunnel| tunnel.schedule.is_some())
+            .cloned()
+            .collect();
+
+        for tunnel in scheduled {
+            let Some(schedule) = &tunnel.schedule else {
+                continue;
+            };
+            let state = match schedule.state_at(now) {
+                Ok(state) => state,
+                Err(e) => {
+                    log::warn!("Invalid schedule for tunnel {}: {}", tunnel.name, e);
+                    continue;
+                }
+            };
+            if self.schedule_overridden(&tunnel.id, now).await
+                || self
+                    .reconnecting_connections
+                    .read()
+                    .await
+                    .contains(&tunnel.connection_id)
+            {
+                continue;
+            }
+
+            let running = !matches!(tunnel.status, TunnelStatus::Inactive | TunnelStatus::Error);
+            if state.active && !running {
+                log::info!("Starting tunnel {} for its scheduled window", tunnel.name);
+                if let Err(e) = self.start_tunnel(&tunnel.id).await {
+                    log::warn!(
+                        "Scheduled start of tunnel {} failed: {}",
+                        tunnel.name,
+                        e.full_message()
+                    );
+                }
+            } else if !state.active && !matches!(tunnel.status, TunnelStatus::Inactive) {
+                log::info!(
+                    "Stopping tunnel {} outside its scheduled window",
+                    tunnel.name
+                );
+                if let Err(e) = self.stop_tunnel(tunnel.id.clone()).await {
+                    log::error!("Failed to stop scheduled tunnel {}: {}", tunnel.name, e);
+                }
+            }
+        }
+    }
+
+    // Expired overrides are dropped so the schedule applies again
+    async fn schedule_overridden(&self, id: &str, now: DateTime<Utc>) -> bool {
+        let mut overrides = self.schedule_overrides.write().await;
+        match overrides.get(id) {
+            Some(Some(until)) if now >= *until => {
+                overrides.remove(id);
+                false
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    // A manual start or stop of a scheduled tunnel holds until the schedule's next transition
+    pub async fn override_schedule(&self, id: &str) {
+        let schedule = self
+            .tunnels
+            .read()
+            .await
+            .get(id)
+            .and_then(|tunnel| tunnel.schedule.clone());
+        let Some(schedule) = schedule else {
+            return;
+        };
+
+        let until = schedule
+            .state_at(Utc::now())
+            .ok()
+            .and_then(|state| state.next_transition);
+        self.schedule_overrides
+            .write()
+            .await
+            .insert(id.to_string(), until);
+    }
+
+    pub async fn get_schedule_status(&self, id: &str) -> VesperResult<ScheduleStatus> {
+        let schedule = {
+            let tunnels = self.tunnels.read().await;
+            let tunnel = tunnels
+                .get(id)
+                .ok_or_else(|| VesperError::not_found("Tunnel", id))?;
+            tunnel.schedule.clone().ok_or_else(|| {
+                VesperError::InvalidInput(format!("Tunnel {} has no schedule", tunnel.name))
+            })?
+        };
+
+        let now = Utc::now();
+        let state = schedule.state_at(now).map_err(VesperError::InvalidInput)?;
+        let overridden = self
+            .schedule_overrides
+            .read()
+            .await
+            .get(id)
+            .is_some_and(|until| until.is_none_or(|until| now < until));
+
+        Ok(ScheduleStatus {
+            in_window: state.active,
+            next_transition_at: state
+                .next_transition
+                .map(|time| time.timestamp_millis() as u64),
+            overridden,
+        })
+    }
+
+    // Sample latency (and optionally server resources) of every open session
+    pub async fn start_metrics_monitoring(&self) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let settings = match manager.store.load().await {
+                    Ok(data) => data.settings,
+                    Err(e) => {
+                        log::error!("Failed to read metrics settings: {}", e);
+                        Default::default()
+                    }
+                };
+
+                let Some(period) = settings.metrics_interval() else {
+                    tokio::time::sleep(Duration::from_secs(METRICS_DISABLED_RECHECK_SECS)).await;
+                    continue;
+                };
+                tokio::time::sleep(period).await;
+                manager.collect_metrics(settings.server_metrics).await;
+            }
+        });
+    }
+
+    async fn collect_metrics(&self, with_resources: bool) {
+        let sessions: Vec<(String, Arc<AsyncSession<TokioTcpStream>>)> = self
+            .ssh_sessions
+            .read()
+            .await
+            .iter()
+            .map(|(id, session)| (id.clone(), session.clone()))
+            .collect();
+
+        let mut workers = JoinSet::new();
+        for (id, session) in sessions {
+            workers.spawn(async move { (id, collect_sample(&session, with_resources).await) });
+        }
+
+        while let Some(joined) = workers.join_next().await {
+            let Ok((id, sample)) = joined else {
+                continue;
+            };
+            if let Some(error) = &sample.error {
+                log::debug!("Metrics sample for connection {} failed: {}", id, error);
+            }
+
+            // 采样期间会话可能已经断开
+            if !self.ssh_sessions.read().await.contains_key(&id) {
+                continue;
+            }
+            self.metrics
+                .write()
+                .await
+                .entry(id)
+                .or_default()
+                .push(sample);
+        }
+    }
+
+    pub async fn get_connection_metrics(&self, id: &str) -> Vec<MetricSample> {
+        self.metrics
+            .read()
+            .await
+            .get(id)
+            .map(MetricsHistory::samples)
+            .unwrap_or_default()
+    }
+
+    pub async fn initialize(&self) -> VesperResult<()> {
+        self.load_from_storage().await
+    }
+
+    // Move the store to another data directory and load its data, with saves held off in between
+    pub async fn switch_data_manager(&self, data_manager: DataManager) -> VesperResult<()> {
+        let _saving = self.save_lock.lock().await;
+        self.store.switch_data_manager(data_manager).await?;
         self.load_from_storage().await
     }
 
-    async fn load_from_storage(&self) -> Result<(), String> {
-        use crate::storage::DataManager;
-        let data_manager = DataManager::new()?;
+    async fn load_from_storage(&self) -> VesperResult<()> {
+        let mut data = self.store.load().await?;
 
-        let (connections, tunnels) = data_manager.load_connections_and_tunnels().await?;
+        // 只有仍在运行的会话和隧道才保留其状态
+        {
+            let sessions = self.ssh_sessions.read().await;
+            for (id, connection) in data.connections.iter_mut() {
+                connection.status = if sessions.contains_key(id) {
+                    ConnectionStatus::Connected
+                } else {
+                    ConnectionStatus::Disconnected
+                };
+            }
+        }
+        {
+            let active_tunnels = self.active_tunnels.read().await;
+            for (id, tunnel) in data.tunnels.iter_mut() {
+                tunnel.status = if active_tunnels.contains_key(id) {
+                    TunnelStatus::Active
+                } else {
+                    TunnelStatus::Inactive
+                };
+            }
+        }
 
         let mut connections_map = self.connections.write().await;
-        *connections_map = connections;
+        *connections_map = data.connections;
 
         let mut tunnels_map = self.tunnels.write().await;
-        *tunnels_map = tunnels;
+        *tunnels_map = data.tunnels;
+
+        let mut snippets_map = self.snippets.write().await;
+        *snippets_map = data.snippets;
 
         Ok(())
     }
 
-    async fn save_to_storage(&self) -> Result<(), String> {
+    async fn save_to_storage(&self) -> VesperResult<()> {
+        let _saving = self.save_lock.lock().await;
+
         #[cfg(test)]
         {
             Ok(())
@@ -183,21 +754,84 @@ impl ConnectionManager {
 
         #[cfg(not(test))]
         {
-            use crate::storage::DataManager;
+            let connections = self.connections.read().await.clone();
+            let tunnels = self.tunnels.read().await.clone();
+            let snippets = self.snippets.read().await.clone();
+            let last_session = session_state(&connections, &tunnels);
+
+            self.store.update(move |data| {
+                data.connections = connections;
+                data.tunnels = tunnels;
+                data.snippets = snippets;
+                data.last_session = last_session;
+            })
+        }
+    }
 
-            let data_manager = DataManager::new()?;
-            let connections = self.connections.read().await;
-            let tunnels = self.tunnels.read().await;
+    // Reconnect the sessions and tunnels recorded at shutdown, once per process
+    pub async fn restore_last_session(&self) -> VesperResult<()> {
+        if self.session_restored.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
 
-            data_manager
-                .save_connections_and_tunnels(&*connections, &*tunnels)
-                .await?;
+        let data = self.store.load().await?;
+        if !data.settings.restore_last_session {
+            return Ok(());
+        }
 
-            Ok(())
+        let tunnels = self.tunnels.read().await.clone();
+        let mut connection_ids = data.last_session.connections.clone();
+        let mut on_demand_tunnels = Vec::new();
+        for tunnel_id in &data.last_session.tunnels {
+            if let Some(tunnel) = tunnels.get(tunnel_id) {
+                if tunnel.on_demand.is_some() {
+                    on_demand_tunnels.push(tunnel.clone());
+                } else if !connection_ids.contains(&tunnel.connection_id) {
+                    connection_ids.push(tunnel.connection_id.clone());
+                }
+            }
+        }
+
+        for connection_id in connection_ids {
+            if let Err(e) = self.ensure_ssh_session(&connection_id).await {
+                log::error!(
+                    "Failed to restore connection {}: {}",
+                    connection_id,
+                    e.full_message()
+                );
+                continue;
+            }
+
+            let tunnel_ids: Vec<String> = data
+                .last_session
+                .tunnels
+                .iter()
+                .filter(|id| {
+                    tunnels.get(*id).is_some_and(|tunnel| {
+                        tunnel.connection_id == connection_id && tunnel.on_demand.is_none()
+                    })
+                })
+                .cloned()
+                .collect();
+            if let Err(e) = self.start_tunnels_by_ids(&connection_id, &tunnel_ids).await {
+                log::error!("Failed to restore tunnels for {}: {}", connection_id, e);
+            }
+        }
+
+        // On-demand tunnels go back to standby without connecting
+        for tunnel in on_demand_tunnels {
+            let tunnel_id = tunnel.id.clone();
+            if let Err(e) = self.start_on_demand_tunnel(tunnel).await {
+                log::error!("Failed to restore tunnel {}: {}", tunnel_id, e);
+                self.set_tunnel_status(&tunnel_id, TunnelStatus::Error)
+                    .await;
+            }
         }
+
+        self.save_to_storage().await
     }
 
-    pub async fn add_connection(&self, connection: SSHConnection) -> Result<String, String> {
+    pub async fn add_connection(&self, connection: SSHConnection) -> VesperResult<String> {
         let id = generate_id();
         let mut connection = connection;
         connection.id = id.clone();
@@ -213,11 +847,41 @@ impl ConnectionManager {
         Ok(id)
     }
 
-    pub async fn update_connection(
+    // Copy a connection and its tunnels; nothing is saved when any copied port conflicts
+    pub async fn duplicate_connection(
         &self,
-        id: String,
-        updates: SSHConnection,
-    ) -> Result<(), String> {
+        id: &str,
+        overrides: &DuplicateOverrides,
+    ) -> VesperResult<String> {
+        let mut connections = self.connections.write().await;
+        let mut tunnels = self.tunnels.write().await;
+
+        let source = connections
+            .get(id)
+            .ok_or_else(|| VesperError::not_found("Connection", id))?;
+        let existing: Vec<SSHTunnel> = tunnels.values().cloned().collect();
+        let source_tunnels: Vec<SSHTunnel> = existing
+            .iter()
+            .filter(|tunnel| tunnel.connection_id == id)
+            .cloned()
+            .collect();
+
+        let duplicate = plan_duplicate(source, &source_tunnels, overrides, &connections, &existing)
+            .map_err(|conflicts| VesperError::Conflict(conflicts.join("; ")))?;
+
+        let new_id = duplicate.connection.id.clone();
+        connections.insert(new_id.clone(), duplicate.connection);
+        for tunnel in duplicate.tunnels {
+            tunnels.insert(tunnel.id.clone(), tunnel);
+        }
+        drop(tunnels);
+        drop(connections);
+
+        self.save_to_storage().await?;
+        Ok(new_id)
+    }
+
+    pub async fn update_connection(&self, id: String, updates: SSHConnection) -> VesperResult<()> {
         let mut connections = self.connections.write().await;
 
         if let Some(connection) = connections.get_mut(&id) {
@@ -228,16 +892,19 @@ impl ConnectionManager {
             connection.auth_method = updates.auth_method;
             connection.password = updates.password;
             connection.key_path = updates.key_path;
+            connection.session_pool_size = updates.session_pool_size;
+            connection.bandwidth = updates.bandwidth;
 
             drop(connections);
+            self.refresh_bandwidth_limit(&id).await;
             self.save_to_storage().await?;
             Ok(())
         } else {
-            Err("Connection not found".to_string())
+            Err(VesperError::not_found("Connection", id))
         }
     }
 
-    pub async fn delete_connection(&self, id: String) -> Result<(), String> {
+    pub async fn delete_connection(&self, id: String) -> VesperResult<()> {
         self.stop_tunnels_for_connection(&id, TunnelControl::Stop)
             .await;
         self.close_ssh_session(&id, "Connection deleted").await;
@@ -250,6 +917,15 @@ impl ConnectionManager {
         {
             let mut tunnels = self.tunnels.write().await;
             tunnels.retain(|_, tunnel| tunnel.connection_id != id);
+            let mut limiters = self.bandwidth_limiters.write().await;
+            limiters.retain(|key, _| *key != id && tunnels.contains_key(key));
+            let mut counters = self.tunnel_counters.write().await;
+            counters.retain(|key, _| tunnels.contains_key(key));
+        }
+
+        {
+            let mut snippets = self.snippets.write().await;
+            snippets.retain(|_, snippet| snippet.connection_id != id);
         }
 
         self.save_to_storage().await?;
@@ -266,104 +942,95 @@ impl ConnectionManager {
         connections.get(id).cloned()
     }
 
-    pub async fn test_connection(&self, connection: &SSHConnection) -> ConnectionResult {
+    pub async fn test_connection(
+        &self,
+        connection: &SSHConnection,
+    ) -> VesperResult<ConnectionResult> {
         test_ssh_connection(connection).await
     }
 
-    pub async fn connect_ssh(&self, id: &str) -> ConnectionResult {
-        let connect_result = self.ensure_ssh_session(id).await;
-        if !connect_result.success {
-            return connect_result;
-        }
+    // Fails if the session can't be opened; tunnels that fail to start are reported in the result
+    pub async fn connect_ssh(&self, id: &str) -> VesperResult<ConnectionResult> {
+        // 用户主动连接的会话不会因按需隧道空闲而关闭
+        self.on_demand_sessions.write().await.remove(id);
+        self.ensure_ssh_session(id).await?;
 
         let tunnel_start_error = match self.start_all_tunnels_for_connection(id).await {
             Ok(()) => None,
             Err(error) => {
-                eprintln!("Failed to start tunnels: {}", error);
+                log::error!("Failed to start tunnels: {}", error);
                 Some(error)
             }
         };
 
         if let Err(e) = self.save_to_storage().await {
-            eprintln!("Failed to save data: {}", e);
+            log::error!("Failed to save data: {}", e);
         }
 
-        if let Some(error) = tunnel_start_error {
-            ConnectionResult {
+        Ok(match tunnel_start_error {
+            Some(error) => ConnectionResult {
                 success: true,
                 message: format!(
                     "SSH connection established, but some tunnels failed to start: {}",
-                    error
+                    error.full_message()
                 ),
-                error_code: Some("TUNNEL_START_FAILED".to_string()),
-            }
-        } else {
-            ConnectionResult {
+                error_code: Some(error.code().to_string()),
+            },
+            None => ConnectionResult {
                 success: true,
                 message: "SSH connection established".to_string(),
                 error_code: None,
-            }
-        }
+            },
+        })
     }
 
-    pub async fn start_tunnel(&self, id: &str) -> ConnectionResult {
+    pub async fn start_tunnel(&self, id: &str) -> VesperResult<ConnectionResult> {
         let tunnel = {
             let tunnels = self.tunnels.read().await;
             tunnels.get(id).cloned()
-        };
+        }
+        .ok_or_else(|| VesperError::not_found("Tunnel", id))?;
 
-        let Some(tunnel) = tunnel else {
-            return ConnectionResult {
-                success: false,
-                message: "Tunnel not found".to_string(),
-                error_code: Some("NOT_FOUND".to_string()),
-            };
+        let result = if tunnel.on_demand.is_some() {
+            self.start_on_demand_tunnel(tunnel.clone()).await
+        } else {
+            self.ensure_ssh_session(&tunnel.connection_id).await?;
+            self.start_tunnels_by_ids(&tunnel.connection_id, &[tunnel.id.clone()])
+                .await
         };
 
-        let connect_result = self.ensure_ssh_session(&tunnel.connection_id).await;
-        if !connect_result.success {
-            return connect_result;
-        }
-
-        match self.start_tunnels_by_ids(&tunnel.connection_id, &[tunnel.id.clone()]).await {
+        match result {
             Ok(()) => {
                 if let Err(e) = self.save_to_storage().await {
-                    eprintln!("Failed to save data: {}", e);
+                    log::error!("Failed to save data: {}", e);
                 }
 
-                ConnectionResult {
+                Ok(ConnectionResult {
                     success: true,
                     message: format!("Tunnel {} started", tunnel.name),
                     error_code: None,
-                }
+                })
             }
             Err(error) => {
-                self.set_tunnel_status(&tunnel.id, TunnelStatus::Error).await;
+                self.set_tunnel_status(&tunnel.id, TunnelStatus::Error)
+                    .await;
                 if let Err(e) = self.save_to_storage().await {
-                    eprintln!("Failed to save data: {}", e);
+                    log::error!("Failed to save data: {}", e);
                 }
 
-                ConnectionResult {
-                    success: false,
-                    message: error,
-                    error_code: Some("TUNNEL_START_FAILED".to_string()),
-                }
+                Err(error)
             }
         }
     }
 
-    async fn ensure_ssh_session(&self, id: &str) -> ConnectionResult {
+    async fn ensure_ssh_session(&self, id: &str) -> VesperResult<()> {
         let connection = {
             let connections = self.connections.read().await;
             connections.get(id).cloned()
         };
 
         let Some(connection) = connection else {
-            return ConnectionResult {
-                success: false,
-                message: "Connection not found".to_string(),
-                error_code: Some("NOT_FOUND".to_string()),
-            };
+            return Err(VesperError::not_found("Connection", id));
         };
 
         let existing_session = {
@@ -380,11 +1047,7 @@ impl ConnectionManager {
                 }
             }
 
-            return ConnectionResult {
-                success: true,
-                message: "SSH connection already established".to_string(),
-                error_code: None,
-            };
+            return Ok(());
         }
 
         {
@@ -394,22 +1057,26 @@ impl ConnectionManager {
             }
         }
 
-        let test_result = test_ssh_connection(&connection).await;
-        if !test_result.success {
-            let mut connections = self.connections.write().await;
-            if let Some(conn) = connections.get_mut(id) {
-                conn.status = ConnectionStatus::Error;
-            }
-            return test_result;
-        }
-
         match establish_ssh_session(&connection).await {
             Ok(session) => {
                 self.close_ssh_session(id, "Replacing existing SSH session")
                     .await;
 
+                let session = Arc::new(session);
+                if connection.session_pool_size > 1 {
+                    let pool = SessionPool::start(
+                        connection.clone(),
+                        session.clone(),
+                        connection.session_pool_size,
+                    );
+                    self.session_pools
+                        .write()
+                        .await
+                        .insert(id.to_string(), pool);
+                }
+
                 let mut sessions = self.ssh_sessions.write().await;
-                sessions.insert(id.to_string(), Arc::new(session));
+                sessions.insert(id.to_string(), session);
                 drop(sessions);
 
                 let mut connections = self.connections.write().await;
@@ -418,39 +1085,27 @@ impl ConnectionManager {
                     conn.last_connected = Some(SystemTime::now());
                 }
 
-                ConnectionResult {
-                    success: true,
-                    message: "SSH connection established".to_string(),
-                    error_code: None,
-                }
+                Ok(())
             }
-            Err(e) => {
+            Err(error) => {
                 let mut connections = self.connections.write().await;
                 if let Some(conn) = connections.get_mut(id) {
                     conn.status = ConnectionStatus::Error;
                 }
 
-                ConnectionResult {
-                    success: false,
-                    message: format!("Failed to establish SSH connection: {}", e),
-                    error_code: Some("CONNECTION_FAILED".to_string()),
-                }
+                Err(error)
             }
         }
     }
 
-    pub async fn disconnect_ssh(&self, id: &str) -> ConnectionResult {
+    pub async fn disconnect_ssh(&self, id: &str) -> VesperResult<ConnectionResult> {
         let connection_exists = {
             let connections = self.connections.read().await;
             connections.contains_key(id)
         };
 
         if !connection_exists {
-            return ConnectionResult {
-                success: false,
-                message: "Connection not found".to_string(),
-                error_code: Some("NOT_FOUND".to_string()),
-            };
+            Err(VesperError::not_found("Connection", id))
         } else {
             self.stop_tunnels_for_connection(id, TunnelControl::Stop)
                 .await;
@@ -465,25 +1120,46 @@ impl ConnectionManager {
             }
 
             if let Err(e) = self.save_to_storage().await {
-                eprintln!("Failed to save data: {}", e);
+                log::error!("Failed to save data: {}", e);
             }
 
-            ConnectionResult {
+            Ok(ConnectionResult {
                 success: true,
                 message: "SSH connection and all tunnels closed gracefully".to_string(),
                 error_code: None,
-            }
+            })
         }
     }
 
-    // Check if a connection is still alive and attempt reconnection if needed
-    pub async fn check_connection_health(&self, id: &str) {
-        let session = {
-            let sessions = self.ssh_sessions.read().await;
-            sessions.get(id).cloned()
+    // Stop every tunnel and session, e.g. before the underlying data file is replaced
+    pub async fn shutdown_all(&self, reason: &str) {
+        let connection_ids: Vec<String> = {
+            let connections = self.connections.read().await;
+            connections.keys().cloned().collect()
         };
 
-        let Some(session) = session else {
+        for (_, run) in self.running_snippets.write().await.drain() {
+            let _ = run.cancel_tx.send(());
+        }
+        for (_, transfer) in self.transfers.write().await.drain() {
+            let _ = transfer.cancel_tx.send(true);
+        }
+
+        for id in &connection_ids {
+            self.stop_tunnels_for_connection(id, TunnelControl::Stop)
+                .await;
+            self.close_ssh_session(id, reason).await;
+        }
+    }
+
+    // Check if a connection is still alive and attempt reconnection if needed
+    pub async fn check_connection_health(&self, id: &str) {
+        let session = {
+            let sessions = self.ssh_sessions.read().await;
+            sessions.get(id).cloned()
+        };
+
+        let Some(session) = session else {
             let is_connected = {
                 let connections = self.connections.read().await;
                 connections
@@ -499,6 +1175,7 @@ impl ConnectionManager {
                         "Connection {} is marked connected but has no SSH session",
                         id
                     ),
+                    FailureCause::SessionLost,
                 )
                 .await;
             }
@@ -507,12 +1184,13 @@ impl ConnectionManager {
 
         if let Err(err) = session.keepalive_send().await {
             let reason = format!("SSH keepalive failed for connection {}: {}", id, err);
-            eprintln!("{}", reason);
-            self.handle_connection_failure(id, reason).await;
+            log::warn!("{}", reason);
+            self.handle_connection_failure(id, reason, FailureCause::SessionLost)
+                .await;
         }
     }
 
-    pub async fn add_tunnel(&self, tunnel: SSHTunnel) -> Result<String, String> {
+    pub async fn add_tunnel(&self, tunnel: SSHTunnel) -> VesperResult<String> {
         let id = generate_id();
         let mut tunnel = tunnel;
         tunnel.id = id.clone();
@@ -527,7 +1205,7 @@ impl ConnectionManager {
         Ok(id)
     }
 
-    pub async fn update_tunnel(&self, id: String, updates: SSHTunnel) -> Result<(), String> {
+    pub async fn update_tunnel(&self, id: String, updates: SSHTunnel) -> VesperResult<()> {
         let mut tunnels = self.tunnels.write().await;
 
         if let Some(tunnel) = tunnels.get_mut(&id) {
@@ -537,21 +1215,36 @@ impl ConnectionManager {
             tunnel.remote_host = updates.remote_host;
             tunnel.remote_port = updates.remote_port;
             tunnel.auto_reconnect = updates.auto_reconnect;
+            tunnel.probe = updates.probe;
+            tunnel.bandwidth = updates.bandwidth;
+            tunnel.allowed_clients = updates.allowed_clients;
+            tunnel.client_limits = updates.client_limits;
+            tunnel.on_demand = updates.on_demand;
+            tunnel.port_count = updates.port_count;
+            tunnel.extra_ports = updates.extra_ports;
+            tunnel.open_url = updates.open_url;
+            tunnel.schedule = updates.schedule;
 
             drop(tunnels);
+            self.schedule_overrides.write().await.remove(&id);
+            self.refresh_bandwidth_limit(&id).await;
+            self.attach_probe(&id).await;
             self.save_to_storage().await?;
             Ok(())
         } else {
-            Err("Tunnel not found".to_string())
+            Err(VesperError::not_found("Tunnel", id))
         }
     }
 
-    pub async fn delete_tunnel(&self, id: String) -> Result<(), String> {
+    pub async fn delete_tunnel(&self, id: String) -> VesperResult<()> {
         self.stop_active_tunnel(&id, TunnelControl::Stop).await;
 
         let mut tunnels = self.tunnels.write().await;
         tunnels.remove(&id);
         drop(tunnels);
+        self.bandwidth_limiters.write().await.remove(&id);
+        self.tunnel_counters.write().await.remove(&id);
+        self.schedule_overrides.write().await.remove(&id);
 
         self.save_to_storage().await?;
         Ok(())
@@ -572,7 +1265,7 @@ impl ConnectionManager {
     }
 
     // Stop a tunnel without deleting it
-    pub async fn stop_tunnel(&self, id: String) -> Result<(), String> {
+    pub async fn stop_tunnel(&self, id: String) -> VesperResult<()> {
         let stopped = self.stop_active_tunnel(&id, TunnelControl::Stop).await;
         if !stopped {
             self.set_tunnel_status(&id, TunnelStatus::Inactive).await;
@@ -582,8 +1275,357 @@ impl ConnectionManager {
         Ok(())
     }
 
+    pub async fn add_snippet(&self, snippet: CommandSnippet) -> VesperResult<String> {
+        if !self
+            .connections
+            .read()
+            .await
+            .contains_key(&snippet.connection_id)
+        {
+            return Err(VesperError::not_found("Connection", snippet.connection_id));
+        }
+
+        let id = generate_id();
+        let mut snippet = snippet;
+        snippet.id = id.clone();
+        snippet.created_at = SystemTime::now();
+
+        let mut snippets = self.snippets.write().await;
+        snippets.insert(id.clone(), snippet);
+        drop(snippets);
+
+        self.save_to_storage().await?;
+
+        Ok(id)
+    }
+
+    pub async fn update_snippet(&self, id: String, updates: CommandSnippet) -> VesperResult<()> {
+        let mut snippets = self.snippets.write().await;
+
+        if let Some(snippet) = snippets.get_mut(&id) {
+            snippet.name = updates.name;
+            snippet.command = updates.command;
+            snippet.timeout_secs = updates.timeout_secs;
+
+            drop(snippets);
+            self.save_to_storage().await?;
+            Ok(())
+        } else {
+            Err(VesperError::not_found("Snippet", id))
+        }
+    }
+
+    pub async fn delete_snippet(&self, id: String) -> VesperResult<()> {
+        let mut snippets = self.snippets.write().await;
+        snippets.remove(&id);
+        drop(snippets);
+
+        self.save_to_storage().await?;
+        Ok(())
+    }
+
+    pub async fn get_snippets_by_connection(&self, connection_id: &str) -> Vec<CommandSnippet> {
+        let snippets = self.snippets.read().await;
+        snippets
+            .values()
+            .filter(|snippet| snippet.connection_id == connection_id)
+            .cloned()
+            .collect()
+    }
+
+    // Run a saved snippet over the connection's SSH session, connecting first if needed
+    pub async fn run_snippet(
+        &self,
+        id: &str,
+        run_id: String,
+        variables: &HashMap<String, String>,
+        output_sink: Option<SnippetOutputSink>,
+    ) -> VesperResult<SnippetRunResult> {
+        let snippet = {
+            let snippets = self.snippets.read().await;
+            snippets.get(id).cloned()
+        }
+        .ok_or_else(|| VesperError::not_found("Snippet", id))?;
+
+        let command = render_snippet_command(&snippet.command, variables)?;
+
+        self.ensure_ssh_session(&snippet.connection_id).await?;
+
+        let session = {
+            let sessions = self.ssh_sessions.read().await;
+            sessions.get(&snippet.connection_id).cloned()
+        }
+        .ok_or_else(|| VesperError::NotConnected(snippet.connection_id.clone()))?;
+
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        {
+            let mut running_snippets = self.running_snippets.write().await;
+            if running_snippets.contains_key(&run_id) {
+                return Err(VesperError::Conflict(format!(
+                    "Snippet run {} is already in progress",
+                    run_id
+                )));
+            }
+            running_snippets.insert(
+                run_id.clone(),
+                RunningSnippet {
+                    connection_id: snippet.connection_id.clone(),
+                    cancel_tx,
+                },
+            );
+        }
+
+        let result = execute_on_session(
+            &session,
+            &run_id,
+            &snippet.id,
+            &command,
+            snippet.timeout(),
+            cancel_rx,
+            output_sink.as_ref(),
+        )
+        .await;
+
+        self.running_snippets.write().await.remove(&run_id);
+
+        result
+    }
+
+    pub async fn cancel_snippet_run(&self, run_id: &str) -> bool {
+        let run = {
+            let mut running_snippets = self.running_snippets.write().await;
+            running_snippets.remove(run_id)
+        };
+
+        match run {
+            Some(run) => run.cancel_tx.send(()).is_ok(),
+            None => false,
+        }
+    }
+
+    // Open an interactive PTY shell on the connection's SSH session
+    pub async fn open_terminal(
+        &self,
+        connection_id: &str,
+        term: String,
+        cols: u32,
+        rows: u32,
+        event_sink: TerminalEventSink,
+    ) -> VesperResult<TerminalInfo> {
+        self.ensure_ssh_session(connection_id).await?;
+
+        let session = {
+            let sessions = self.ssh_sessions.read().await;
+            sessions.get(connection_id).cloned()
+        }
+        .ok_or_else(|| VesperError::NotConnected(connection_id.to_string()))?;
+
+        let info = TerminalInfo {
+            id: generate_id(),
+            connection_id: connection_id.to_string(),
+            term,
+            cols,
+            rows,
+        };
+        let channel = open_shell_channel(&session, &info)
+            .await
+            .map_err(VesperError::Ssh)?;
+
+        let (command_tx, command_rx) = mpsc::channel(TERMINAL_INPUT_QUEUE_SIZE);
+        let manager = self.clone();
+        let terminal_id = info.id.clone();
+        let mut terminals = self.terminals.write().await;
+        let task_handle = tokio::spawn(async move {
+            run_terminal(channel, terminal_id.clone(), command_rx, event_sink).await;
+            manager.terminals.write().await.remove(&terminal_id);
+        });
+
+        terminals.insert(
+            info.id.clone(),
+            TerminalHandle {
+                info: info.clone(),
+                command_tx,
+                task_handle,
+            },
+        );
+
+        Ok(info)
+    }
+
+    pub async fn get_terminals_by_connection(&self, connection_id: &str) -> Vec<TerminalInfo> {
+        let terminals = self.terminals.read().await;
+        terminals
+            .values()
+            .filter(|terminal| terminal.info.connection_id == connection_id)
+            .map(|terminal| terminal.info.clone())
+            .collect()
+    }
+
+    pub async fn write_terminal(&self, id: &str, data: Vec<u8>) -> VesperResult<()> {
+        self.send_terminal_command(id, TerminalCommand::Input(data))
+            .await
+    }
+
+    pub async fn resize_terminal(&self, id: &str, cols: u32, rows: u32) -> VesperResult<()> {
+        {
+            let mut terminals = self.terminals.write().await;
+            if let Some(terminal) = terminals.get_mut(id) {
+                terminal.info.cols = cols;
+                terminal.info.rows = rows;
+            }
+        }
+
+        self.send_terminal_command(id, TerminalCommand::Resize { cols, rows })
+            .await
+    }
+
+    async fn send_terminal_command(&self, id: &str, command: TerminalCommand) -> VesperResult<()> {
+        let command_tx = {
+            let terminals = self.terminals.read().await;
+            terminals
+                .get(id)
+                .map(|terminal| terminal.command_tx.clone())
+        }
+        .ok_or_else(|| VesperError::not_found("Terminal", id))?;
+
+        command_tx
+            .send(command)
+            .await
+            .map_err(|_| VesperError::Ssh(format!("Terminal {} is closed", id)))
+    }
+
+    pub async fn close_terminal(&self, id: &str) -> bool {
+        let terminal = {
+            let mut terminals = self.terminals.write().await;
+            terminals.remove(id)
+        };
+
+        let Some(terminal) = terminal else {
+            return false;
+        };
+
+        let _ = terminal.command_tx.send(TerminalCommand::Close).await;
+
+        let mut task_handle = terminal.task_handle;
+        if timeout(
+            Duration::from_secs(TERMINAL_CLOSE_TIMEOUT_SECS),
+            &mut task_handle,
+        )
+        .await
+        .is_err()
+        {
+            log::warn!("Terminal {} did not exit in time, aborting the task", id);
+            task_handle.abort();
+            let _ = task_handle.await;
+        }
+
+        true
+    }
+
+    async fn close_terminals_for_connection(&self, connection_id: &str) {
+        let terminal_ids: Vec<String> = {
+            let terminals = self.terminals.read().await;
+            terminals
+                .values()
+                .filter(|terminal| terminal.info.connection_id == connection_id)
+                .map(|terminal| terminal.info.id.clone())
+                .collect()
+        };
+
+        for terminal_id in terminal_ids {
+            self.close_terminal(&terminal_id).await;
+        }
+    }
+
+    // Open (or reuse) the SFTP subsystem on the connection's SSH session
+    pub async fn sftp_session(
+        &self,
+        connection_id: &str,
+    ) -> VesperResult<Arc<AsyncSftp<TokioTcpStream>>> {
+        self.ensure_ssh_session(connection_id).await?;
+
+        if let Some(sftp) = self.sftp_sessions.read().await.get(connection_id) {
+            return Ok(sftp.clone());
+        }
+
+        let session = {
+            let sessions = self.ssh_sessions.read().await;
+            sessions.get(connection_id).cloned()
+        }
+        .ok_or_else(|| VesperError::NotConnected(connection_id.to_string()))?;
+
+        let sftp = Arc::new(
+            session
+                .sftp()
+                .await
+                .map_err(VesperError::sftp("start SFTP subsystem"))?,
+        );
+
+        let mut sftp_sessions = self.sftp_sessions.write().await;
+        Ok(sftp_sessions
+            .entry(connection_id.to_string())
+            .or_insert(sftp)
+            .clone())
+    }
+
+    pub async fn start_transfer(
+        &self,
+        connection_id: &str,
+        direction: TransferDirection,
+        local_path: String,
+        remote_path: String,
+        resume: bool,
+        progress_sink: TransferProgressSink,
+    ) -> VesperResult<TransferProgress> {
+        let sftp = self.sftp_session(connection_id).await?;
+
+        let progress = TransferProgress {
+            transfer_id: generate_id(),
+            connection_id: connection_id.to_string(),
+            direction,
+            local_path,
+            remote_path,
+            transferred: 0,
+            total: None,
+            resumed_from: 0,
+            state: TransferState::Running,
+            error: None,
+        };
+
+        let (cancel_tx, cancel_rx) = watch::channel(false);
+        self.transfers.write().await.insert(
+            progress.transfer_id.clone(),
+            RunningTransfer {
+                connection_id: connection_id.to_string(),
+                cancel_tx,
+            },
+        );
+
+        let manager = self.clone();
+        let transfer = progress.clone();
+        tokio::spawn(async move {
+            let transfer_id = transfer.transfer_id.clone();
+            let result = run_transfer(sftp, transfer, resume, cancel_rx, progress_sink).await;
+            if let Some(error) = result.error {
+                log::error!("SFTP transfer {} failed: {}", transfer_id, error);
+            }
+            manager.transfers.write().await.remove(&transfer_id);
+        });
+
+        Ok(progress)
+    }
+
+    pub async fn cancel_transfer(&self, transfer_id: &str) -> bool {
+        let transfer = self.transfers.write().await.remove(transfer_id);
+
+        match transfer {
+            Some(transfer) => transfer.cancel_tx.send(true).is_ok(),
+            None => false,
+        }
+    }
+
     // Start all tunnels for a given connection
-    async fn start_all_tunnels_for_connection(&self, connection_id: &str) -> Result<(), String> {
+    async fn start_all_tunnels_for_connection(&self, connection_id: &str) -> VesperResult<()> {
         let tunnel_ids: Vec<String> = self
             .get_tunnels_by_connection(connection_id)
             .await
@@ -598,7 +1640,7 @@ impl ConnectionManager {
         &self,
         connection_id: &str,
         tunnel_ids: &[String],
-    ) -> Result<(), String> {
+    ) -> VesperResult<()> {
         let id_set: HashSet<&str> = tunnel_ids.iter().map(String::as_str).collect();
         let connection_tunnels: Vec<SSHTunnel> = self
             .get_tunnels_by_connection(connection_id)
@@ -617,24 +1659,21 @@ impl ConnectionManager {
                 self.set_tunnel_status(&tunnel.id, TunnelStatus::Error)
                     .await;
             }
-            let error = format!(
-                "No active SSH session found for connection {}",
-                connection_id
-            );
             if let Err(e) = self.save_to_storage().await {
-                eprintln!("Failed to save data: {}", e);
+                log::error!("Failed to save data: {}", e);
             }
-            return Err(error);
+            return Err(VesperError::NotConnected(connection_id.to_string()));
         };
 
-        self.start_tunnels_with_session(connection_tunnels, session).await
+        self.start_tunnels_with_session(connection_tunnels, session)
+            .await
     }
 
     async fn start_tunnels_with_session(
         &self,
         tunnels_to_start: Vec<SSHTunnel>,
         session: Arc<AsyncSession<TokioTcpStream>>,
-    ) -> Result<(), String> {
+    ) -> VesperResult<()> {
         let mut first_error = None;
 
         for tunnel in tunnels_to_start {
@@ -652,9 +1691,14 @@ impl ConnectionManager {
                     drop(active_tunnels);
                     self.set_tunnel_status(&tunnel_id, TunnelStatus::Active)
                         .await;
+                    self.attach_probe(&tunnel_id).await;
                 }
                 Err(err) => {
-                    eprintln!("Failed to start tunnel {}: {}", tunnel.id, err);
+                    log::error!(
+                        "Failed to start tunnel {}: {}",
+                        tunnel.id,
+                        err.full_message()
+                    );
                     self.set_tunnel_status(&tunnel.id, TunnelStatus::Error)
                         .await;
                     if first_error.is_none() {
@@ -665,7 +1709,7 @@ impl ConnectionManager {
         }
 
         if let Err(e) = self.save_to_storage().await {
-            eprintln!("Failed to save data: {}", e);
+            log::error!("Failed to save data: {}", e);
         }
 
         if let Some(err) = first_error {
@@ -679,13 +1723,357 @@ impl ConnectionManager {
         &self,
         tunnel: SSHTunnel,
         session: Arc<AsyncSession<TokioTcpStream>>,
-    ) -> Result<ActiveTunnel, String> {
+    ) -> VesperResult<ActiveTunnel> {
         match tunnel.tunnel_type {
+            TunnelType::Local if tunnel.on_demand.is_some() => {
+                start_on_demand_forwarding(self.clone(), tunnel, Some(session)).await
+            }
             TunnelType::Local => start_local_forwarding(self.clone(), tunnel, session).await,
             TunnelType::Remote => start_remote_forwarding(self.clone(), tunnel, session).await,
         }
     }
 
+    // (Re)start the periodic probe of an active tunnel to match its config
+    async fn attach_probe(&self, tunnel_id: &str) {
+        let Some(tunnel) = self.tunnels.read().await.get(tunnel_id).cloned() else {
+            return;
+        };
+        let session = self
+            .ssh_sessions
+            .read()
+            .await
+            .get(&tunnel.connection_id)
+            .cloned();
+
+        let probe_task = match (tunnel.probe.clone(), session) {
+            (Some(probe), Some(session)) if tunnel.status != TunnelStatus::Standby => {
+                let manager = self.clone();
+                Some(ProbeTask::new(tokio::spawn(async move {
+                    let mut ticker = interval(probe.interval());
+                    loop {
+                        ticker.tick().await;
+                        let result = probe_target(&session, &tunnel, &probe).await;
+                        manager.record_probe_result(&tunnel.id, result).await;
+                    }
+                })))
+            }
+            _ => None,
+        };
+
+        if let Some(active_tunnel) = self.active_tunnels.write().await.get_mut(tunnel_id) {
+            active_tunnel.probe_task = probe_task;
+        }
+    }
+
+    // Probe a tunnel's target now, whether or not the tunnel is running
+    pub async fn probe_tunnel(&self, id: &str) -> VesperResult<ProbeResult> {
+        let tunnel = self
+            .tunnels
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| VesperError::not_found("Tunnel", id))?;
+
+        self.ensure_ssh_session(&tunnel.connection_id).await?;
+        let session = self
+            .ssh_sessions
+            .read()
+            .await
+            .get(&tunnel.connection_id)
+            .cloned()
+            .ok_or_else(|| VesperError::NotConnected(tunnel.connection_id.clone()))?;
+
+        let probe = tunnel.probe.clone().unwrap_or_default();
+        let result = probe_target(&session, &tunnel, &probe).await;
+        self.record_probe_result(id, result.clone()).await;
+        if let Err(e) = self.save_to_storage().await {
+            log::error!("Failed to save data: {}", e);
+        }
+
+        Ok(result)
+    }
+
+    // A running tunnel is Degraded while its target fails probes, Active again once it answers
+    async fn record_probe_result(&self, tunnel_id: &str, result: ProbeResult) {
+        let running = self.active_tunnels.read().await.contains_key(tunnel_id);
+        let mut tunnels = self.tunnels.write().await;
+        let Some(tunnel) = tunnels.get_mut(tunnel_id) else {
+            return;
+        };
+
+        if running && matches!(tunnel.status, TunnelStatus::Active | TunnelStatus::Degraded) {
+            let status = if result.reachable {
+                TunnelStatus::Active
+            } else {
+                TunnelStatus::Degraded
+            };
+            if status != tunnel.status {
+                match &result.error {
+                    Some(error) => {
+                        log::warn!("Tunnel {} target is unreachable: {}", tunnel_id, error)
+                    }
+                    None => log::info!("Tunnel {} target is reachable again", tunnel_id),
+                }
+                tunnel.status = status;
+            }
+        }
+
+        tunnel.last_probe = Some(result);
+    }
+
+    // The tunnel's own limiter and its connection's, created on first use
+    async fn bandwidth_limiters(&self, tunnel: &SSHTunnel) -> Vec<Arc<BandwidthLimiter>> {
+        let connection_limit = self
+            .connections
+            .read()
+            .await
+            .get(&tunnel.connection_id)
+            .and_then(|connection| connection.bandwidth.clone());
+
+        let mut limiters = self.bandwidth_limiters.write().await;
+        [
+            (&tunnel.id, tunnel.bandwidth.clone()),
+            (&tunnel.connection_id, connection_limit),
+        ]
+        .into_iter()
+        .map(|(id, limit)| {
+            let limit = limit.unwrap_or_default();
+            let limiter = limiters
+                .entry(id.clone())
+                .or_insert_with(|| Arc::new(BandwidthLimiter::new(&limit)));
+            limiter.set_limit(&limit);
+            limiter.clone()
+        })
+        .collect()
+    }
+
+    async fn forwarding_policy(&self, tunnel: &SSHTunnel) -> VesperResult<Arc<ForwardingPolicy>> {
+        let allowlist =
+            ClientAllowlist::parse(&tunnel.allowed_clients).map_err(VesperError::InvalidInput)?;
+        let counters = self
+            .tunnel_counters
+            .write()
+            .await
+            .entry(tunnel.id.clone())
+            .or_default()
+            .clone();
+
+        let limits = tunnel.client_limits.clone().unwrap_or_default();
+        let slots = limits
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max as usize)));
+
+        Ok(Arc::new(ForwardingPolicy {
+            tunnel_id: tunnel.id.clone(),
+            allowlist,
+            limiters: self.bandwidth_limiters(tunnel).await,
+            counters,
+            limits,
+            slots,
+        }))
+    }
+
+    pub async fn get_tunnel_stats(&self, id: &str) -> VesperResult<TunnelStats> {
+        if !self.tunnels.read().await.contains_key(id) {
+            return Err(VesperError::not_found("Tunnel", id));
+        }
+
+        Ok(self
+            .tunnel_counters
+            .read()
+            .await
+            .get(id)
+            .map(|counters| counters.snapshot())
+            .unwrap_or_default())
+    }
+
+    // Apply a changed tunnel or connection limit to streams that are already running
+    async fn refresh_bandwidth_limit(&self, id: &str) {
+        let limit = match self.tunnels.read().await.get(id) {
+            Some(tunnel) => tunnel.bandwidth.clone(),
+            None => self
+                .connections
+                .read()
+                .await
+                .get(id)
+                .and_then(|connection| connection.bandwidth.clone()),
+        };
+
+        if let Some(limiter) = self.bandwidth_limiters.read().await.get(id) {
+            limiter.set_limit(&limit.unwrap_or_default());
+        }
+    }
+
+    async fn session_pool(&self, connection_id: &str) -> Option<Arc<SessionPool>> {
+        self.session_pools.read().await.get(connection_id).cloned()
+    }
+
+    // Open every port mapping of a tunnel and record the ones that failed; any failure fails the tunnel
+    async fn open_port_mappings<T, F, Fut>(
+        &self,
+        tunnel: &SSHTunnel,
+        mut open: F,
+    ) -> VesperResult<Vec<T>>
+    where
+        F: FnMut(PortMapping) -> Fut,
+        Fut: Future<Output = VesperResult<T>>,
+    {
+        // Saved tunnels skip the command validators; a tunnel with no ports would have nothing to accept on
+        validate_port_mappings(tunnel).map_err(VesperError::InvalidInput)?;
+
+        let mut opened = Vec::new();
+        let mut errors = Vec::new();
+        let mut first_error = None;
+        for mapping in port_mappings(tunnel) {
+            match open(mapping).await {
+                Ok(port) => opened.push(port),
+                Err(error) => {
+                    log::error!(
+                        "Tunnel {} failed to forward port {} -> {}: {}",
+                        tunnel.id,
+                        mapping.local_port,
+                        mapping.remote_port,
+                        error.full_message()
+                    );
+                    errors.push(PortError::new(mapping, error.full_message()));
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+
+        if let Some(tunnel) = self.tunnels.write().await.get_mut(&tunnel.id) {
+            tunnel.port_errors = errors;
+        }
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(opened),
+        }
+    }
+
+    // Bind an on-demand tunnel's listener; the SSH session is opened by its first client
+    async fn start_on_demand_tunnel(&self, tunnel: SSHTunnel) -> VesperResult<()> {
+        self.stop_active_tunnel(&tunnel.id, TunnelControl::Stop)
+            .await;
+
+        let active_tunnel = start_on_demand_forwarding(self.clone(), tunnel.clone(), None).await?;
+        self.active_tunnels
+            .write()
+            .await
+            .insert(tunnel.id.clone(), active_tunnel);
+        self.set_tunnel_status(&tunnel.id, TunnelStatus::Standby)
+            .await;
+        Ok(())
+    }
+
+    // Open or reuse the connection's session for a standby tunnel's first client
+    async fn wake_on_demand_tunnel(
+        &self,
+        tunnel: &SSHTunnel,
+    ) -> VesperResult<Arc<AsyncSession<TokioTcpStream>>> {
+        let connection_id = &tunnel.connection_id;
+        let was_connected = self.ssh_sessions.read().await.contains_key(connection_id);
+        self.ensure_ssh_session(connection_id).await?;
+        if !was_connected {
+            self.on_demand_sessions
+                .write()
+                .await
+                .insert(connection_id.clone());
+        }
+
+        let session = self
+            .ssh_sessions
+            .read()
+            .await
+            .get(connection_id)
+            .cloned()
+            .ok_or_else(|| VesperError::NotConnected(connection_id.clone()))?;
+
+        log::info!("On-demand tunnel {} is active", tunnel.id);
+        self.set_tunnel_status(&tunnel.id, TunnelStatus::Active)
+            .await;
+        self.attach_probe(&tunnel.id).await;
+        if let Err(e) = self.save_to_storage().await {
+            log::error!("Failed to save data: {}", e);
+        }
+        Ok(session)
+    }
+
+    // An on-demand tunnel has had no clients for its idle timeout
+    async fn on_demand_tunnel_idle(&self, tunnel: &SSHTunnel) {
+        log::info!("On-demand tunnel {} is idle, going to standby", tunnel.id);
+        self.set_tunnel_status(&tunnel.id, TunnelStatus::Standby)
+            .await;
+        self.attach_probe(&tunnel.id).await;
+        self.release_on_demand_session(&tunnel.connection_id).await;
+
+        if let Err(e) = self.save_to_storage().await {
+            log::error!("Failed to save data: {}", e);
+        }
+    }
+
+    // Close a session opened for on-demand tunnels once no tunnel or terminal uses it
+    async fn release_on_demand_session(&self, connection_id: &str) {
+        if !self.on_demand_sessions.read().await.contains(connection_id) {
+            return;
+        }
+
+        let tunnels_running = self.tunnels.read().await.values().any(|tunnel| {
+            tunnel.connection_id == connection_id
+                && matches!(tunnel.status, TunnelStatus::Active | TunnelStatus::Degraded)
+        });
+        let terminals_open = self
+            .terminals
+            .read()
+            .await
+            .values()
+            .any(|terminal| terminal.info.connection_id == connection_id);
+        let snippets_running = self
+            .running_snippets
+            .read()
+            .await
+            .values()
+            .any(|run| run.connection_id == connection_id);
+        let transfers_running = self
+            .transfers
+            .read()
+            .await
+            .values()
+            .any(|transfer| transfer.connection_id == connection_id);
+        if tunnels_running || terminals_open || snippets_running || transfers_running {
+            return;
+        }
+
+        log::info!(
+            "Closing SSH session of connection {}: its on-demand tunnels are idle",
+            connection_id
+        );
+        self.close_ssh_session(connection_id, "On-demand tunnels idle")
+            .await;
+        if let Some(connection) = self.connections.write().await.get_mut(connection_id) {
+            connection.status = ConnectionStatus::Disconnected;
+        }
+    }
+
+    // A wake dropped because its tunnel stopped may leave the connection marked as connecting
+    async fn abandon_on_demand_wake(&self, connection_id: &str) {
+        if self.ssh_sessions.read().await.contains_key(connection_id)
+            || self
+                .reconnecting_connections
+                .read()
+                .await
+                .contains(connection_id)
+        {
+            return;
+        }
+
+        if let Some(connection) = self.connections.write().await.get_mut(connection_id) {
+            if matches!(connection.status, ConnectionStatus::Connecting) {
+                connection.status = ConnectionStatus::Disconnected;
+            }
+        }
+    }
+
     async fn set_tunnel_status(&self, id: &str, status: TunnelStatus) {
         let mut tunnels = self.tunnels.write().await;
         if let Some(tunnel) = tunnels.get_mut(id) {
@@ -694,6 +2082,22 @@ impl ConnectionManager {
     }
 
     async fn close_ssh_session(&self, id: &str, description: &str) {
+        self.on_demand_sessions.write().await.remove(id);
+        self.close_terminals_for_connection(id).await;
+        self.transfers.write().await.retain(|_, transfer| {
+            let keep = transfer.connection_id != id;
+            if !keep {
+                let _ = transfer.cancel_tx.send(true);
+            }
+            keep
+        });
+        self.sftp_sessions.write().await.remove(id);
+        self.metrics.write().await.remove(id);
+        let pool = self.session_pools.write().await.remove(id);
+        if let Some(pool) = pool {
+            pool.close().await;
+        }
+
         let session = {
             let mut sessions = self.ssh_sessions.write().await;
             sessions.remove(id)
@@ -701,20 +2105,28 @@ impl ConnectionManager {
 
         if let Some(session) = session {
             if let Err(err) = session.disconnect(None, description, None).await {
-                eprintln!("Failed to disconnect SSH session {} cleanly: {}", id, err);
+                log::error!("Failed to disconnect SSH session {} cleanly: {}", id, err);
             }
         }
     }
 
     async fn stop_tunnels_for_connection(&self, connection_id: &str, signal: TunnelControl) {
-        let tunnel_ids: Vec<String> = {
+        let mut tunnel_ids = Vec::new();
+        {
             let active_tunnels = self.active_tunnels.read().await;
-            active_tunnels
+            let for_connection = active_tunnels
                 .iter()
-                .filter(|(_, tunnel)| tunnel.tunnel.connection_id == connection_id)
-                .map(|(tunnel_id, _)| tunnel_id.clone())
-                .collect()
-        };
+                .filter(|(_, tunnel)| tunnel.tunnel.connection_id == connection_id);
+            for (tunnel_id, active_tunnel) in for_connection {
+                match (&signal, &active_tunnel.session_lost) {
+                    // On-demand tunnels keep listening and open a new session for their next client
+                    (TunnelControl::ConnectionLost(_), Some(session_lost)) => {
+                        session_lost.notify_one()
+                    }
+                    _ => tunnel_ids.push(tunnel_id.clone()),
+                }
+            }
+        }
 
         for tunnel_id in tunnel_ids {
             self.stop_active_tunnel(&tunnel_id, signal.clone()).await;
@@ -731,11 +2143,13 @@ impl ConnectionManager {
             return false;
         };
 
-        println!(
+        log::info!(
             "Stopping {:?} tunnel: {}",
-            active_tunnel.tunnel.tunnel_type, tunnel_id
+            active_tunnel.tunnel.tunnel_type,
+            tunnel_id
         );
 
+        active_tunnel.probe_task.take();
         if let Some(shutdown_tx) = active_tunnel.shutdown_tx.take() {
             let _ = shutdown_tx.send(signal);
         }
@@ -750,12 +2164,12 @@ impl ConnectionManager {
             Ok(join_result) => {
                 if let Err(err) = join_result {
                     if !err.is_cancelled() {
-                        eprintln!("Tunnel task {} exited with error: {}", tunnel_id, err);
+                        log::error!("Tunnel task {} exited with error: {}", tunnel_id, err);
                     }
                 }
             }
             Err(_) => {
-                eprintln!(
+                log::warn!(
                     "Tunnel {} did not exit in time, aborting the task",
                     tunnel_id
                 );
@@ -779,34 +2193,44 @@ impl ConnectionManager {
                     .await;
             }
             TunnelExitReason::TunnelError(message) => {
-                eprintln!("Tunnel {} exited with an error: {}", tunnel.id, message);
+                log::error!("Tunnel {} exited with an error: {}", tunnel.id, message);
                 self.set_tunnel_status(&tunnel.id, TunnelStatus::Error)
                     .await;
             }
             TunnelExitReason::ConnectionLost(message) => {
-                eprintln!(
+                log::warn!(
                     "Tunnel {} detected SSH session loss: {}",
-                    tunnel.id, message
+                    tunnel.id,
+                    message
                 );
                 self.set_tunnel_status(&tunnel.id, TunnelStatus::Error)
                     .await;
-                self.handle_connection_failure(&tunnel.connection_id, message)
-                    .await;
+                self.handle_connection_failure(
+                    &tunnel.connection_id,
+                    message,
+                    FailureCause::SessionLost,
+                )
+                .await;
             }
         }
 
         if let Err(err) = self.save_to_storage().await {
-            eprintln!("Failed to save data: {}", err);
+            log::error!("Failed to save data: {}", err);
         }
     }
 
-    async fn handle_connection_failure(&self, id: &str, reason: String) {
+    async fn handle_connection_failure(&self, id: &str, reason: String, cause: FailureCause) {
+        // Sessions opened by an on-demand tunnel are reopened by its next client instead
+        let reconnect_session = cause == FailureCause::NetworkEvent
+            && !self.on_demand_sessions.read().await.contains(id);
         let restart_tunnel_ids: Vec<String> = {
             let active_tunnels = self.active_tunnels.read().await;
             active_tunnels
                 .values()
                 .filter(|active_tunnel| {
-                    active_tunnel.tunnel.connection_id == id && active_tunnel.tunnel.auto_reconnect
+                    active_tunnel.tunnel.connection_id == id
+                        && active_tunnel.tunnel.auto_reconnect
+                        && active_tunnel.session_lost.is_none()
                 })
                 .map(|active_tunnel| active_tunnel.tunnel.id.clone())
                 .collect()
@@ -816,29 +2240,32 @@ impl ConnectionManager {
             .await;
         self.close_ssh_session(id, "SSH session lost").await;
 
-        {
+        let was_connected = {
             let mut connections = self.connections.write().await;
-            if let Some(connection) = connections.get_mut(id) {
-                connection.status = ConnectionStatus::Error;
-            } else {
+            let Some(connection) = connections.get_mut(id) else {
                 return;
-            }
-        }
+            };
+            let was_connected = matches!(connection.status, ConnectionStatus::Connected);
+            connection.status = ConnectionStatus::Error;
+            was_connected
+        };
 
         if let Err(err) = self.save_to_storage().await {
-            eprintln!("Failed to save data: {}", err);
+            log::error!("Failed to save data: {}", err);
         }
 
-        if !restart_tunnel_ids.is_empty() {
-            self.spawn_connection_reconnect(id.to_string(), reason, restart_tunnel_ids);
+        if (reconnect_session && was_connected) || !restart_tunnel_ids.is_empty() {
+            self.spawn_connection_reconnect(id.to_string(), reason, restart_tunnel_ids, cause);
         }
     }
 
+    // After a network event the first attempt is made right away; later ones back off
     fn spawn_connection_reconnect(
         &self,
         id: String,
         reason: String,
         restart_tunnel_ids: Vec<String>,
+        cause: FailureCause,
     ) {
         let manager = self.clone();
         tokio::spawn(async move {
@@ -848,13 +2275,14 @@ impl ConnectionManager {
             };
 
             if !should_reconnect {
-                eprintln!("Reconnect for connection {} is already in progress", id);
+                log::warn!("Reconnect for connection {} is already in progress", id);
                 return;
             }
 
-            eprintln!(
+            log::warn!(
                 "Attempting to reconnect connection {} after failure: {}",
-                id, reason
+                id,
+                reason
             );
 
             {
@@ -868,32 +2296,80 @@ impl ConnectionManager {
             }
 
             if let Err(err) = manager.save_to_storage().await {
-                eprintln!("Failed to save data: {}", err);
+                log::error!("Failed to save data: {}", err);
             }
 
-            tokio::time::sleep(Duration::from_secs(RECONNECT_DELAY_SECS)).await;
+            let mut reconnected = false;
+            for attempt in 0..RECONNECT_MAX_ATTEMPTS {
+                tokio::time::sleep(reconnect_delay(attempt, cause)).await;
+                if !manager.network.is_online() {
+                    log::info!(
+                        "Waiting for the network before reconnecting connection {}",
+                        id
+                    );
+                }
+                manager.network.wait_online().await;
+
+                // 用户在重试期间断开或删除了连接
+                let still_wanted =
+                    manager
+                        .connections
+                        .read()
+                        .await
+                        .get(&id)
+                        .is_some_and(|connection| {
+                            matches!(connection.status, ConnectionStatus::Connecting)
+                        });
+                if !still_wanted {
+                    break;
+                }
 
-            let reconnect_result = manager.ensure_ssh_session(&id).await;
-            if !reconnect_result.success {
-                eprintln!(
-                    "Failed to reconnect connection {}: {}",
-                    id, reconnect_result.message
-                );
+                match manager.ensure_ssh_session(&id).await {
+                    Ok(()) => {
+                        reconnected = true;
+                        break;
+                    }
+                    Err(error) => {
+                        log::warn!(
+                            "Reconnect attempt {} of {} for connection {} failed: {}",
+                            attempt + 1,
+                            RECONNECT_MAX_ATTEMPTS,
+                            id,
+                            error.full_message()
+                        );
+                        if !reconnect_can_succeed(&error) {
+                            break;
+                        }
+                        // ensure_ssh_session marked it failed; it's still being retried
+                        let mut connections = manager.connections.write().await;
+                        if let Some(connection) = connections.get_mut(&id) {
+                            connection.status = ConnectionStatus::Connecting;
+                        }
+                    }
+                }
+            }
+
+            if !reconnected {
+                log::error!("Giving up reconnecting connection {}", id);
                 let mut connections = manager.connections.write().await;
                 if let Some(connection) = connections.get_mut(&id) {
-                    connection.status = ConnectionStatus::Error;
+                    if matches!(connection.status, ConnectionStatus::Connecting) {
+                        connection.status = ConnectionStatus::Error;
+                    }
                 }
-            } else if let Err(error) = manager.start_tunnels_by_ids(&id, &restart_tunnel_ids).await {
-                eprintln!(
+            } else if let Err(error) = manager.start_tunnels_by_ids(&id, &restart_tunnel_ids).await
+            {
+                log::error!(
                     "SSH reconnected for connection {}, but failed to restart tunnels: {}",
-                    id, error
+                    id,
+                    error
                 );
             } else {
-                eprintln!("Successfully reconnected connection {}", id);
+                log::info!("Successfully reconnected connection {}", id);
             }
 
             if let Err(err) = manager.save_to_storage().await {
-                eprintln!("Failed to save data: {}", err);
+                log::error!("Failed to save data: {}", err);
             }
 
             manager.reconnecting_connections.write().await.remove(&id);
@@ -901,10 +2377,57 @@ impl ConnectionManager {
     }
 }
 
-// Establish a real SSH session
-async fn establish_ssh_session(
+// Wait before a reconnect attempt, doubling each time up to a limit
+fn reconnect_delay(attempt: u32, cause: FailureCause) -> Duration {
+    let step = match cause {
+        FailureCause::NetworkEvent if attempt == 0 => return Duration::ZERO,
+        FailureCause::NetworkEvent => attempt - 1,
+        FailureCause::SessionLost => attempt,
+    };
+    let delay = RECONNECT_DELAY_SECS.saturating_mul(1 << step.min(16));
+    Duration::from_secs(delay.min(RECONNECT_MAX_DELAY_SECS))
+}
+
+// Retrying can't fix missing or rejected credentials
+fn reconnect_can_succeed(error: &VesperError) -> bool {
+    !matches!(
+        error,
+        VesperError::NotFound { .. }
+            | VesperError::PasswordMissing
+            | VesperError::KeyPathMissing
+            | VesperError::KeyFileNotFound(_)
+            | VesperError::Auth(_)
+    )
+}
+
+// A keepalive only queues a packet; opening a channel needs an answer from the server
+pub(crate) async fn verify_session(session: &AsyncSession<TokioTcpStream>) -> Result<(), String> {
+    let reply = timeout(
+        Duration::from_secs(SESSION_VERIFY_TIMEOUT_SECS),
+        session.channel_session(),
+    )
+    .await;
+    match reply {
+        Ok(Ok(mut channel)) => {
+            if let Err(e) = channel.close().await {
+                log::debug!("Failed to close check channel: {}", e);
+            }
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("no reply from server".to_string()),
+    }
+}
+
+enum Credentials<'a> {
+    Password(&'a str),
+    KeyFile(&'a Path),
+}
+
+// Establish an authenticated SSH session with a single TCP connect and handshake
+pub(crate) async fn establish_ssh_session(
     connection: &SSHConnection,
-) -> Result<AsyncSession<TokioTcpStream>, String> {
+) -> VesperResult<AsyncSession<TokioTcpStream>> {
     match timeout(
         Duration::from_secs(SSH_CONNECT_TIMEOUT_SECS),
         establish_ssh_session_inner(connection),
@@ -912,74 +2435,64 @@ async fn establish_ssh_session(
     .await
     {
         Ok(result) => result,
-        Err(_) => Err(format!(
-            "SSH connection timed out after {} seconds",
-            SSH_CONNECT_TIMEOUT_SECS
-        )),
+        Err(_) => Err(VesperError::Timeout(SSH_CONNECT_TIMEOUT_SECS)),
     }
 }
 
 async fn establish_ssh_session_inner(
     connection: &SSHConnection,
-) -> Result<AsyncSession<TokioTcpStream>, String> {
-    let connection = connection.clone();
+) -> VesperResult<AsyncSession<TokioTcpStream>> {
+    // Validate credentials before touching the network
+    let credentials = match connection.auth_method {
+        AuthMethod::Password => match &connection.password {
+            Some(password) => Credentials::Password(password),
+            None => return Err(VesperError::PasswordMissing),
+        },
+        AuthMethod::Key => match &connection.key_path {
+            Some(key_path) if Path::new(key_path).exists() => {
+                Credentials::KeyFile(Path::new(key_path))
+            }
+            Some(key_path) => return Err(VesperError::KeyFileNotFound(key_path.clone())),
+            None => return Err(VesperError::KeyPathMissing),
+        },
+    };
 
     // Try to establish TCP connection
     let tcp_addr = format!("{}:{}", connection.host, connection.port);
-    let tcp = match TcpStream::connect(&tcp_addr).await {
-        Ok(stream) => stream,
-        Err(e) => {
-            return Err(format!("TCP connection failed {}: {}", tcp_addr, e));
-        }
-    };
+    let tcp = TcpStream::connect(&tcp_addr)
+        .await
+        .map_err(|source| VesperError::Tcp {
+            addr: tcp_addr.clone(),
+            source,
+        })?;
 
-    let mut session = match AsyncSession::new(tcp, Some(build_session_configuration())) {
-        Ok(session) => session,
-        Err(e) => {
-            return Err(format!("Failed to create SSH session: {}", e));
-        }
-    };
+    let mut session = AsyncSession::new(tcp, Some(build_session_configuration()))
+        .map_err(VesperError::SshSession)?;
 
     // Perform SSH handshake
-    if let Err(e) = session.handshake().await {
-        return Err(format!("SSH handshake failed: {}", e));
-    }
+    session.handshake().await.map_err(VesperError::handshake)?;
 
     // Try user authentication
-    let auth_result = match connection.auth_method {
-        AuthMethod::Password => {
-            if let Some(password) = &connection.password {
-                session
-                    .userauth_password(&connection.username, password)
-                    .await
-            } else {
-                return Err("Password authentication requires a password".to_string());
-            }
+    let auth_result = match credentials {
+        Credentials::Password(password) => {
+            session
+                .userauth_password(&connection.username, password)
+                .await
         }
-        AuthMethod::Key => {
-            if let Some(key_path) = &connection.key_path {
-                // Check if the key file exists
-                if !Path::new(key_path).exists() {
-                    return Err(format!("Private key file not found: {}", key_path));
-                }
-
-                // Try to authenticate using private key file
-                session
-                    .userauth_pubkey_file(&connection.username, None, Path::new(key_path), None)
-                    .await
-            } else {
-                return Err("Key authentication requires a key file path".to_string());
-            }
+        Credentials::KeyFile(key_path) => {
+            session
+                .userauth_pubkey_file(&connection.username, None, key_path, None)
+                .await
         }
     };
 
     if let Err(e) = auth_result {
-        return Err(format!("SSH authentication failed: {}", e));
+        return Err(VesperError::Auth(Some(e)));
     }
 
     // Verify authentication
     if !session.authenticated() {
-        return Err("SSH authentication failed".to_string());
+        return Err(VesperError::Auth(None));
     }
 
     Ok(session)
@@ -998,68 +2511,129 @@ fn create_local_tunnel_listener(port: u16) -> std::io::Result<TcpListener> {
     socket.listen(1024)
 }
 
+fn bind_local_tunnel_listener(port: u16) -> VesperResult<TcpListener> {
+    match create_local_tunnel_listener(port) {
+        Ok(listener) => Ok(listener),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Err(VesperError::PortInUse {
+            side: "Local",
+            port,
+        }),
+        Err(source) => Err(VesperError::PortBind { port, source }),
+    }
+}
+
+async fn bind_local_ports(
+    manager: &ConnectionManager,
+    tunnel: &SSHTunnel,
+) -> VesperResult<Vec<LocalPort>> {
+    manager
+        .open_port_mappings(tunnel, |mapping| async move {
+            Ok(LocalPort {
+                mapping,
+                listener: bind_local_tunnel_listener(mapping.local_port)?,
+            })
+        })
+        .await
+}
+
 async fn start_local_forwarding(
     manager: ConnectionManager,
     tunnel: SSHTunnel,
     session: Arc<AsyncSession<TokioTcpStream>>,
-) -> Result<ActiveTunnel, String> {
-    println!(
+) -> VesperResult<ActiveTunnel> {
+    log::info!(
         "Creating SSH tunnel: {} -> {}:{} (tunnel: {})",
-        tunnel.name, tunnel.remote_host, tunnel.remote_port, tunnel.name
+        tunnel.name,
+        tunnel.remote_host,
+        tunnel.remote_port,
+        tunnel.name
     );
+    let policy = manager.forwarding_policy(&tunnel).await?;
+    let ports = bind_local_ports(&manager, &tunnel).await?;
+    for port in &ports {
+        log::info!(
+            "Local tunnel listening on {}",
+            port.listener.local_addr().unwrap()
+        );
+    }
 
-    let listener = match create_local_tunnel_listener(tunnel.local_port) {
-        Ok(listener) => listener,
-        Err(e) => {
-            if e.kind() == std::io::ErrorKind::AddrInUse {
-                return Err(format!(
-                    "Local port {} is already in use",
-                    tunnel.local_port
-                ));
-            }
-            return Err(format!(
-                "Failed to bind local tunnel port {}: {}",
-                tunnel.local_port, e
-            ));
-        }
-    };
-
-    let local_addr = listener.local_addr().unwrap();
-    println!("Local tunnel listening on {}", local_addr);
-
+    // Forwarded channels are spread over the connection's session pool when it has one
+    let pool = manager.session_pool(&tunnel.connection_id).await;
     let tunnel_for_task = tunnel.clone();
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
     let handle = tokio::spawn(async move {
-        run_local_forwarding_loop(manager, tunnel_for_task, listener, session, shutdown_rx).await;
+        run_local_forwarding_loop(
+            manager,
+            tunnel_for_task,
+            ports,
+            session,
+            pool,
+            policy,
+            shutdown_rx,
+        )
+        .await;
     });
 
-    println!(
+    log::info!(
         "SSH tunnel created successfully for {}: {}",
-        tunnel.name, tunnel.id
+        tunnel.name,
+        tunnel.id
     );
 
     Ok(ActiveTunnel {
         tunnel,
         shutdown_tx: Some(shutdown_tx),
         task_handle: handle,
+        probe_task: None,
+        session_lost: None,
     })
 }
 
 // Handle a single local forwarding connection
 async fn handle_local_connection(
     session: Arc<AsyncSession<TokioTcpStream>>,
+    pool: Option<Arc<SessionPool>>,
+    policy: &ForwardingPolicy,
     local_stream: &mut TcpStream,
     remote_host: &str,
     remote_port: u16,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Create SSH channel to remote host
-    let mut channel = session
-        .channel_direct_tcpip(remote_host, remote_port, None)
-        .await?;
+    let Some(_slot) = policy.client_slot().await else {
+        return Ok(());
+    };
+
+    // Create SSH channel to remote host, falling back to the primary session if a pooled one fails
+    let lease = pool.map(|pool| {
+        let lease = pool.acquire();
+        (pool, lease)
+    });
+    let mut channel = match &lease {
+        Some((pool, lease)) if !lease.is_primary() => {
+            match lease
+                .session()
+                .channel_direct_tcpip(remote_host, remote_port, None)
+                .await
+            {
+                Ok(channel) => channel,
+                Err(err) => {
+                    log::debug!("Pooled session failed to open a channel: {}", err);
+                    pool.check(lease);
+                    session
+                        .channel_direct_tcpip(remote_host, remote_port, None)
+                        .await?
+                }
+            }
+        }
+        _ => {
+            session
+                .channel_direct_tcpip(remote_host, remote_port, None)
+                .await?
+        }
+    };
 
     // Copy data bidirectionally
-    if let Err(err) = tokio::io::copy_bidirectional(&mut channel, local_stream).await {
-        eprintln!(
+    if let Err(err) = policy.forward_client(&mut channel, local_stream).await {
+        log::debug!(
             "Copying data between local stream and SSH tunnel failed: {:?}",
             err
         );
@@ -1068,78 +2642,188 @@ async fn handle_local_connection(
     Ok(())
 }
 
-async fn start_remote_forwarding(
+fn spawn_local_client(
+    workers: &mut JoinSet<()>,
+    session: Arc<AsyncSession<TokioTcpStream>>,
+    pool: Option<Arc<SessionPool>>,
+    policy: Arc<ForwardingPolicy>,
+    mut local_stream: TcpStream,
+    remote_host: &str,
+    remote_port: u16,
+) {
+    let remote_host = remote_host.to_string();
+    workers.spawn(async move {
+        if let Err(err) = handle_local_connection(
+            session,
+            pool,
+            &policy,
+            &mut local_stream,
+            &remote_host,
+            remote_port,
+        )
+        .await
+        {
+            log::warn!("Tunnel connection error: {}", err);
+        }
+    });
+}
+
+async fn start_on_demand_forwarding(
     manager: ConnectionManager,
     tunnel: SSHTunnel,
-    session: Arc<AsyncSession<TokioTcpStream>>,
-) -> Result<ActiveTunnel, String> {
-    let local_addr = format!("127.0.0.1:{}", tunnel.local_port);
+    session: Option<Arc<AsyncSession<TokioTcpStream>>>,
+) -> VesperResult<ActiveTunnel> {
+    let policy = manager.forwarding_policy(&tunnel).await?;
+    let ports = bind_local_ports(&manager, &tunnel).await?;
+    for port in &ports {
+        log::info!(
+            "On-demand tunnel {} listening on {}",
+            tunnel.name,
+            port.listener.local_addr().unwrap()
+        );
+    }
 
-    println!(
-        "Creating remote forwarding: remote:{} -> local:{} (tunnel: {})",
-        tunnel.remote_port, tunnel.local_port, tunnel.name
-    );
+    let tunnel_for_task = tunnel.clone();
+    let session_lost = Arc::new(Notify::new());
+    let session_lost_for_task = session_lost.clone();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let handle = tokio::spawn(async move {
+        run_on_demand_forwarding_loop(
+            manager,
+            tunnel_for_task,
+            ports,
+            session,
+            policy,
+            session_lost_for_task,
+            shutdown_rx,
+        )
+        .await;
+    });
 
-    let (listener, _) = match session
-        .channel_forward_listen(tunnel.remote_port, None, None)
-        .await
-    {
-        Ok(listener) => listener,
-        Err(e) => {
-            let raw_error = e.to_string();
-            let error_msg = if raw_error
-                .to_ascii_lowercase()
-                .contains("address already in use")
-            {
-                format!("Remote port {} is already in use", tunnel.remote_port)
-            } else {
-                format!(
-                    "Failed to create remote forwarding for tunnel {}: {}",
-                    tunnel.name, raw_error
-                )
-            };
-            eprintln!("{}", error_msg);
-            return Err(error_msg);
-        }
-    };
+    Ok(ActiveTunnel {
+        tunnel,
+        shutdown_tx: Some(shutdown_tx),
+        task_handle: handle,
+        probe_task: None,
+        session_lost: Some(session_lost),
+    })
+}
+
+async fn start_remote_forwarding(
+    manager: ConnectionManager,
+    tunnel: SSHTunnel,
+    session: Arc<AsyncSession<TokioTcpStream>>,
+) -> VesperResult<ActiveTunnel> {
+    let listeners = manager
+        .open_port_mappings(&tunnel, |mapping| {
+            let session = session.clone();
+            let tunnel_name = tunnel.name.clone();
+            async move {
+                log::info!(
+                    "Creating remote forwarding: remote:{} -> local:{} (tunnel: {})",
+                    mapping.remote_port,
+                    mapping.local_port,
+                    tunnel_name
+                );
+                let listener =
+                    open_remote_listener(&session, &tunnel_name, mapping.remote_port).await?;
+                Ok((mapping, listener))
+            }
+        })
+        .await?;
 
+    let policy = manager.forwarding_policy(&tunnel).await?;
     let tunnel_for_task = tunnel.clone();
     let (shutdown_tx, shutdown_rx) = oneshot::channel();
     let handle = tokio::spawn(async move {
         run_remote_forwarding_loop(
             manager,
             tunnel_for_task,
-            listener,
-            local_addr,
+            listeners,
             session,
+            policy,
             shutdown_rx,
         )
         .await;
     });
 
-    println!(
+    log::info!(
         "Remote forwarding created successfully for {}: {}",
-        tunnel.name, tunnel.id
+        tunnel.name,
+        tunnel.id
     );
 
     Ok(ActiveTunnel {
         tunnel,
         shutdown_tx: Some(shutdown_tx),
         task_handle: handle,
+        probe_task: None,
+        session_lost: None,
     })
 }
 
+async fn open_remote_listener(
+    session: &AsyncSession<TokioTcpStream>,
+    tunnel_name: &str,
+    remote_port: u16,
+) -> VesperResult<AsyncListener<TokioTcpStream>> {
+    match session
+        .channel_forward_listen(remote_port, None, None)
+        .await
+    {
+        Ok((listener, _)) => Ok(listener),
+        Err(e) => {
+            let error = if e
+                .to_string()
+                .to_ascii_lowercase()
+                .contains("address already in use")
+            {
+                VesperError::PortInUse {
+                    side: "Remote",
+                    port: remote_port,
+                }
+            } else {
+                VesperError::RemoteForward {
+                    tunnel: tunnel_name.to_string(),
+                    source: e,
+                }
+            };
+            log::error!("{}", error.full_message());
+            Err(error)
+        }
+    }
+}
+
+// Wait for a forwarded channel on any of the remote ports
+async fn accept_remote(
+    listeners: &mut [(PortMapping, AsyncListener<TokioTcpStream>)],
+) -> (
+    PortMapping,
+    Result<AsyncChannel<TokioTcpStream>, async_ssh2_lite::Error>,
+) {
+    let accepts = listeners.iter_mut().map(|(mapping, listener)| {
+        let mapping = *mapping;
+        Box::pin(async move { (mapping, listener.accept().await) })
+    });
+    select_all(accepts).await.0
+}
+
 // Handle a single remote forwarding connection
 async fn handle_remote_connection(
     mut channel: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
     local_addr: &str,
+    policy: &ForwardingPolicy,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(_slot) = policy.client_slot().await else {
+        return Ok(());
+    };
+
     // Connect to local service
     let mut local_stream = TcpStream::connect(local_addr).await?;
 
     // Copy data bidirectionally
-    if let Err(err) = tokio::io::copy_bidirectional(&mut channel, &mut local_stream).await {
-        eprintln!(
+    if let Err(err) = policy.forward_client(&mut channel, &mut local_stream).await {
+        log::debug!(
             "Copying data between Unix domain socket A and SSH tunnel failed: {:?}",
             err
         );
@@ -1151,16 +2835,16 @@ async fn handle_remote_connection(
 async fn run_local_forwarding_loop(
     manager: ConnectionManager,
     tunnel: SSHTunnel,
-    listener: TcpListener,
+    ports: Vec<LocalPort>,
     session: Arc<AsyncSession<TokioTcpStream>>,
+    pool: Option<Arc<SessionPool>>,
+    policy: Arc<ForwardingPolicy>,
     mut shutdown_rx: oneshot::Receiver<TunnelControl>,
 ) {
     let mut heartbeat = interval(Duration::from_secs(SSH_KEEPALIVE_INTERVAL_SECS));
     heartbeat.tick().await;
     let mut failure_count = 0;
     let mut workers = JoinSet::new();
-    let remote_host = tunnel.remote_host.clone();
-    let remote_port = tunnel.remote_port;
 
     let exit_reason = loop {
         tokio::select! {
@@ -1177,7 +2861,7 @@ async fn run_local_forwarding_loop(
                     }
                     Err(err) => {
                         failure_count += 1;
-                        eprintln!(
+                        log::error!(
                             "SSH keepalive failed for tunnel {} (attempt {}): {}",
                             tunnel.id, failure_count, err
                         );
@@ -1187,25 +2871,159 @@ async fn run_local_forwarding_loop(
                     }
                 }
             }
-            accept_result = listener.accept() => {
+            (mapping, accept_result) = accept_any(&ports) => {
                 match accept_result {
+                    Ok((local_stream, peer)) if policy.rejects(peer) => drop(local_stream),
                     Ok((local_stream, _)) => {
-                        let session = session.clone();
-                        let remote_host = remote_host.clone();
-                        workers.spawn(async move {
-                            let mut local_stream = local_stream;
-                            if let Err(err) = handle_local_connection(
-                                session,
-                                &mut local_stream,
-                                &remote_host,
-                                remote_port,
-                            )
-                            .await
-                            {
-                                eprintln!("Tunnel connection error: {}", err);
-                            }
-                        });
+                        spawn_local_client(
+                            &mut workers,
+                            session.clone(),
+                            pool.clone(),
+                            policy.clone(),
+                            local_stream,
+                            &tunnel.remote_host,
+                            mapping.remote_port,
+                        );
+                    }
+                    Err(err) => {
+                        break TunnelExitReason::TunnelError(format!(
+                            "Failed to accept local connection: {}",
+                            err
+                        ));
+                    }
+                }
+            }
+            join_result = workers.join_next(), if !workers.is_empty() => {
+                if let Some(Err(err)) = join_result {
+                    if !err.is_cancelled() {
+                        log::error!("Tunnel worker for {} exited unexpectedly: {}", tunnel.id, err);
+                    }
+                }
+            }
+        }
+    };
+
+    workers.abort_all();
+    while let Some(join_result) = workers.join_next().await {
+        if let Err(err) = join_result {
+            if !err.is_cancelled() {
+                log::error!(
+                    "Tunnel worker for {} exited unexpectedly: {}",
+                    tunnel.id,
+                    err
+                );
+            }
+        }
+    }
+
+    manager
+        .handle_tunnel_runtime_exit(tunnel, exit_reason)
+        .await;
+}
+
+// Like the local forwarding loop, but the SSH session is only held while clients are connected
+async fn run_on_demand_forwarding_loop(
+    manager: ConnectionManager,
+    tunnel: SSHTunnel,
+    ports: Vec<LocalPort>,
+    mut session: Option<Arc<AsyncSession<TokioTcpStream>>>,
+    policy: Arc<ForwardingPolicy>,
+    session_lost: Arc<Notify>,
+    mut shutdown_rx: oneshot::Receiver<TunnelControl>,
+) {
+    let idle_timeout = tunnel.on_demand.as_ref().map_or(
+        Duration::from_secs(DEFAULT_ON_DEMAND_IDLE_SECS),
+        OnDemand::idle_timeout,
+    );
+    let mut pool = match session {
+        Some(_) => manager.session_pool(&tunnel.connection_id).await,
+        None => None,
+    };
+    let mut idle_since = Instant::now();
+    let mut heartbeat = interval(Duration::from_secs(SSH_KEEPALIVE_INTERVAL_SECS));
+    heartbeat.tick().await;
+    let mut failure_count = 0;
+    let mut workers = JoinSet::new();
+    // Opening the session runs alongside everything else so a stop isn't held up by it
+    let mut waking: Option<BoxFuture<'static, VesperResult<Arc<AsyncSession<TokioTcpStream>>>>> =
+        None;
+    let mut waiting_clients: Vec<(TcpStream, SocketAddr, u16)> = Vec::new();
+
+    let exit_reason = loop {
+        tokio::select! {
+            signal = &mut shutdown_rx => {
+                match signal {
+                    Ok(TunnelControl::Stop) | Err(_) => break TunnelExitReason::Stopped,
+                    Ok(TunnelControl::ConnectionLost(message)) => break TunnelExitReason::ConnectionLost(message),
+                }
+            }
+            // The connection failed somewhere else; its session is already being closed
+            _ = session_lost.notified() => {
+                if session.take().is_some() {
+                    log::info!("On-demand tunnel {} lost its session, going to standby", tunnel.id);
+                    workers.abort_all();
+                    pool = None;
+                    failure_count = 0;
+                    manager.set_tunnel_status(&tunnel.id, TunnelStatus::Standby).await;
+                    manager.attach_probe(&tunnel.id).await;
+                }
+            }
+            _ = heartbeat.tick(), if session.is_some() => {
+                let Some(current) = session.clone() else {
+                    continue;
+                };
+                match current.keepalive_send().await {
+                    Ok(_) => {
+                        failure_count = 0;
                     }
+                    Err(err) => {
+                        failure_count += 1;
+                        log::error!(
+                            "SSH keepalive failed for tunnel {} (attempt {}): {}",
+                            tunnel.id, failure_count, err
+                        );
+                        if failure_count >= SSH_KEEPALIVE_FAILURE_THRESHOLD {
+                            // Drop back to standby; the next client opens a new session
+                            workers.abort_all();
+                            session = None;
+                            pool = None;
+                            failure_count = 0;
+                            manager.set_tunnel_status(&tunnel.id, TunnelStatus::Standby).await;
+                            manager.attach_probe(&tunnel.id).await;
+                            manager
+                                .handle_connection_failure(
+                                    &tunnel.connection_id,
+                                    format!("SSH keepalive failed: {}", err),
+                                    FailureCause::SessionLost,
+                                )
+                                .await;
+                        }
+                    }
+                }
+            }
+            (mapping, accept_result) = accept_any(&ports) => {
+                match accept_result {
+                    Ok((local_stream, peer)) if policy.rejects(peer) => drop(local_stream),
+                    Ok((local_stream, peer)) => match &session {
+                        Some(session) => spawn_local_client(
+                            &mut workers,
+                            session.clone(),
+                            pool.clone(),
+                            policy.clone(),
+                            local_stream,
+                            &tunnel.remote_host,
+                            mapping.remote_port,
+                        ),
+                        None => {
+                            waiting_clients.push((local_stream, peer, mapping.remote_port));
+                            if waking.is_none() {
+                                let (manager, tunnel) = (manager.clone(), tunnel.clone());
+                                waking = Some(
+                                    async move { manager.wake_on_demand_tunnel(&tunnel).await }.boxed(),
+                                );
+                            }
+                        }
+                    },
                     Err(err) => {
                         break TunnelExitReason::TunnelError(format!(
                             "Failed to accept local connection: {}",
@@ -1214,12 +3032,47 @@ async fn run_local_forwarding_loop(
                     }
                 }
             }
+            woken = async { waking.as_mut().unwrap().await }, if waking.is_some() => {
+                waking = None;
+                match woken {
+                    Ok(opened) => {
+                        pool = manager.session_pool(&tunnel.connection_id).await;
+                        failure_count = 0;
+                        for (local_stream, _, remote_port) in waiting_clients.drain(..) {
+                            spawn_local_client(
+                                &mut workers,
+                                opened.clone(),
+                                pool.clone(),
+                                policy.clone(),
+                                local_stream,
+                                &tunnel.remote_host,
+                                remote_port,
+                            );
+                        }
+                        session = Some(opened);
+                    }
+                    Err(error) => {
+                        for (_, peer, _) in waiting_clients.drain(..) {
+                            log::warn!(
+                                "On-demand tunnel {} could not connect for client {}: {}",
+                                tunnel.id, peer, error.full_message()
+                            );
+                        }
+                    }
+                }
+            }
             join_result = workers.join_next(), if !workers.is_empty() => {
                 if let Some(Err(err)) = join_result {
                     if !err.is_cancelled() {
-                        eprintln!("Tunnel worker for {} exited unexpectedly: {}", tunnel.id, err);
+                        log::error!("Tunnel worker for {} exited unexpectedly: {}", tunnel.id, err);
                     }
                 }
+                idle_since = Instant::now();
+            }
+            _ = sleep_until(idle_since + idle_timeout), if session.is_some() && workers.is_empty() => {
+                session = None;
+                pool = None;
+                manager.on_demand_tunnel_idle(&tunnel).await;
             }
         }
     };
@@ -1228,25 +3081,34 @@ async fn run_local_forwarding_loop(
     while let Some(join_result) = workers.join_next().await {
         if let Err(err) = join_result {
             if !err.is_cancelled() {
-                eprintln!(
+                log::error!(
                     "Tunnel worker for {} exited unexpectedly: {}",
-                    tunnel.id, err
+                    tunnel.id,
+                    err
                 );
             }
         }
     }
+    if waking.take().is_some() {
+        manager.abandon_on_demand_wake(&tunnel.connection_id).await;
+    }
 
+    let connection_id = tunnel.connection_id.clone();
+    let stopped = matches!(exit_reason, TunnelExitReason::Stopped);
     manager
         .handle_tunnel_runtime_exit(tunnel, exit_reason)
         .await;
+    if stopped && session.is_some() {
+        manager.release_on_demand_session(&connection_id).await;
+    }
 }
 
 async fn run_remote_forwarding_loop(
     manager: ConnectionManager,
     tunnel: SSHTunnel,
-    mut listener: AsyncListener<TokioTcpStream>,
-    local_addr: String,
+    mut listeners: Vec<(PortMapping, AsyncListener<TokioTcpStream>)>,
     session: Arc<AsyncSession<TokioTcpStream>>,
+    policy: Arc<ForwardingPolicy>,
     mut shutdown_rx: oneshot::Receiver<TunnelControl>,
 ) {
     let mut heartbeat = interval(Duration::from_secs(SSH_KEEPALIVE_INTERVAL_SECS));
@@ -1269,7 +3131,7 @@ async fn run_remote_forwarding_loop(
                     }
                     Err(err) => {
                         failure_count += 1;
-                        eprintln!(
+                        log::error!(
                             "SSH keepalive failed for tunnel {} (attempt {}): {}",
                             tunnel.id, failure_count, err
                         );
@@ -1279,13 +3141,14 @@ async fn run_remote_forwarding_loop(
                     }
                 }
             }
-            accept_result = listener.accept() => {
+            (mapping, accept_result) = accept_remote(&mut listeners) => {
                 match accept_result {
                     Ok(channel) => {
-                        let local_addr = local_addr.clone();
+                        let local_addr = format!("127.0.0.1:{}", mapping.local_port);
+                        let policy = policy.clone();
                         workers.spawn(async move {
-                            if let Err(err) = handle_remote_connection(channel, &local_addr).await {
-                                eprintln!("Remote tunnel error: {}", err);
+                            if let Err(err) = handle_remote_connection(channel, &local_addr, &policy).await {
+                                log::warn!("Remote tunnel error: {}", err);
                             }
                         });
                     }
@@ -1300,7 +3163,7 @@ async fn run_remote_forwarding_loop(
             join_result = workers.join_next(), if !workers.is_empty() => {
                 if let Some(Err(err)) = join_result {
                     if !err.is_cancelled() {
-                        eprintln!("Remote tunnel worker for {} exited unexpectedly: {}", tunnel.id, err);
+                        log::error!("Remote tunnel worker for {} exited unexpectedly: {}", tunnel.id, err);
                     }
                 }
             }
@@ -1311,9 +3174,10 @@ async fn run_remote_forwarding_loop(
     while let Some(join_result) = workers.join_next().await {
         if let Err(err) = join_result {
             if !err.is_cancelled() {
-                eprintln!(
+                log::error!(
                     "Remote tunnel worker for {} exited unexpectedly: {}",
-                    tunnel.id, err
+                    tunnel.id,
+                    err
                 );
             }
         }
@@ -1325,159 +3189,47 @@ async fn run_remote_forwarding_loop(
 }
 
 // Test SSH connection
-pub async fn test_ssh_connection(connection: &SSHConnection) -> ConnectionResult {
-    match timeout(
-        Duration::from_secs(SSH_CONNECT_TIMEOUT_SECS),
-        test_ssh_connection_async(connection),
-    )
-    .await
+pub async fn test_ssh_connection(connection: &SSHConnection) -> VesperResult<ConnectionResult> {
+    let session = establish_ssh_session(connection).await?;
+    if let Err(err) = session
+        .disconnect(None, "Connection test finished", None)
+        .await
     {
-        Ok(result) => result,
-        Err(_) => ConnectionResult {
-            success: false,
-            message: format!(
-                "SSH connection test timed out after {} seconds",
-                SSH_CONNECT_TIMEOUT_SECS
-            ),
-            error_code: Some("TIMEOUT".to_string()),
-        },
-    }
-}
-
-// Async SSH connection test function
-async fn test_ssh_connection_async(connection: &SSHConnection) -> ConnectionResult {
-    // First validate key file path (if using key authentication)
-    if let AuthMethod::Key = connection.auth_method {
-        if let Some(key_path) = &connection.key_path {
-            if !Path::new(key_path).exists() {
-                return ConnectionResult {
-                    success: false,
-                    message: format!("Key file does not exist: {}", key_path),
-                    error_code: Some("KEY_FILE_NOT_FOUND".to_string()),
-                };
-            }
-        } else {
-            return ConnectionResult {
-                success: false,
-                message: "Key authentication requires specifying a key file path".to_string(),
-                error_code: Some("KEY_PATH_MISSING".to_string()),
-            };
-        }
-    }
-
-    // Try to establish TCP connection
-    let tcp_addr = format!("{}:{}", connection.host, connection.port);
-    let tcp = match TcpStream::connect(&tcp_addr).await {
-        Ok(stream) => stream,
-        Err(e) => {
-            let error_code = match e.kind() {
-                std::io::ErrorKind::ConnectionRefused => "CONNECTION_REFUSED",
-                std::io::ErrorKind::TimedOut => "CONNECTION_TIMEOUT",
-                std::io::ErrorKind::HostUnreachable => "HOST_UNREACHABLE",
-                _ => "TCP_CONNECTION_ERROR",
-            };
-
-            return ConnectionResult {
-                success: false,
-                message: format!(
-                    "Unable to connect to server {}:{} - {}",
-                    connection.host, connection.port, e
-                ),
-                error_code: Some(error_code.to_string()),
-            };
-        }
-    };
-
-    // Try to establish SSH session
-    let mut session = match AsyncSession::new(tcp, Some(build_session_configuration())) {
-        Ok(session) => session,
-        Err(e) => {
-            return ConnectionResult {
-                success: false,
-                message: format!("Failed to create SSH session: {}", e),
-                error_code: Some("SSH_SESSION_ERROR".to_string()),
-            };
-        }
-    };
-
-    // Perform SSH handshake
-    if let Err(e) = session.handshake().await {
-        return ConnectionResult {
-            success: false,
-            message: format!("SSH handshake failed: {}", e),
-            error_code: Some("SSH_HANDSHAKE_ERROR".to_string()),
-        };
+        log::error!("Failed to close test SSH session cleanly: {}", err);
     }
 
-    // Try user authentication
-    let auth_result = match connection.auth_method {
-        AuthMethod::Password => {
-            if let Some(password) = &connection.password {
-                session
-                    .userauth_password(&connection.username, password)
-                    .await
-            } else {
-                return ConnectionResult {
-                    success: false,
-                    message: "Password authentication requires providing a password".to_string(),
-                    error_code: Some("PASSWORD_MISSING".to_string()),
-                };
-            }
-        }
-        AuthMethod::Key => {
-            if let Some(key_path) = &connection.key_path {
-                // Check if the key file exists
-                if !Path::new(key_path).exists() {
-                    return ConnectionResult {
-                        success: false,
-                        message: format!("Private key file not found: {}", key_path),
-                        error_code: Some("KEY_FILE_NOT_FOUND".to_string()),
-                    };
-                }
+    Ok(ConnectionResult {
+        success: true,
+        message: "SSH connection test successful".to_string(),
+        error_code: None,
+    })
+}
 
-                // Try to authenticate using private key file
-                session
-                    .userauth_pubkey_file(&connection.username, None, Path::new(key_path), None)
-                    .await
-            } else {
-                return ConnectionResult {
-                    success: false,
-                    message: "Key authentication requires providing a key file path".to_string(),
-                    error_code: Some("KEY_PATH_MISSING".to_string()),
-                };
-            }
-        }
+// Record what is currently up so it can be restored on the next start
+fn session_state(
+    connections: &HashMap<String, SSHConnection>,
+    tunnels: &HashMap<String, SSHTunnel>,
+) -> SessionState {
+    let mut state = SessionState {
+        connections: connections
+            .values()
+            .filter(|connection| matches!(connection.status, ConnectionStatus::Connected))
+            .map(|connection| connection.id.clone())
+            .collect(),
+        tunnels: tunnels
+            .values()
+            .filter(|tunnel| {
+                matches!(
+                    tunnel.status,
+                    TunnelStatus::Active | TunnelStatus::Degraded | TunnelStatus::Standby
+                )
+            })
+            .map(|tunnel| tunnel.id.clone())
+            .collect(),
     };
-
-    match auth_result {
-        Ok(_) => {
-            // Verify authentication
-            if session.authenticated() {
-                ConnectionResult {
-                    success: true,
-                    message: "SSH connection test successful".to_string(),
-                    error_code: None,
-                }
-            } else {
-                ConnectionResult {
-                    success: false,
-                    message: "SSH authentication failed".to_string(),
-                    error_code: Some("SSH_AUTH_ERROR".to_string()),
-                }
-            }
-        }
-        Err(e) => {
-            // Simplify error handling, directly use error message
-            let message = format!("SSH authentication failed: {}", e);
-            let error_code = Some("SSH_AUTH_ERROR".to_string());
-
-            ConnectionResult {
-                success: false,
-                message,
-                error_code,
-            }
-        }
-    }
+    state.connections.sort();
+    state.tunnels.sort();
+    state
 }
 
 // Helper function to generate UUID
@@ -1485,11 +3237,10 @@ pub fn generate_id() -> String {
     Uuid::new_v4().to_string()
 }
 
+// Test fixtures; tests override the fields they care about with struct update syntax
 #[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn sample_connection(id: &str, status: ConnectionStatus) -> SSHConnection {
+impl SSHConnection {
+    pub fn fixture(id: &str) -> Self {
         SSHConnection {
             id: id.to_string(),
             name: "Test Connection".to_string(),
@@ -1499,18 +3250,18 @@ mod tests {
             auth_method: AuthMethod::Password,
             password: Some("secret".to_string()),
             key_path: None,
-            status,
+            status: ConnectionStatus::Disconnected,
             last_connected: None,
             created_at: SystemTime::now(),
+            session_pool_size: 1,
+            bandwidth: None,
         }
     }
+}
 
-    fn sample_tunnel(
-        id: &str,
-        connection_id: &str,
-        status: TunnelStatus,
-        auto_reconnect: bool,
-    ) -> SSHTunnel {
+#[cfg(test)]
+impl SSHTunnel {
+    pub fn fixture(id: &str, connection_id: &str) -> Self {
         SSHTunnel {
             id: id.to_string(),
             connection_id: connection_id.to_string(),
@@ -1519,8 +3270,50 @@ mod tests {
             local_port: 8080,
             remote_host: "127.0.0.1".to_string(),
             remote_port: 80,
+            status: TunnelStatus::Inactive,
+            auto_reconnect: false,
+            probe: None,
+            bandwidth: None,
+            allowed_clients: Vec::new(),
+            client_limits: None,
+            on_demand: None,
+            port_count: None,
+            extra_ports: Vec::new(),
+            open_url: None,
+            schedule: None,
+            port_errors: Vec::new(),
+            last_probe: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::ScheduleRule;
+
+    fn test_manager() -> ConnectionManager {
+        let data_path = std::env::temp_dir().join(format!("vesper-test-{}", generate_id()));
+        ConnectionManager::new(Store::spawn(DataManager::with_path(data_path)))
+    }
+
+    fn sample_connection(id: &str, status: ConnectionStatus) -> SSHConnection {
+        SSHConnection {
+            status,
+            ..SSHConnection::fixture(id)
+        }
+    }
+
+    fn sample_tunnel(
+        id: &str,
+        connection_id: &str,
+        status: TunnelStatus,
+        auto_reconnect: bool,
+    ) -> SSHTunnel {
+        SSHTunnel {
             status,
             auto_reconnect,
+            ..SSHTunnel::fixture(id, connection_id)
         }
     }
 
@@ -1543,12 +3336,14 @@ mod tests {
             tunnel,
             shutdown_tx: Some(shutdown_tx),
             task_handle,
+            probe_task: None,
+            session_lost: None,
         }
     }
 
     #[tokio::test]
     async fn stop_active_tunnel_marks_it_inactive() {
-        let manager = ConnectionManager::new();
+        let manager = test_manager();
         let connection = sample_connection("conn-stop", ConnectionStatus::Connected);
         let tunnel = sample_tunnel("tunnel-stop", "conn-stop", TunnelStatus::Active, false);
 
@@ -1581,9 +3376,285 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn tunnels_without_ports_are_refused_before_binding() {
+        let manager = test_manager();
+        let mut tunnel = sample_tunnel("tunnel-empty", "conn-empty", TunnelStatus::Inactive, false);
+        tunnel.tunnel_type = TunnelType::Remote;
+        tunnel.port_count = Some(0);
+
+        let opened = manager
+            .open_port_mappings(&tunnel, |mapping| async move { Ok(mapping) })
+            .await;
+        assert_eq!(opened.unwrap_err().code(), "INVALID_INPUT");
+    }
+
+    #[tokio::test]
+    async fn failed_probes_degrade_a_running_tunnel() {
+        let manager = test_manager();
+        let tunnel = sample_tunnel("tunnel-probe", "conn-probe", TunnelStatus::Active, false);
+        manager
+            .tunnels
+            .write()
+            .await
+            .insert(tunnel.id.clone(), tunnel.clone());
+        manager.active_tunnels.write().await.insert(
+            tunnel.id.clone(),
+            spawn_dummy_active_tunnel(manager.clone(), tunnel.clone()),
+        );
+        let probe_result = |reachable: bool| ProbeResult {
+            reachable,
+            latency_ms: 3,
+            checked_at: 0,
+            error: (!reachable).then(|| "connection refused".to_string()),
+        };
+
+        manager
+            .record_probe_result(&tunnel.id, probe_result(false))
+            .await;
+        assert_eq!(
+            manager.tunnels.read().await[&tunnel.id].status,
+            TunnelStatus::Degraded
+        );
+
+        manager
+            .record_probe_result(&tunnel.id, probe_result(true))
+            .await;
+        assert_eq!(
+            manager.tunnels.read().await[&tunnel.id].status,
+            TunnelStatus::Active
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_loss_sends_on_demand_tunnels_back_to_standby() {
+        let manager = test_manager();
+        let running = sample_tunnel("tunnel-running", "conn-lost", TunnelStatus::Active, false);
+        let awake = sample_tunnel("tunnel-awake", "conn-lost", TunnelStatus::Active, true);
+        let standby = sample_tunnel("tunnel-standby", "conn-lost", TunnelStatus::Standby, true);
+        let mut session_lost = HashMap::new();
+        for tunnel in [&running, &awake, &standby] {
+            manager
+                .tunnels
+                .write()
+                .await
+                .insert(tunnel.id.clone(), tunnel.clone());
+            let mut active_tunnel = spawn_dummy_active_tunnel(manager.clone(), tunnel.clone());
+            if tunnel.id != running.id {
+                let notify = Arc::new(Notify::new());
+                active_tunnel.session_lost = Some(notify.clone());
+                session_lost.insert(tunnel.id.clone(), notify);
+            }
+            manager
+                .active_tunnels
+                .write()
+                .await
+                .insert(tunnel.id.clone(), active_tunnel);
+        }
+
+        manager
+            .stop_tunnels_for_connection(
+                "conn-lost",
+                TunnelControl::ConnectionLost("keepalive failed".to_string()),
+            )
+            .await;
+
+        let active_tunnels = manager.active_tunnels.read().await;
+        assert!(!active_tunnels.contains_key(&running.id));
+        for tunnel in [&awake, &standby] {
+            assert!(active_tunnels.contains_key(&tunnel.id));
+            timeout(Duration::from_secs(1), session_lost[&tunnel.id].notified())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn network_changes_reconnect_sessions_that_stop_answering() {
+        let manager = test_manager();
+        // The server accepts TCP and never speaks SSH
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connection = SSHConnection {
+            port: addr.port(),
+            ..sample_connection("conn-net", ConnectionStatus::Connected)
+        };
+        manager
+            .connections
+            .write()
+            .await
+            .insert(connection.id.clone(), connection);
+
+        let (stream, _server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let session = AsyncSession::new(stream.unwrap(), None).unwrap();
+        manager
+            .ssh_sessions
+            .write()
+            .await
+            .insert("conn-net".to_string(), Arc::new(session));
+
+        // Going offline only pauses reconnects
+        manager
+            .handle_network_event(NetworkEvent::Changed { online: false })
+            .await;
+        assert!(!manager.network.is_online());
+        assert!(manager.ssh_sessions.read().await.contains_key("conn-net"));
+
+        manager
+            .handle_network_event(NetworkEvent::Changed { online: true })
+            .await;
+        assert!(manager.network.is_online());
+        assert!(manager.ssh_sessions.read().await.is_empty());
+
+        // No tunnel needs it, yet the session is dialled again without waiting
+        let redial = timeout(Duration::from_secs(1), listener.accept()).await;
+        assert!(redial.unwrap().is_ok());
+        assert!(manager
+            .reconnecting_connections
+            .read()
+            .await
+            .contains("conn-net"));
+    }
+
+    #[test]
+    fn reconnects_back_off_and_skip_the_first_wait_after_network_events() {
+        let delays = |cause| {
+            (0..RECONNECT_MAX_ATTEMPTS)
+                .map(|attempt| reconnect_delay(attempt, cause).as_secs())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            delays(FailureCause::NetworkEvent),
+            vec![0, 5, 10, 20, 40, 60]
+        );
+        assert_eq!(
+            delays(FailureCause::SessionLost),
+            vec![5, 10, 20, 40, 60, 60]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queued_clients_are_turned_away_when_the_queue_is_full_or_too_slow() {
+        let manager = test_manager();
+        let mut tunnel = sample_tunnel("tunnel-queue", "conn-queue", TunnelStatus::Active, false);
+        tunnel.client_limits = Some(ClientLimits {
+            max_connections: Some(1),
+            max_queued: Some(1),
+            queue_timeout_secs: Some(5),
+            ..Default::default()
+        });
+        let policy = manager.forwarding_policy(&tunnel).await.unwrap();
+
+        let _first = policy.client_slot().await.unwrap();
+        let waiting = tokio::spawn({
+            let policy = policy.clone();
+            async move { policy.client_slot().await.is_some() }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(policy.counters.snapshot().queued_connections, 1);
+
+        assert!(policy.client_slot().await.is_none());
+        assert!(!waiting.await.unwrap());
+
+        let stats = policy.counters.snapshot();
+        assert_eq!(
+            (stats.rejected_over_limit, stats.queued_connections),
+            (2, 0)
+        );
+    }
+
+    #[tokio::test]
+    async fn on_demand_session_is_released_once_nothing_uses_it() {
+        let manager = test_manager();
+        let connection = sample_connection("conn-idle", ConnectionStatus::Connected);
+        let standby = sample_tunnel("tunnel-idle", "conn-idle", TunnelStatus::Standby, false);
+        let running = sample_tunnel("tunnel-busy", "conn-idle", TunnelStatus::Active, false);
+        manager
+            .connections
+            .write()
+            .await
+            .insert(connection.id.clone(), connection.clone());
+        for tunnel in [&standby, &running] {
+            manager
+                .tunnels
+                .write()
+                .await
+                .insert(tunnel.id.clone(), tunnel.clone());
+        }
+        manager
+            .on_demand_sessions
+            .write()
+            .await
+            .insert(connection.id.clone());
+
+        manager.release_on_demand_session(&connection.id).await;
+        assert!(matches!(
+            manager.connections.read().await[&connection.id].status,
+            ConnectionStatus::Connected
+        ));
+
+        manager
+            .set_tunnel_status(&running.id, TunnelStatus::Inactive)
+            .await;
+        manager.release_on_demand_session(&connection.id).await;
+        assert!(matches!(
+            manager.connections.read().await[&connection.id].status,
+            ConnectionStatus::Disconnected
+        ));
+        assert!(manager.on_demand_sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn schedule_stops_tunnels_outside_their_window_once_override_expires() {
+        let manager = test_manager();
+        let mut tunnel = sample_tunnel(
+            "tunnel-nightly",
+            "conn-nightly",
+            TunnelStatus::Active,
+            false,
+        );
+        tunnel.schedule = Some(TunnelSchedule {
+            timezone: "UTC".to_string(),
+            rule: ScheduleRule::Cron {
+                expression: "0 2 * * *".to_string(),
+                duration_minutes: 60,
+            },
+        });
+        manager
+            .tunnels
+            .write()
+            .await
+            .insert(tunnel.id.clone(), tunnel.clone());
+        manager.active_tunnels.write().await.insert(
+            tunnel.id.clone(),
+            spawn_dummy_active_tunnel(manager.clone(), tunnel.clone()),
+        );
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+
+        // Started by hand outside the window
+        manager
+            .schedule_overrides
+            .write()
+            .await
+            .insert(tunnel.id.clone(), Some(at("2026-10-20T02:00:00Z")));
+        manager.apply_schedules(at("2026-10-19T12:00:00Z")).await;
+        assert_eq!(
+            manager.tunnels.read().await[&tunnel.id].status,
+            TunnelStatus::Active
+        );
+
+        manager.apply_schedules(at("2026-10-20T03:30:00Z")).await;
+        assert_eq!(
+            manager.tunnels.read().await[&tunnel.id].status,
+            TunnelStatus::Inactive
+        );
+        assert!(manager.schedule_overrides.read().await.is_empty());
+    }
+
     #[tokio::test]
     async fn health_check_without_session_marks_connection_and_tunnel_error() {
-        let manager = ConnectionManager::new();
+        let manager = test_manager();
         let connection = sample_connection("conn-health", ConnectionStatus::Connected);
         let tunnel = sample_tunnel("tunnel-health", "conn-health", TunnelStatus::Active, false);
 
@@ -1623,6 +3694,85 @@ mod tests {
         assert!(manager.active_tunnels.read().await.is_empty());
     }
 
+    #[tokio::test]
+    async fn stale_status_is_reset_on_load() {
+        let manager = test_manager();
+        let connection = sample_connection("conn-stale", ConnectionStatus::Connected);
+        let tunnel = sample_tunnel("tunnel-stale", "conn-stale", TunnelStatus::Active, false);
+        manager
+            .store
+            .update(move |data| {
+                data.connections.insert(connection.id.clone(), connection);
+                data.tunnels.insert(tunnel.id.clone(), tunnel);
+            })
+            .unwrap();
+
+        manager.initialize().await.unwrap();
+
+        assert!(matches!(
+            manager.get_connection("conn-stale").await.map(|c| c.status),
+            Some(ConnectionStatus::Disconnected)
+        ));
+        assert!(matches!(
+            manager
+                .get_tunnels()
+                .await
+                .first()
+                .map(|t| t.status.clone()),
+            Some(TunnelStatus::Inactive)
+        ));
+    }
+
+    #[test]
+    fn session_state_records_only_live_entries() {
+        let connections = HashMap::from([
+            (
+                "conn-up".to_string(),
+                sample_connection("conn-up", ConnectionStatus::Connected),
+            ),
+            (
+                "conn-down".to_string(),
+                sample_connection("conn-down", ConnectionStatus::Error),
+            ),
+        ]);
+        let tunnels = HashMap::from([
+            (
+                "tunnel-up".to_string(),
+                sample_tunnel("tunnel-up", "conn-up", TunnelStatus::Active, false),
+            ),
+            (
+                "tunnel-down".to_string(),
+                sample_tunnel("tunnel-down", "conn-up", TunnelStatus::Inactive, false),
+            ),
+            (
+                "tunnel-standby".to_string(),
+                sample_tunnel("tunnel-standby", "conn-down", TunnelStatus::Standby, false),
+            ),
+        ]);
+
+        let state = session_state(&connections, &tunnels);
+
+        assert_eq!(state.connections, vec!["conn-up".to_string()]);
+        assert_eq!(
+            state.tunnels,
+            vec!["tunnel-standby".to_string(), "tunnel-up".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_credentials_fail_before_connecting() {
+        let mut connection = sample_connection("conn-creds", ConnectionStatus::Disconnected);
+        connection.password = None;
+
+        let error = test_ssh_connection(&connection).await.unwrap_err();
+        assert_eq!(error.code(), "PASSWORD_MISSING");
+
+        connection.auth_method = AuthMethod::Key;
+        connection.key_path = Some("/nonexistent/vesper/id_ed25519".to_string());
+        let error = test_ssh_connection(&connection).await.unwrap_err();
+        assert_eq!(error.code(), "KEY_FILE_NOT_FOUND");
+    }
+
     #[test]
     fn local_listener_fails_when_loopback_port_is_already_bound() {
         let occupied_listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
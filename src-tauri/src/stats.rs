@@ -0,0 +1,56 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Live counters of one tunnel, kept in memory for as long as the app runs
+#[derive(Debug, Default)]
+pub struct TunnelCounters {
+    pub rejected_clients: AtomicU64,
+    pub active_connections: AtomicU64,
+    pub queued_connections: AtomicU64,
+    pub total_connections: AtomicU64,
+    pub rejected_over_limit: AtomicU64,
+    pub idle_timeouts: AtomicU64,
+    pub lifetime_exceeded: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TunnelStats {
+    pub rejected_clients: u64,
+    pub active_connections: u64,
+    pub queued_connections: u64,
+    pub total_connections: u64,
+    pub rejected_over_limit: u64,
+    pub idle_timeouts: u64,
+    pub lifetime_exceeded: u64,
+}
+
+impl TunnelCounters {
+    pub fn snapshot(&self) -> TunnelStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        TunnelStats {
+            rejected_clients: load(&self.rejected_clients),
+            active_connections: load(&self.active_connections),
+            queued_connections: load(&self.queued_connections),
+            total_connections: load(&self.total_connections),
+            rejected_over_limit: load(&self.rejected_over_limit),
+            idle_timeouts: load(&self.idle_timeouts),
+            lifetime_exceeded: load(&self.lifetime_exceeded),
+        }
+    }
+}
+
+// Counts something for as long as the guard is alive
+pub struct Gauge<'a>(&'a AtomicU64);
+
+impl<'a> Gauge<'a> {
+    pub fn enter(counter: &'a AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Gauge<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
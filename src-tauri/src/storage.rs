@@ -1,47 +1,315 @@
+use crate::error::{VesperError, VesperResult};
+use crate::migrations::{migrate, schema_version_of, strip_runtime_fields, CURRENT_SCHEMA_VERSION};
 use crate::settings::AppConfig;
+use crate::snippets::CommandSnippet;
 use crate::ssh::{SSHConnection, SSHTunnel};
+use crate::templates::TunnelTemplate;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppData {
+    #[serde(default)]
+    pub schema_version: u32,
     pub connections: HashMap<String, SSHConnection>,
     pub tunnels: HashMap<String, SSHTunnel>,
     pub settings: AppConfig,
+    #[serde(default)]
+    pub snippets: HashMap<String, CommandSnippet>,
+    #[serde(default)]
+    pub last_session: SessionState,
+    // User-defined; built-in templates live in code
+    #[serde(default)]
+    pub tunnel_templates: HashMap<String, TunnelTemplate>,
+}
+
+// Connections and tunnels that were up when the data was last saved
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionState {
+    pub connections: Vec<String>,
+    pub tunnels: Vec<String>,
 }
 
 impl Default for AppData {
     fn default() -> Self {
         Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
             connections: HashMap::new(),
             tunnels: HashMap::new(),
             settings: AppConfig::default(),
+            snippets: HashMap::new(),
+            last_session: SessionState::default(),
+            tunnel_templates: HashMap::new(),
         }
     }
 }
 
+#[cfg(feature = "sqlite")]
+const SQLITE_FILE_NAME: &str = "vesper.db";
+const SNAPSHOT_RETENTION: usize = 10;
+const SNAPSHOT_MIN_INTERVAL_SECS: u64 = 300;
+
+static RECOVERY_NOTICE: Mutex<Option<RecoveryNotice>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub created_at: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryNotice {
+    pub snapshot_id: String,
+    pub snapshot_created_at: u64,
+    pub corrupted_file: String,
+    pub error: String,
+}
+
+// Parse and migrate a data file, returning the schema version it was migrated from.
+// StorageCorrupt means a snapshot may be used instead; SchemaTooNew must never fall back silently
+fn parse_data(content: &str, what: &str) -> VesperResult<(AppData, Option<u32>)> {
+    let value: serde_json::Value =
+        serde_json::from_str(content).map_err(VesperError::corrupt(what))?;
+    parse_value(value, what)
+}
+
+fn parse_value(mut value: serde_json::Value, what: &str) -> VesperResult<(AppData, Option<u32>)> {
+    let version = schema_version_of(&value).map_err(VesperError::corrupt(what))?;
+    if version > CURRENT_SCHEMA_VERSION {
+        return Err(VesperError::SchemaTooNew {
+            found: version,
+            supported: CURRENT_SCHEMA_VERSION,
+        });
+    }
+    migrate(&mut value, version).map_err(VesperError::corrupt(what))?;
+
+    let data: AppData = serde_json::from_value(value).map_err(VesperError::corrupt(what))?;
+
+    Ok((data, (version != CURRENT_SCHEMA_VERSION).then_some(version)))
+}
+
+// Migrate a whole-document value assembled by a backend other than the JSON file
+#[cfg(feature = "sqlite")]
+pub(crate) fn app_data_from_value(
+    value: serde_json::Value,
+) -> VesperResult<(AppData, Option<u32>)> {
+    parse_value(value, "Database")
+}
+
+// AppData as it is written to disk, without runtime-only fields
+pub(crate) fn persisted_value(data: &AppData) -> VesperResult<serde_json::Value> {
+    let mut value = serde_json::to_value(data).map_err(VesperError::Serialization)?;
+    strip_runtime_fields(&mut value);
+    Ok(value)
+}
+
+fn snapshot_timestamp(id: &str) -> Option<u64> {
+    id.strip_prefix("data-")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// Where AppData lives on disk; all methods are blocking
+pub trait StorageBackend: Send + Sync {
+    fn load(&self) -> VesperResult<AppData>;
+    fn save(&self, data: &AppData) -> VesperResult<()>;
+
+    // Snapshots sorted newest first
+    fn list_snapshots(&self) -> VesperResult<Vec<SnapshotInfo>>;
+    fn restore_snapshot(&self, id: &str) -> VesperResult<AppData>;
+}
+
+#[derive(Clone)]
 pub struct DataManager {
     data_path: PathBuf,
+    // Chosen by open(), once the store has tried to lock the directory
+    backend: Arc<Mutex<Option<Arc<dyn StorageBackend>>>>,
 }
 
 impl DataManager {
-    pub fn new() -> Result<Self, String> {
-        // 使用固定的数据目录路径
-        let data_path = dirs::data_dir()
-            .ok_or("Failed to get data directory")?
-            .join("vesper");
+    pub fn with_path(data_path: PathBuf) -> Self {
+        Self {
+            data_path,
+            backend: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Pick the storage backend; data is only moved between backends by the lock holder
+    pub async fn open(&self, read_only: bool) -> VesperResult<()> {
+        if self.backend.lock().unwrap().is_some() {
+            return Ok(());
+        }
+
+        let data_path = self.data_path.clone();
+        let backend = run_blocking(move || open_backend(&data_path, read_only)).await?;
+        *self.backend.lock().unwrap() = Some(backend);
+        Ok(())
+    }
+
+    // Advisory lock held for the lifetime of the process to detect a second instance
+    pub fn open_lock_file(&self) -> VesperResult<fs::File> {
+        fs::create_dir_all(&self.data_path)
+            .map_err(VesperError::storage_io("create data directory"))?;
+        fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.data_path.join("vesper.lock"))
+            .map_err(VesperError::storage_io("open the data directory lock"))
+    }
+
+    // Async wrapper that runs blocking operations in a separate thread
+    pub async fn load_data(&self) -> VesperResult<AppData> {
+        let backend = self.backend()?;
+        run_blocking(move || backend.load()).await
+    }
+
+    pub async fn save_data(&self, data: AppData) -> VesperResult<()> {
+        let backend = self.backend()?;
+        run_blocking(move || backend.save(&data)).await
+    }
+
+    pub async fn list_snapshots(&self) -> VesperResult<Vec<SnapshotInfo>> {
+        let backend = self.backend()?;
+        run_blocking(move || backend.list_snapshots()).await
+    }
+
+    pub async fn restore_snapshot(&self, id: String) -> VesperResult<AppData> {
+        let backend = self.backend()?;
+        run_blocking(move || backend.restore_snapshot(&id)).await
+    }
+
+    fn backend(&self) -> VesperResult<Arc<dyn StorageBackend>> {
+        self.backend
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| VesperError::Storage("Data directory is not open".to_string()))
+    }
+
+    // 取出最近一次从快照自动恢复的记录（只返回一次）
+    pub fn take_recovery_notice() -> Option<RecoveryNotice> {
+        RECOVERY_NOTICE.lock().unwrap().take()
+    }
+}
+
+async fn run_blocking<T: Send + 'static>(
+    operation: impl FnOnce() -> VesperResult<T> + Send + 'static,
+) -> VesperResult<T> {
+    tokio::task::spawn_blocking(operation)
+        .await
+        .map_err(|e| VesperError::Internal(format!("Failed to join blocking task: {}", e)))?
+}
+
+// 根据设置选择存储后端，切换时一次性迁移现有数据
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn open_backend(data_path: &Path, read_only: bool) -> VesperResult<Arc<dyn StorageBackend>> {
+    let json = JsonStorage {
+        data_path: data_path.to_path_buf(),
+        read_only,
+    };
+
+    #[cfg(feature = "sqlite")]
+    {
+        use crate::settings::StorageBackendKind;
+        use crate::sqlite_storage::SqliteStorage;
+
+        let db_path = data_path.join(SQLITE_FILE_NAME);
+        if db_path.exists() {
+            let sqlite = SqliteStorage::open(&db_path)?;
+            // 另一个实例持有目录锁，由它负责迁移
+            if read_only {
+                return Ok(Arc::new(sqlite));
+            }
+            let data = sqlite.load()?;
+            if data.settings.storage_backend == StorageBackendKind::Sqlite {
+                return Ok(Arc::new(sqlite));
+            }
+
+            json.save_data_sync(&data)?;
+            drop(sqlite);
+            retire_file(&db_path, "exported")?;
+            log::info!("Moved data from SQLite back to data.json");
+            return Ok(Arc::new(json));
+        }
+
+        if read_only {
+            return Ok(Arc::new(json));
+        }
+        let data = json.load_data_sync()?;
+        if data.settings.storage_backend == StorageBackendKind::Sqlite {
+            let sqlite = SqliteStorage::open(&db_path)?;
+            sqlite.save(&data)?;
+            let file_path = json.get_data_file_path();
+            if file_path.exists() {
+                retire_file(&file_path, "imported")?;
+                log::info!("Imported data.json into SQLite");
+            }
+            return Ok(Arc::new(sqlite));
+        }
+    }
+
+    Ok(Arc::new(json))
+}
+
+// Keep a file that was migrated to another backend instead of deleting it
+#[cfg(feature = "sqlite")]
+fn retire_file(path: &Path, suffix: &str) -> VesperResult<()> {
+    let mut retired = path.as_os_str().to_owned();
+    retired.push(format!(".{}-{}", suffix, unix_millis()));
+    fs::rename(path, &retired).map_err(VesperError::storage_io(format!("move {}", path.display())))
+}
+
+// The original single-file backend: pretty-printed data.json with rolling snapshots
+pub struct JsonStorage {
+    data_path: PathBuf,
+    // Another instance owns the directory; loads must not write anything back
+    read_only: bool,
+}
+
+impl StorageBackend for JsonStorage {
+    fn load(&self) -> VesperResult<AppData> {
+        self.load_data_sync()
+    }
+
+    fn save(&self, data: &AppData) -> VesperResult<()> {
+        self.save_data_sync(data)
+    }
+
+    fn list_snapshots(&self) -> VesperResult<Vec<SnapshotInfo>> {
+        Ok(self.list_snapshots_sync())
+    }
 
-        Ok(Self { data_path })
+    fn restore_snapshot(&self, id: &str) -> VesperResult<AppData> {
+        self.restore_snapshot_sync(id)
     }
+}
 
+impl JsonStorage {
     fn get_data_file_path(&self) -> PathBuf {
         self.data_path.join("data.json")
     }
 
+    fn get_snapshot_dir(&self) -> PathBuf {
+        self.data_path.join("snapshots")
+    }
+
     // Synchronous version for blocking operations
-    fn load_data_sync(&self) -> Result<AppData, String> {
+    fn load_data_sync(&self) -> VesperResult<AppData> {
         let file_path = self.get_data_file_path();
 
         if !file_path.exists() {
@@ -49,105 +317,368 @@ impl DataManager {
             return Ok(AppData::default());
         }
 
-        let content = fs::read_to_string(&file_path)
-            .map_err(|e| format!("Failed to read data file: {}", e))?;
+        let content = match fs::read_to_string(&file_path) {
+            Ok(content) => content,
+            Err(e) => {
+                return self.recover_from_snapshot(VesperError::storage_io("read data file")(e))
+            }
+        };
+
+        let (data, migrated_from) = match parse_data(&content, "Data file") {
+            Ok(parsed) => parsed,
+            Err(error @ VesperError::StorageCorrupt { .. }) => {
+                return self.recover_from_snapshot(error)
+            }
+            Err(error) => return Err(error),
+        };
 
-        let data: AppData = serde_json::from_str(&content)
-            .map_err(|e| format!("Failed to parse data file: {}", e))?;
+        if let Some(version) = migrated_from.filter(|_| !self.read_only) {
+            // 迁移前保留带版本号的备份
+            let backup_path = self.data_path.join(format!("data.v{}.json.bak", version));
+            fs::copy(&file_path, &backup_path).map_err(VesperError::storage_io(
+                "back up data file before migration",
+            ))?;
+            log::info!(
+                "Migrated data file from schema v{} to v{}",
+                version,
+                CURRENT_SCHEMA_VERSION
+            );
+            self.save_data_sync(&data)?;
+        }
 
         Ok(data)
     }
 
-    // Synchronous version for blocking operations
-    fn save_data_sync(&self, data: &AppData) -> Result<(), String> {
-        let file_path = self.get_data_file_path();
-        let backup_path = self.data_path.join("data.json.bak");
+    // Fall back to the newest snapshot that still parses, keeping the broken file aside
+    fn recover_from_snapshot(&self, error: VesperError) -> VesperResult<AppData> {
+        log::warn!(
+            "Data file is unreadable, trying snapshots: {}",
+            error.full_message()
+        );
+
+        for snapshot in self.list_snapshots_sync() {
+            let snapshot_path = self.get_snapshot_dir().join(&snapshot.id);
+            let Ok(content) = fs::read_to_string(&snapshot_path) else {
+                continue;
+            };
+            let Ok((data, _)) = parse_data(&content, "Snapshot") else {
+                log::warn!("Skipping unreadable snapshot {}", snapshot.id);
+                continue;
+            };
 
+            // 只读时由持有目录锁的实例负责修复文件
+            if self.read_only {
+                log::warn!(
+                    "Using snapshot {} without repairing the data file",
+                    snapshot.id
+                );
+                return Ok(data);
+            }
+
+            let file_path = self.get_data_file_path();
+            let corrupted_path = self
+                .data_path
+                .join(format!("data.json.corrupt-{}", unix_millis()));
+            if let Err(e) = fs::rename(&file_path, &corrupted_path) {
+                log::warn!("Failed to move corrupted data file aside: {}", e);
+            }
+            self.write_data_file(&data)?;
+
+            log::warn!("Recovered data from snapshot {}", snapshot.id);
+            *RECOVERY_NOTICE.lock().unwrap() = Some(RecoveryNotice {
+                snapshot_id: snapshot.id,
+                snapshot_created_at: snapshot.created_at,
+                corrupted_file: corrupted_path.to_string_lossy().into_owned(),
+                error: error.full_message(),
+            });
+            return Ok(data);
+        }
+
+        log::error!("No usable snapshot found to recover the data file from");
+        Err(error)
+    }
+
+    // Synchronous version for blocking operations
+    fn save_data_sync(&self, data: &AppData) -> VesperResult<()> {
         // 创建数据目录（如果不存在）
         if !self.data_path.exists() {
             fs::create_dir_all(&self.data_path)
-                .map_err(|e| format!("Failed to create data directory: {}", e))?;
+                .map_err(VesperError::storage_io("create data directory"))?;
         }
 
-        // 创建备份（如果主文件存在）
-        if file_path.exists() {
-            if let Err(e) = fs::copy(&file_path, &backup_path) {
-                eprintln!("Warning: Failed to create backup: {}", e);
-            }
+        if let Err(e) = self.take_snapshot_sync(false) {
+            log::warn!("Failed to create snapshot: {}", e.full_message());
         }
 
-        let content = serde_json::to_string_pretty(data)
-            .map_err(|e| format!("Failed to serialize data: {}", e))?;
+        self.write_data_file(data)
+    }
+
+    fn write_data_file(&self, data: &AppData) -> VesperResult<()> {
+        let file_path = self.get_data_file_path();
+        // 运行时状态不写入磁盘
+        let value = persisted_value(data)?;
+        let content = serde_json::to_string_pretty(&value).map_err(VesperError::Serialization)?;
 
-        // 写入临时文件，然后原子性移动
+        // 写入临时文件并落盘，然后原子性移动
         let temp_path = file_path.with_extension("tmp");
-        fs::write(&temp_path, content).map_err(|e| format!("Failed to write temp file: {}", e))?;
+        let mut temp_file =
+            fs::File::create(&temp_path).map_err(VesperError::storage_io("write temp file"))?;
+        temp_file
+            .write_all(content.as_bytes())
+            .and_then(|_| temp_file.sync_all())
+            .map_err(VesperError::storage_io("write temp file"))?;
+        drop(temp_file);
 
         // 原子性移动
-        fs::rename(&temp_path, &file_path)
-            .map_err(|e| format!("Failed to move temp file: {}", e))?;
+        fs::rename(&temp_path, &file_path).map_err(VesperError::storage_io("move temp file"))?;
 
-        // 删除备份
-        let _ = fs::remove_file(backup_path);
+        // 确保目录项的重命名也已落盘
+        #[cfg(unix)]
+        if let Err(e) = fs::File::open(&self.data_path).and_then(|dir| dir.sync_all()) {
+            log::warn!("Failed to sync data directory: {}", e);
+        }
 
         Ok(())
     }
 
-    // Async wrapper that runs blocking operations in a separate thread
-    pub async fn load_data(&self) -> Result<AppData, String> {
-        let data_path = self.data_path.clone();
-        tokio::task::spawn_blocking(move || {
-            // Create a new DataManager in the blocking thread
-            let manager = DataManager { data_path };
-            manager.load_data_sync()
-        })
-        .await
-        .map_err(|e| format!("Failed to join blocking task: {}", e))?
+    // Copy the current data file into the snapshot directory and prune old snapshots
+    fn take_snapshot_sync(&self, force: bool) -> VesperResult<()> {
+        let file_path = self.get_data_file_path();
+        if !file_path.exists() {
+            return Ok(());
+        }
+
+        let snapshots = self.list_snapshots_sync();
+        if !force {
+            let now = unix_millis();
+            let recent = snapshots.first().is_some_and(|newest| {
+                now.saturating_sub(newest.created_at) < SNAPSHOT_MIN_INTERVAL_SECS * 1000
+            });
+            if recent {
+                return Ok(());
+            }
+        }
+
+        // 不为已损坏的文件创建快照
+        let content =
+            fs::read_to_string(&file_path).map_err(VesperError::storage_io("read data file"))?;
+        serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(VesperError::corrupt("Current data file"))?;
+
+        let snapshot_dir = self.get_snapshot_dir();
+        fs::create_dir_all(&snapshot_dir)
+            .map_err(VesperError::storage_io("create snapshot directory"))?;
+        fs::write(
+            snapshot_dir.join(format!("data-{}.json", unix_millis())),
+            content,
+        )
+        .map_err(VesperError::storage_io("write snapshot"))?;
+
+        for stale in self.list_snapshots_sync().iter().skip(SNAPSHOT_RETENTION) {
+            if let Err(e) = fs::remove_file(snapshot_dir.join(&stale.id)) {
+                log::warn!("Failed to remove old snapshot {}: {}", stale.id, e);
+            }
+        }
+
+        Ok(())
     }
 
-    pub async fn save_data(&self, data: AppData) -> Result<(), String> {
-        let data_path = self.data_path.clone();
+    // Snapshots sorted newest first
+    fn list_snapshots_sync(&self) -> Vec<SnapshotInfo> {
+        let Ok(entries) = fs::read_dir(self.get_snapshot_dir()) else {
+            return Vec::new();
+        };
 
-        tokio::task::spawn_blocking(move || {
-            // Create a new DataManager in the blocking thread
-            let manager = DataManager { data_path };
-            manager.save_data_sync(&data)
-        })
-        .await
-        .map_err(|e| format!("Failed to join blocking task: {}", e))?
+        let mut snapshots: Vec<SnapshotInfo> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let id = entry.file_name().to_string_lossy().into_owned();
+                let created_at = snapshot_timestamp(&id)?;
+                let size = entry.metadata().ok()?.len();
+                Some(SnapshotInfo {
+                    id,
+                    created_at,
+                    size,
+                })
+            })
+            .collect();
+
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.created_at));
+        snapshots
     }
 
-    // 便利方法：直接保存连接和隧道
-    #[cfg_attr(test, allow(dead_code))]
-    pub async fn save_connections_and_tunnels(
-        &self,
-        connections: &HashMap<String, SSHConnection>,
-        tunnels: &HashMap<String, SSHTunnel>,
-    ) -> Result<(), String> {
-        let mut data = self.load_data().await?;
-        data.connections = connections.clone();
-        data.tunnels = tunnels.clone();
-        self.save_data(data).await
+    fn restore_snapshot_sync(&self, id: &str) -> VesperResult<AppData> {
+        if snapshot_timestamp(id).is_none() {
+            return Err(VesperError::InvalidInput(format!(
+                "Invalid snapshot id: {}",
+                id
+            )));
+        }
+
+        let content = match fs::read_to_string(self.get_snapshot_dir().join(id)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(VesperError::SnapshotNotFound(id.to_string()))
+            }
+            Err(e) => return Err(VesperError::storage_io(format!("read snapshot {}", id))(e)),
+        };
+        let (data, _) = parse_data(&content, &format!("Snapshot {}", id))?;
+
+        // 恢复前先为当前文件保留快照，以便撤销
+        if let Err(e) = self.take_snapshot_sync(true) {
+            log::warn!(
+                "Failed to snapshot data before restore: {}",
+                e.full_message()
+            );
+        }
+        self.write_data_file(&data)?;
+
+        Ok(data)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // 便利方法：加载连接和隧道
-    pub async fn load_connections_and_tunnels(
-        &self,
-    ) -> Result<(HashMap<String, SSHConnection>, HashMap<String, SSHTunnel>), String> {
-        let data = self.load_data().await?;
-        Ok((data.connections, data.tunnels))
+    fn temp_data_manager() -> JsonStorage {
+        let data_path =
+            std::env::temp_dir().join(format!("vesper-test-{}", crate::ssh::generate_id()));
+        fs::create_dir_all(&data_path).unwrap();
+        JsonStorage {
+            data_path,
+            read_only: false,
+        }
     }
 
-    // 便利方法：保存设置
-    pub async fn save_settings(&self, settings: &AppConfig) -> Result<(), String> {
-        let mut data = self.load_data().await?;
-        data.settings = settings.clone();
-        self.save_data(data).await
+    #[test]
+    fn legacy_file_is_migrated_and_backed_up() {
+        let manager = temp_data_manager();
+        let legacy = r#"{"connections":{},"tunnels":{},"settings":{"theme":"dark","language":"en","auto_start":false,"log_level":"info","window_width":1200,"window_height":800}}"#;
+        fs::write(manager.get_data_file_path(), legacy).unwrap();
+
+        let data = manager.load_data_sync().unwrap();
+
+        assert_eq!(data.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(
+            fs::read_to_string(manager.data_path.join("data.v0.json.bak")).unwrap(),
+            legacy
+        );
+        let saved = fs::read_to_string(manager.get_data_file_path()).unwrap();
+        assert!(saved.contains("\"schema_version\""));
+
+        fs::remove_dir_all(&manager.data_path).unwrap();
     }
 
-    // 便利方法：加载设置
-    pub async fn load_settings(&self) -> Result<AppConfig, String> {
-        let data = self.load_data().await?;
-        Ok(data.settings)
+    #[test]
+    fn read_only_load_migrates_in_memory_only() {
+        let manager = JsonStorage {
+            read_only: true,
+            ..temp_data_manager()
+        };
+        let legacy = r#"{"connections":{},"tunnels":{},"settings":{"theme":"dark","language":"en","auto_start":false,"log_level":"info","window_width":1200,"window_height":800}}"#;
+        fs::write(manager.get_data_file_path(), legacy).unwrap();
+
+        let data = manager.load_data_sync().unwrap();
+
+        assert_eq!(data.schema_version, CURRENT_SCHEMA_VERSION);
+        assert_eq!(
+            fs::read_to_string(manager.get_data_file_path()).unwrap(),
+            legacy
+        );
+        assert!(!manager.data_path.join("data.v0.json.bak").exists());
+
+        fs::remove_dir_all(&manager.data_path).unwrap();
+    }
+
+    #[test]
+    fn newer_file_is_left_untouched() {
+        let manager = temp_data_manager();
+        let newer = format!(
+            r#"{{"schema_version":{},"connections":{{}}}}"#,
+            CURRENT_SCHEMA_VERSION + 1
+        );
+        fs::write(manager.get_data_file_path(), &newer).unwrap();
+
+        assert!(matches!(
+            manager.load_data_sync(),
+            Err(VesperError::SchemaTooNew { .. })
+        ));
+        assert_eq!(
+            fs::read_to_string(manager.get_data_file_path()).unwrap(),
+            newer
+        );
+
+        fs::remove_dir_all(&manager.data_path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_file_is_recovered_from_newest_valid_snapshot() {
+        let manager = temp_data_manager();
+        manager.save_data_sync(&AppData::default()).unwrap();
+        manager.save_data_sync(&AppData::default()).unwrap();
+        let snapshot_dir = manager.get_snapshot_dir();
+        assert_eq!(manager.list_snapshots_sync().len(), 1);
+        fs::write(snapshot_dir.join("data-99999999999999.json"), "{ broken").unwrap();
+        fs::write(manager.get_data_file_path(), "{\"connections\": ").unwrap();
+
+        let data = manager.load_data_sync().unwrap();
+
+        assert_eq!(data.schema_version, CURRENT_SCHEMA_VERSION);
+        let notice = DataManager::take_recovery_notice().unwrap();
+        assert_ne!(notice.snapshot_id, "data-99999999999999.json");
+        assert!(PathBuf::from(&notice.corrupted_file).exists());
+        assert!(serde_json::from_str::<AppData>(
+            &fs::read_to_string(manager.get_data_file_path()).unwrap()
+        )
+        .is_ok());
+
+        fs::remove_dir_all(&manager.data_path).unwrap();
+    }
+
+    #[test]
+    fn read_only_recovery_leaves_the_corrupted_file_in_place() {
+        let manager = temp_data_manager();
+        manager.save_data_sync(&AppData::default()).unwrap();
+        manager.save_data_sync(&AppData::default()).unwrap();
+        fs::write(manager.get_data_file_path(), "{\"connections\": ").unwrap();
+        let manager = JsonStorage {
+            read_only: true,
+            ..manager
+        };
+
+        manager.load_data_sync().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(manager.get_data_file_path()).unwrap(),
+            "{\"connections\": "
+        );
+        let corrupt_copies = fs::read_dir(&manager.data_path)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().contains("corrupt"))
+            .count();
+        assert_eq!(corrupt_copies, 0);
+
+        fs::remove_dir_all(&manager.data_path).unwrap();
+    }
+
+    #[test]
+    fn old_snapshots_are_pruned() {
+        let manager = temp_data_manager();
+        let snapshot_dir = manager.get_snapshot_dir();
+        fs::create_dir_all(&snapshot_dir).unwrap();
+        for i in 0..SNAPSHOT_RETENTION as u64 + 3 {
+            fs::write(snapshot_dir.join(format!("data-{}.json", 1000 + i)), "{}").unwrap();
+        }
+        manager.save_data_sync(&AppData::default()).unwrap();
+
+        manager.take_snapshot_sync(true).unwrap();
+
+        let snapshots = manager.list_snapshots_sync();
+        assert_eq!(snapshots.len(), SNAPSHOT_RETENTION);
+        assert!(snapshots.iter().all(|snapshot| snapshot.created_at > 1003));
+
+        fs::remove_dir_all(&manager.data_path).unwrap();
     }
 }
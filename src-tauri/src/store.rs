@@ -0,0 +1,307 @@
+use serde_json::Value;
+use std::fs::{File, TryLockError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::error::{VesperError, VesperResult};
+use crate::storage::{persisted_value, AppData, DataManager, SnapshotInfo};
+
+// Changes arriving within this window are written to disk together
+const PERSIST_DEBOUNCE_MS: u64 = 500;
+
+type Update = Box<dyn FnOnce(&mut AppData) + Send>;
+
+enum StoreCommand {
+    Load(oneshot::Sender<VesperResult<AppData>>),
+    Update(Update),
+    Flush(oneshot::Sender<VesperResult<()>>),
+    RestoreSnapshot(String, oneshot::Sender<VesperResult<()>>),
+    ListSnapshots(oneshot::Sender<VesperResult<Vec<SnapshotInfo>>>),
+    SwitchDataManager(DataManager, oneshot::Sender<VesperResult<()>>),
+}
+
+// Handle to the single task that owns AppData and the data file
+#[derive(Clone)]
+pub struct Store {
+    command_tx: mpsc::UnboundedSender<StoreCommand>,
+    read_only: Arc<AtomicBool>,
+}
+
+impl Store {
+    pub fn spawn(data_manager: DataManager) -> Self {
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let read_only = Arc::new(AtomicBool::new(false));
+
+        let actor = StoreActor {
+            data_manager,
+            data: None,
+            persisted: None,
+            lock: None,
+            read_only: read_only.clone(),
+            write_deadline: None,
+        };
+        tauri::async_runtime::spawn(actor.run(command_rx));
+
+        Self {
+            command_tx,
+            read_only,
+        }
+    }
+
+    // A copy of the current data, read from disk on first use
+    pub async fn load(&self) -> VesperResult<AppData> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(StoreCommand::Load(reply_tx))?;
+        reply_rx.await.map_err(|_| task_stopped())?
+    }
+
+    // Apply a change in memory and schedule a debounced write
+    pub fn update(&self, update: impl FnOnce(&mut AppData) + Send + 'static) -> VesperResult<()> {
+        self.send(StoreCommand::Update(Box::new(update)))
+    }
+
+    // Write any pending changes immediately
+    pub async fn flush(&self) -> VesperResult<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(StoreCommand::Flush(reply_tx))?;
+        reply_rx.await.map_err(|_| task_stopped())?
+    }
+
+    // Replace the data file with a snapshot, discarding unsaved changes
+    pub async fn restore_snapshot(&self, id: String) -> VesperResult<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(StoreCommand::RestoreSnapshot(id, reply_tx))?;
+        reply_rx.await.map_err(|_| task_stopped())?
+    }
+
+    pub async fn list_snapshots(&self) -> VesperResult<Vec<SnapshotInfo>> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(StoreCommand::ListSnapshots(reply_tx))?;
+        reply_rx.await.map_err(|_| task_stopped())?
+    }
+
+    // Save pending changes, release the current data directory and load another one
+    pub async fn switch_data_manager(&self, data_manager: DataManager) -> VesperResult<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(StoreCommand::SwitchDataManager(data_manager, reply_tx))?;
+        reply_rx.await.map_err(|_| task_stopped())?
+    }
+
+    // True when another Vesper instance holds the data directory lock
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    fn send(&self, command: StoreCommand) -> VesperResult<()> {
+        self.command_tx.send(command).map_err(|_| task_stopped())
+    }
+}
+
+fn task_stopped() -> VesperError {
+    VesperError::Storage("Storage task stopped".to_string())
+}
+
+struct StoreActor {
+    data_manager: DataManager,
+    data: Option<AppData>,
+    // What is on disk, so status-only changes don't rewrite the file
+    persisted: Option<Value>,
+    lock: Option<File>,
+    read_only: Arc<AtomicBool>,
+    write_deadline: Option<Instant>,
+}
+
+impl StoreActor {
+    async fn run(mut self, mut command_rx: mpsc::UnboundedReceiver<StoreCommand>) {
+        loop {
+            let deadline = self.write_deadline;
+            tokio::select! {
+                command = command_rx.recv() => match command {
+                    Some(command) => self.handle(command).await,
+                    None => break,
+                },
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    if let Err(e) = self.write().await {
+                        log::error!("Failed to save data: {}", e);
+                    }
+                }
+            }
+        }
+
+        // 所有句柄都已释放，写入剩余的更改
+        if let Err(e) = self.write().await {
+            log::error!("Failed to save data: {}", e);
+        }
+    }
+
+    async fn handle(&mut self, command: StoreCommand) {
+        match command {
+            StoreCommand::Load(reply_tx) => {
+                let result = self.ensure_loaded().await.map(|data| data.clone());
+                let _ = reply_tx.send(result);
+            }
+            StoreCommand::Update(update) => match self.ensure_loaded().await {
+                Ok(data) => {
+                    update(data);
+                    if self.write_deadline.is_none() {
+                        self.write_deadline =
+                            Some(Instant::now() + Duration::from_millis(PERSIST_DEBOUNCE_MS));
+                    }
+                }
+                // 数据文件无法读取时不能用默认数据覆盖它
+                Err(e) => log::warn!("Dropping change because data could not be loaded: {}", e),
+            },
+            StoreCommand::Flush(reply_tx) => {
+                let _ = reply_tx.send(self.write().await);
+            }
+            StoreCommand::RestoreSnapshot(id, reply_tx) => {
+                let result = self.restore_snapshot(id).await;
+                let _ = reply_tx.send(result);
+            }
+            StoreCommand::ListSnapshots(reply_tx) => {
+                let _ = reply_tx.send(self.data_manager.list_snapshots().await);
+            }
+            StoreCommand::SwitchDataManager(data_manager, reply_tx) => {
+                let result = self.switch_data_manager(data_manager).await;
+                let _ = reply_tx.send(result);
+            }
+        }
+    }
+
+    async fn ensure_loaded(&mut self) -> VesperResult<&mut AppData> {
+        if self.data.is_none() {
+            self.acquire_lock();
+            self.data_manager
+                .open(self.read_only.load(Ordering::Relaxed))
+                .await?;
+            let data = self.data_manager.load_data().await?;
+            self.persisted = persisted_value(&data).ok();
+            self.data = Some(data);
+        }
+
+        Ok(self.data.as_mut().expect("data was just loaded"))
+    }
+
+    async fn write(&mut self) -> VesperResult<()> {
+        if self.write_deadline.take().is_none() {
+            return Ok(());
+        }
+        if self.read_only.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let Some(data) = &self.data else {
+            return Ok(());
+        };
+        let value = persisted_value(data)?;
+        if self.persisted.as_ref() == Some(&value) {
+            return Ok(());
+        }
+
+        self.data_manager.save_data(data.clone()).await?;
+        self.persisted = Some(value);
+        Ok(())
+    }
+
+    async fn restore_snapshot(&mut self, id: String) -> VesperResult<()> {
+        if self.read_only.load(Ordering::Relaxed) {
+            return Err(VesperError::ReadOnly);
+        }
+
+        let data = self.data_manager.restore_snapshot(id).await?;
+        self.persisted = persisted_value(&data).ok();
+        self.data = Some(data);
+        self.write_deadline = None;
+        Ok(())
+    }
+
+    async fn switch_data_manager(&mut self, data_manager: DataManager) -> VesperResult<()> {
+        self.write().await?;
+
+        // 释放旧目录的锁，新目录在加载时重新加锁
+        self.lock = None;
+        self.read_only.store(false, Ordering::Relaxed);
+        self.data = None;
+        self.persisted = None;
+        self.data_manager = data_manager;
+
+        self.ensure_loaded().await.map(|_| ())
+    }
+
+    // Only the instance holding the lock writes; others keep working read-only
+    fn acquire_lock(&mut self) {
+        if self.lock.is_some() || self.read_only.load(Ordering::Relaxed) {
+            return;
+        }
+
+        match self.data_manager.open_lock_file() {
+            Ok(file) => match file.try_lock() {
+                Ok(()) => self.lock = Some(file),
+                Err(TryLockError::WouldBlock) => {
+                    log::warn!("Another Vesper instance is running; changes will not be saved");
+                    self.read_only.store(true, Ordering::Relaxed);
+                }
+                Err(TryLockError::Error(e)) => {
+                    log::warn!("Failed to lock data directory: {}", e);
+                }
+            },
+            Err(e) => log::warn!("Failed to open lock file: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{Language, Theme};
+    use crate::ssh::generate_id;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("vesper-test-{}", generate_id()))
+    }
+
+    #[tokio::test]
+    async fn updates_are_batched_until_flushed() {
+        let data_path = temp_dir();
+        let data_manager = DataManager::with_path(data_path.clone());
+        let store = Store::spawn(data_manager.clone());
+
+        store
+            .update(|data| data.settings.theme = Theme::Dark)
+            .unwrap();
+        store
+            .update(|data| data.settings.language = Language::Zh)
+            .unwrap();
+        store.load().await.unwrap();
+        assert_ne!(
+            data_manager.load_data().await.unwrap().settings.theme,
+            Theme::Dark
+        );
+
+        store.flush().await.unwrap();
+
+        let saved = data_manager.load_data().await.unwrap().settings;
+        assert_eq!((saved.theme, saved.language), (Theme::Dark, Language::Zh));
+
+        fs::remove_dir_all(&data_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn second_instance_is_read_only() {
+        let data_path = temp_dir();
+        let first = Store::spawn(DataManager::with_path(data_path.clone()));
+        let second = Store::spawn(DataManager::with_path(data_path.clone()));
+
+        first.load().await.unwrap();
+        second.load().await.unwrap();
+
+        assert!(!first.is_read_only());
+        assert!(second.is_read_only());
+
+        fs::remove_dir_all(&data_path).unwrap();
+    }
+}
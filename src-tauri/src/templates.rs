@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::TcpListener;
+
+use crate::probe::{ProbeCheck, TunnelProbe};
+use crate::ssh::TunnelType;
+
+// How many ports above the preferred one are tried before letting the OS choose
+const FREE_PORT_SEARCH_RANGE: u16 = 100;
+const BUILTIN_ID_PREFIX: &str = "builtin-";
+const LOCAL_PORT_PLACEHOLDER: &str = "{local_port}";
+
+// Defaults for a new tunnel to a common service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TunnelTemplate {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub tunnel_type: TunnelType,
+    #[serde(default = "default_remote_host")]
+    pub remote_host: String,
+    pub remote_port: u16,
+    // Preferred local port; a free one close to it is used when it's taken
+    pub local_port: u16,
+    #[serde(default)]
+    pub auto_reconnect: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<TunnelProbe>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_url: Option<String>,
+    // Built-in templates ship with the app and can't be changed
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+fn default_remote_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+pub fn is_builtin_id(id: &str) -> bool {
+    id.starts_with(BUILTIN_ID_PREFIX)
+}
+
+pub fn builtin_templates() -> Vec<TunnelTemplate> {
+    let template = |id: &str, name: &str, description: &str, port: u16| TunnelTemplate {
+        id: format!("{}{}", BUILTIN_ID_PREFIX, id),
+        name: name.to_string(),
+        description: description.to_string(),
+        tunnel_type: TunnelType::Local,
+        remote_host: default_remote_host(),
+        remote_port: port,
+        local_port: port,
+        auto_reconnect: true,
+        probe: Some(TunnelProbe::default()),
+        open_url: None,
+        builtin: true,
+    };
+
+    vec![
+        template("postgresql", "PostgreSQL", "PostgreSQL on port 5432", 5432),
+        TunnelTemplate {
+            probe: Some(TunnelProbe {
+                check: ProbeCheck::Payload {
+                    send: "PING\r\n".to_string(),
+                    expect: Some("PONG".to_string()),
+                },
+                ..TunnelProbe::default()
+            }),
+            ..template("redis", "Redis", "Redis on port 6379", 6379)
+        },
+        template(
+            "kubernetes-api",
+            "Kubernetes API",
+            "Kubernetes API server on port 6443",
+            6443,
+        ),
+        TunnelTemplate {
+            auto_reconnect: false,
+            probe: None,
+            open_url: Some("http://localhost:{local_port}/json/list".to_string()),
+            ..template(
+                "remote-debug",
+                "Remote debug",
+                "Node.js / Chrome DevTools inspector on port 9229",
+                9229,
+            )
+        },
+    ]
+}
+
+// Only web pages are opened, so a saved URL can't launch other handlers
+pub fn validate_open_url(url: &str) -> Result<(), String> {
+    let url = url.trim().to_ascii_lowercase();
+    if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(())
+    } else {
+        Err("Open URL must start with http:// or https://".to_string())
+    }
+}
+
+pub fn render_open_url(url: &str, local_port: u16) -> String {
+    url.trim()
+        .replace(LOCAL_PORT_PLACEHOLDER, &local_port.to_string())
+}
+
+// The preferred port if it's free, else the next free one above it, else any free port
+pub fn pick_free_local_port(preferred: u16, taken: &HashSet<u16>) -> Option<u16> {
+    let candidates = (0..FREE_PORT_SEARCH_RANGE).filter_map(|offset| preferred.checked_add(offset));
+    for port in candidates.filter(|port| *port != 0 && !taken.contains(port)) {
+        if TcpListener::bind(("0.0.0.0", port)).is_ok() {
+            return Some(port);
+        }
+    }
+
+    let port = TcpListener::bind(("0.0.0.0", 0))
+        .and_then(|listener| listener.local_addr())
+        .ok()?
+        .port();
+    (!taken.contains(&port)).then_some(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_templates_have_unique_builtin_ids() {
+        let templates = builtin_templates();
+        let ids: HashSet<&str> = templates
+            .iter()
+            .map(|template| template.id.as_str())
+            .collect();
+
+        assert_eq!(ids.len(), templates.len());
+        assert!(templates
+            .iter()
+            .all(|template| template.builtin && is_builtin_id(&template.id)));
+    }
+
+    #[test]
+    fn open_url_gets_the_local_port() {
+        let url = "http://localhost:{local_port}/json/list";
+        assert!(validate_open_url(url).is_ok());
+        assert!(validate_open_url("file:///etc/passwd").is_err());
+        assert_eq!(
+            render_open_url(url, 19229),
+            "http://localhost:19229/json/list"
+        );
+    }
+
+    #[test]
+    fn busy_and_taken_ports_are_skipped() {
+        let busy = TcpListener::bind(("0.0.0.0", 0)).unwrap();
+        let busy_port = busy.local_addr().unwrap().port();
+        let taken = HashSet::from([busy_port.wrapping_add(1)]);
+
+        let port = pick_free_local_port(busy_port, &taken).unwrap();
+        assert_ne!(port, busy_port);
+        assert!(!taken.contains(&port));
+    }
+}
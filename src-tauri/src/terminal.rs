@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use async_ssh2_lite::{AsyncChannel, AsyncSession, TokioTcpStream};
+
+pub const DEFAULT_TERMINAL_TYPE: &str = "xterm-256color";
+pub const TERMINAL_INPUT_QUEUE_SIZE: usize = 256;
+const TERMINAL_READ_BUFFER_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerminalInfo {
+    pub id: String,
+    pub connection_id: String,
+    pub term: String,
+    pub cols: u32,
+    pub rows: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TerminalEvent {
+    Output {
+        terminal_id: String,
+        data: String,
+    },
+    Exit {
+        terminal_id: String,
+        exit_status: Option<i32>,
+        error: Option<String>,
+    },
+}
+
+pub type TerminalEventSink = Arc<dyn Fn(TerminalEvent) + Send + Sync>;
+
+#[derive(Debug)]
+pub enum TerminalCommand {
+    Input(Vec<u8>),
+    Resize { cols: u32, rows: u32 },
+    Close,
+}
+
+pub struct TerminalHandle {
+    pub info: TerminalInfo,
+    pub command_tx: mpsc::Sender<TerminalCommand>,
+    pub task_handle: JoinHandle<()>,
+}
+
+// Open a PTY shell channel on an existing SSH session
+pub async fn open_shell_channel(
+    session: &AsyncSession<TokioTcpStream>,
+    info: &TerminalInfo,
+) -> Result<AsyncChannel<TokioTcpStream>, String> {
+    let mut channel = session
+        .channel_session()
+        .await
+        .map_err(|e| format!("Failed to open shell channel: {}", e))?;
+
+    channel
+        .request_pty(&info.term, None, Some((info.cols, info.rows, 0, 0)))
+        .await
+        .map_err(|e| format!("Failed to request PTY: {}", e))?;
+
+    channel
+        .shell()
+        .await
+        .map_err(|e| format!("Failed to start shell: {}", e))?;
+
+    Ok(channel)
+}
+
+// Bridge a shell channel to the frontend until either side closes
+pub async fn run_terminal(
+    mut channel: AsyncChannel<TokioTcpStream>,
+    terminal_id: String,
+    mut command_rx: mpsc::Receiver<TerminalCommand>,
+    event_sink: TerminalEventSink,
+) {
+    let mut buf = vec![0u8; TERMINAL_READ_BUFFER_SIZE];
+    let mut decoder = Utf8Decoder::default();
+
+    let error = loop {
+        tokio::select! {
+            read_result = channel.read(&mut buf) => {
+                match read_result {
+                    Ok(0) => break None,
+                    Ok(read) => {
+                        let data = decoder.decode(&buf[..read]);
+                        if !data.is_empty() {
+                            event_sink(TerminalEvent::Output {
+                                terminal_id: terminal_id.clone(),
+                                data,
+                            });
+                        }
+                    }
+                    Err(e) => break Some(format!("Failed to read from shell: {}", e)),
+                }
+            }
+            command = command_rx.recv() => {
+                match command {
+                    Some(TerminalCommand::Input(data)) => {
+                        if let Err(e) = channel.write_all(&data).await {
+                            break Some(format!("Failed to write to shell: {}", e));
+                        }
+                        if let Err(e) = channel.flush().await {
+                            break Some(format!("Failed to flush shell input: {}", e));
+                        }
+                    }
+                    Some(TerminalCommand::Resize { cols, rows }) => {
+                        if let Err(e) = channel.request_pty_size(cols, rows, None, None).await {
+                            log::error!("Failed to resize terminal {}: {}", terminal_id, e);
+                        }
+                    }
+                    Some(TerminalCommand::Close) | None => {
+                        if let Err(e) = channel.send_eof().await {
+                            log::error!("Failed to send EOF to terminal {}: {}", terminal_id, e);
+                        }
+                        break None;
+                    }
+                }
+            }
+        }
+    };
+
+    if let Err(e) = channel.close().await {
+        log::error!("Failed to close terminal channel {}: {}", terminal_id, e);
+    }
+
+    event_sink(TerminalEvent::Exit {
+        terminal_id,
+        exit_status: channel.exit_status().ok(),
+        error,
+    });
+}
+
+// Decodes a byte stream as UTF-8, holding back sequences split across reads
+#[derive(Default)]
+struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+
+        match std::str::from_utf8(&self.pending) {
+            Ok(text) => {
+                let text = text.to_string();
+                self.pending.clear();
+                text
+            }
+            Err(e) if e.error_len().is_none() => {
+                let valid = e.valid_up_to();
+                let text = String::from_utf8_lossy(&self.pending[..valid]).into_owned();
+                self.pending.drain(..valid);
+                text
+            }
+            Err(_) => {
+                let text = String::from_utf8_lossy(&self.pending).into_owned();
+                self.pending.clear();
+                text
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoder_holds_back_split_multibyte_sequences() {
+        let mut decoder = Utf8Decoder::default();
+        let bytes = "héllo".as_bytes();
+
+        assert_eq!(decoder.decode(&bytes[..2]), "h");
+        assert_eq!(decoder.decode(&bytes[2..]), "éllo");
+    }
+
+    #[test]
+    fn decoder_replaces_invalid_bytes() {
+        let mut decoder = Utf8Decoder::default();
+
+        assert_eq!(decoder.decode(&[b'a', 0xff, b'b']), "a\u{fffd}b");
+    }
+}
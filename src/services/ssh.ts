@@ -1,13 +1,59 @@
 import { invoke } from '@tauri-apps/api/core';
-import type { SSHConnection, SSHTunnel } from '../types';
+import type {
+  BandwidthLimit,
+  ClientLimits,
+  MetricSample,
+  OnDemand,
+  PortMapping,
+  ProbeResult,
+  SSHConnection,
+  SSHTunnel,
+  ScheduleStatus,
+  TunnelProbe,
+  TunnelSchedule,
+  TunnelStats,
+  TunnelTemplate,
+} from '../types';
 
 // API Response Types
 export interface ConnectionResult {
   success: boolean;
   message: string;
+  // Set when connecting worked but some tunnels didn't start; failures are thrown as BackendError
   error_code?: string;
 }
 
+// Shape of every error returned by a backend command
+export interface BackendErrorPayload {
+  code: string;
+  message: string;
+  causes: string[];
+}
+
+export class BackendError extends Error {
+  code: string;
+  causes: string[];
+
+  constructor(payload: BackendErrorPayload) {
+    super(payload.message);
+    this.name = 'BackendError';
+    this.code = payload.code;
+    this.causes = payload.causes;
+  }
+}
+
+// Invoke a command, rethrowing structured backend errors as BackendError
+async function call<T>(command: string, args?: Record<string, unknown>): Promise<T> {
+  try {
+    return await invoke<T>(command, args);
+  } catch (error) {
+    if (error && typeof error === 'object' && 'code' in error && 'message' in error) {
+      throw new BackendError(error as BackendErrorPayload);
+    }
+    throw error;
+  }
+}
+
 export interface CreateConnectionRequest {
   name: string;
   host: string;
@@ -16,6 +62,8 @@ export interface CreateConnectionRequest {
   auth_method: 'password' | 'key';
   password?: string;
   key_path?: string;
+  session_pool_size?: number;
+  bandwidth?: BandwidthLimit;
 }
 
 export interface UpdateConnectionRequest {
@@ -27,6 +75,16 @@ export interface UpdateConnectionRequest {
   auth_method: 'password' | 'key';
   password?: string;
   key_path?: string;
+  session_pool_size?: number;
+  bandwidth?: BandwidthLimit;
+}
+
+// Changes applied to a duplicated connection
+export interface DuplicateOverrides {
+  name?: string;
+  host?: string;
+  // Added to the ports local tunnels listen on
+  local_port_offset?: number;
 }
 
 export interface CreateTunnelRequest {
@@ -37,6 +95,15 @@ export interface CreateTunnelRequest {
   remote_host: string;
   remote_port: number;
   auto_reconnect: boolean;
+  probe?: TunnelProbe;
+  bandwidth?: BandwidthLimit;
+  allowed_clients?: string[];
+  client_limits?: ClientLimits;
+  on_demand?: OnDemand;
+  port_count?: number;
+  extra_ports?: PortMapping[];
+  open_url?: string;
+  schedule?: TunnelSchedule;
 }
 
 export interface UpdateTunnelRequest {
@@ -48,95 +115,153 @@ export interface UpdateTunnelRequest {
   remote_host: string;
   remote_port: number;
   auto_reconnect: boolean;
+  probe?: TunnelProbe;
+  bandwidth?: BandwidthLimit;
+  allowed_clients?: string[];
+  client_limits?: ClientLimits;
+  on_demand?: OnDemand;
+  port_count?: number;
+  extra_ports?: PortMapping[];
+  open_url?: string;
+  schedule?: TunnelSchedule;
+}
+
+export interface CreateTunnelFromTemplateRequest {
+  template_id: string;
+  connection_id: string;
+  name?: string;
 }
 
 // SSH Connection API
 export const sshApi = {
   // Storage initialization
   async initializeStorage(): Promise<void> {
-    return await invoke('initialize_storage');
+    return await call('initialize_storage');
   },
 
   // Connection CRUD operations
   async createConnection(connection: CreateConnectionRequest): Promise<string> {
-    return await invoke('create_connection', { request: connection });
+    return await call('create_connection', { request: connection });
   },
 
   async getConnections(): Promise<SSHConnection[]> {
-    return await invoke('get_connections');
+    return await call('get_connections');
   },
 
   async getConnection(id: string): Promise<SSHConnection | null> {
-    return await invoke('get_connection', { id });
+    return await call('get_connection', { id });
   },
 
   async updateConnection(connection: UpdateConnectionRequest): Promise<void> {
-    return await invoke('update_connection', { request: connection });
+    return await call('update_connection', { request: connection });
   },
 
   async deleteConnection(id: string): Promise<void> {
-    return await invoke('delete_connection', { id });
+    return await call('delete_connection', { id });
+  },
+
+  // Copies the connection and its tunnels; port conflicts reject with code CONFLICT
+  async duplicateConnection(id: string, overrides?: DuplicateOverrides): Promise<string> {
+    return await call('duplicate_connection', { id, overrides });
   },
 
   // Connection operations
   async testConnection(id: string): Promise<ConnectionResult> {
-    return await invoke('test_connection', { id });
+    return await call('test_connection', { id });
   },
 
   async testConnectionData(connectionData: CreateConnectionRequest): Promise<ConnectionResult> {
-    return await invoke('test_connection_data', { request: connectionData });
+    return await call('test_connection_data', { request: connectionData });
   },
 
   async connectSSH(id: string): Promise<ConnectionResult> {
-    return await invoke('connect_ssh', { id });
+    return await call('connect_ssh', { id });
   },
 
   async disconnectSSH(id: string): Promise<ConnectionResult> {
-    return await invoke('disconnect_ssh', { id });
+    return await call('disconnect_ssh', { id });
+  },
+
+  async getConnectionMetrics(connectionId: string): Promise<MetricSample[]> {
+    return await call('get_connection_metrics', { connection_id: connectionId });
   },
 
   // Tunnel CRUD operations
   async createTunnel(tunnel: CreateTunnelRequest): Promise<string> {
-    return await invoke('create_tunnel', { request: tunnel });
+    return await call('create_tunnel', { request: tunnel });
   },
 
   async updateTunnel(tunnel: UpdateTunnelRequest): Promise<void> {
-    return await invoke('update_tunnel', { request: tunnel });
+    return await call('update_tunnel', { request: tunnel });
   },
 
   async getTunnels(): Promise<SSHTunnel[]> {
-    return await invoke('get_tunnels');
+    return await call('get_tunnels');
   },
 
   async getTunnelsByConnection(connectionId: string): Promise<SSHTunnel[]> {
-    return await invoke('get_tunnels_by_connection', { connection_id: connectionId });
+    return await call('get_tunnels_by_connection', { connection_id: connectionId });
   },
 
   async deleteTunnel(id: string): Promise<void> {
     console.log('Calling delete_tunnel with ID:', id, '(length:', id.length, ')');
     // Ensure we're passing the ID as a string directly
-    return await invoke('delete_tunnel', { id: String(id) });
+    return await call('delete_tunnel', { id: String(id) });
   },
 
   async stopTunnel(id: string): Promise<void> {
-    return await invoke('stop_tunnel', { id: String(id) });
+    return await call('stop_tunnel', { id: String(id) });
+  },
+
+  async getTunnelStats(id: string): Promise<TunnelStats> {
+    return await call('get_tunnel_stats', { id: String(id) });
+  },
+
+  async probeTunnel(id: string): Promise<ProbeResult> {
+    return await call('probe_tunnel', { id: String(id) });
   },
 
   async startTunnel(id: string): Promise<ConnectionResult> {
-    return await invoke('start_tunnel', { id: String(id) });
+    return await call('start_tunnel', { id: String(id) });
+  },
+
+  // open_url with the tunnel's local port filled in
+  async getTunnelOpenUrl(id: string): Promise<string | null> {
+    return await call('get_tunnel_open_url', { id: String(id) });
+  },
+
+  async getTunnelScheduleStatus(id: string): Promise<ScheduleStatus> {
+    return await call('get_tunnel_schedule_status', { id: String(id) });
+  },
+
+  // Tunnel templates
+  async getTunnelTemplates(): Promise<TunnelTemplate[]> {
+    return await call('get_tunnel_templates');
+  },
+
+  async saveTunnelTemplate(template: TunnelTemplate): Promise<string> {
+    return await call('save_tunnel_template', { template });
+  },
+
+  async deleteTunnelTemplate(id: string): Promise<void> {
+    return await call('delete_tunnel_template', { id });
+  },
+
+  async createTunnelFromTemplate(request: CreateTunnelFromTemplateRequest): Promise<string> {
+    return await call('create_tunnel_from_template', { request });
   },
 
   
   // Settings operations
   async getSettings(): Promise<any> {
-    return await invoke('get_settings');
+    return await call('get_settings');
   },
 
   async updateSettings(settings: any): Promise<void> {
-    return await invoke('update_settings', { settings });
+    return await call('update_settings', { settings });
   },
 
   async resetSettings(): Promise<any> {
-    return await invoke('reset_settings');
+    return await call('reset_settings');
   }
 };
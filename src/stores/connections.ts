@@ -1,6 +1,6 @@
 import { acceptHMRUpdate, defineStore } from 'pinia';
 import { ref, computed } from 'vue';
-import { sshApi } from '../services/ssh';
+import { sshApi, type DuplicateOverrides } from '../services/ssh';
 import type { SSHConnection, SSHTunnel } from '../types';
 
 export const useConnectionsStore = defineStore('connections', () => {
@@ -17,7 +17,7 @@ export const useConnectionsStore = defineStore('connections', () => {
   );
 
   const activeTunnels = computed(() =>
-    tunnels.value.filter(tunnel => tunnel.status === 'active')
+    tunnels.value.filter(tunnel => ['active', 'degraded', 'standby'].includes(tunnel.status))
   );
 
   const getConnectionById = (id: string) =>
@@ -143,6 +143,21 @@ export const useConnectionsStore = defineStore('connections', () => {
     }
   };
 
+  const duplicateConnection = async (id: string, overrides?: DuplicateOverrides) => {
+    try {
+      error.value = null;
+      const newId = await sshApi.duplicateConnection(id, overrides);
+
+      await fetchConnections();
+      await fetchTunnels();
+      return newId;
+    } catch (err) {
+      error.value = err as string;
+      console.error('Failed to duplicate connection:', err);
+      throw err;
+    }
+  };
+
   const removeConnection = async (id: string) => {
     try {
       error.value = null;
@@ -419,6 +434,7 @@ export const useConnectionsStore = defineStore('connections', () => {
     addConnection,
     updateConnection,
     removeConnection,
+    duplicateConnection,
     testConnection,
     connectSSH,
     disconnectSSH,
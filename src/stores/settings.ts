@@ -10,6 +10,11 @@ export interface AppSettings {
   default_key_path?: string;
   window_width: number;
   window_height: number;
+  window_x?: number;
+  window_y?: number;
+  window_maximized?: boolean;
+  metrics_interval_secs?: number;
+  server_metrics?: boolean;
 }
 
 export const useSettingsStore = defineStore('settings', () => {
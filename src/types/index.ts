@@ -9,6 +9,14 @@ export interface SSHConnection {
   key_path?: string;
   status: 'disconnected' | 'connecting' | 'connected' | 'error';
   last_connected?: string | Date;
+  session_pool_size?: number;
+  bandwidth?: BandwidthLimit;
+}
+
+// Bytes per second; omitted means unlimited
+export interface BandwidthLimit {
+  upload_bytes_per_sec?: number;
+  download_bytes_per_sec?: number;
 }
 
 export interface SSHTunnel {
@@ -19,8 +27,131 @@ export interface SSHTunnel {
   local_port: number;
   remote_host: string;
   remote_port: number;
-  status: 'inactive' | 'active' | 'error';
+  status: 'inactive' | 'active' | 'degraded' | 'standby' | 'error';
+  auto_reconnect: boolean;
+  probe?: TunnelProbe;
+  bandwidth?: BandwidthLimit;
+  allowed_clients?: string[];
+  client_limits?: ClientLimits;
+  on_demand?: OnDemand;
+  // Consecutive ports forwarded from local_port -> remote_port; omitted means one
+  port_count?: number;
+  extra_ports?: PortMapping[];
+  // Opened once the tunnel is up; {local_port} is replaced with the tunnel's local port
+  open_url?: string;
+  // Started and stopped automatically during its windows
+  schedule?: TunnelSchedule;
+  port_errors?: PortError[];
+  last_probe?: ProbeResult;
+}
+
+export interface PortMapping {
+  local_port: number;
+  remote_port: number;
+}
+
+// A mapping of a multi-port tunnel that failed to start
+export interface PortError {
+  local_port: number;
+  remote_port: number;
+  error: string;
+}
+
+// Local tunnels that connect for their first client and disconnect when idle
+export interface OnDemand {
+  idle_timeout_secs?: number;
+}
+
+// Limits on the clients a tunnel forwards; omitted values mean unlimited, except the queue bounds
+export interface ClientLimits {
+  max_connections?: number;
+  when_full?: 'queue' | 'reject';
+  max_queued?: number;
+  queue_timeout_secs?: number;
+  idle_timeout_secs?: number;
+  max_lifetime_secs?: number;
+}
+
+export type ProbeCheck =
+  | { type: 'connect' }
+  | { type: 'http'; path?: string; expected_status?: number }
+  | { type: 'payload'; send?: string; expect?: string };
+
+export interface TunnelProbe {
+  interval_secs?: number;
+  timeout_secs?: number;
+  check?: ProbeCheck;
+}
+
+export interface ProbeResult {
+  reachable: boolean;
+  latency_ms: number;
+  checked_at: number;
+  error?: string;
+}
+
+export type Weekday = 'Mon' | 'Tue' | 'Wed' | 'Thu' | 'Fri' | 'Sat' | 'Sun';
+
+export interface WeeklyWindow {
+  days: Weekday[];
+  // "HH:MM" local time; an end at or before the start runs past midnight
+  start: string;
+  end: string;
+}
+
+// timezone is an IANA name such as "Europe/Berlin"
+export type TunnelSchedule =
+  | { kind: 'weekly'; timezone?: string; windows: WeeklyWindow[] }
+  | { kind: 'cron'; timezone?: string; expression: string; duration_minutes: number };
+
+export interface ScheduleStatus {
+  in_window: boolean;
+  next_transition_at?: number;
+  // A manual start or stop holds until the next transition
+  overridden: boolean;
+}
+
+// Defaults for a new tunnel to a common service
+export interface TunnelTemplate {
+  id: string;
+  name: string;
+  description: string;
+  tunnel_type: 'local' | 'remote';
+  remote_host: string;
+  remote_port: number;
+  // Preferred local port; a free one close to it is used when it's taken
+  local_port: number;
   auto_reconnect: boolean;
+  probe?: TunnelProbe;
+  open_url?: string;
+  builtin: boolean;
+}
+
+export interface TunnelStats {
+  rejected_clients: number;
+  active_connections: number;
+  queued_connections: number;
+  total_connections: number;
+  rejected_over_limit: number;
+  idle_timeouts: number;
+  lifetime_exceeded: number;
+}
+
+export interface ServerResources {
+  load_1: number;
+  load_5: number;
+  load_15: number;
+  memory_total: number;
+  memory_used: number;
+  disk_total: number;
+  disk_used: number;
+}
+
+export interface MetricSample {
+  timestamp: number;
+  rtt_ms?: number;
+  resources?: ServerResources;
+  error?: string;
 }
 
 export interface AppConfig {